@@ -0,0 +1,316 @@
+use core::fmt;
+
+use crate::allocators::combinators::{FallbackError, LimitError};
+#[cfg(target_os = "linux")]
+use crate::allocators::reserving::ReservingError;
+use crate::allocators::static_heap::StaticHeapOverflowError;
+#[cfg(any(feature = "alloc", test))]
+use crate::allocators::toy_heap::ToyHeapOverflowError;
+
+/// The system's numbers for the codes `Errno` names. They differ between
+/// systems: `EAGAIN` is 11 on Linux, but 35 on macOS and the BSDs.
+#[cfg(feature = "use_libc")]
+mod codes {
+    pub(super) use libc::{EAGAIN, EINVAL, ENOMEM, ENOSYS, EPERM};
+}
+
+#[cfg(all(
+    not(feature = "use_libc"),
+    any(
+        target_os = "macos",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "netbsd",
+        target_os = "dragonfly"
+    )
+))]
+mod codes {
+    pub(super) const EPERM: i32 = 1;
+    pub(super) const ENOMEM: i32 = 12;
+    pub(super) const EINVAL: i32 = 22;
+    pub(super) const EAGAIN: i32 = 35;
+    pub(super) const ENOSYS: i32 = 78;
+}
+
+#[cfg(all(
+    not(feature = "use_libc"),
+    not(any(
+        target_os = "macos",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "netbsd",
+        target_os = "dragonfly"
+    ))
+))]
+mod codes {
+    pub(super) const EPERM: i32 = 1;
+    pub(super) const EAGAIN: i32 = 11;
+    pub(super) const ENOMEM: i32 = 12;
+    pub(super) const EINVAL: i32 = 22;
+    pub(super) const ENOSYS: i32 = 38;
+}
+
+/// The error codes an allocator is likely to see, by name.
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Errno {
+    /// Out of memory, or out of address space.
+    ENOMEM,
+    /// A bad argument, e.g. a misaligned address or an unknown flag.
+    EINVAL,
+    /// A resource is temporarily short, e.g. locked memory.
+    EAGAIN,
+    /// Not allowed, e.g. by a seccomp filter or a sealed mapping.
+    EPERM,
+    /// The system call does not exist, e.g. on a kernel built without it.
+    ENOSYS,
+    Other(i32),
+}
+
+impl Errno {
+    /// Names `code`, as numbered on the system this is built for.
+    pub const fn from_code(code: i32) -> Self {
+        match code {
+            codes::EPERM => Errno::EPERM,
+            codes::EAGAIN => Errno::EAGAIN,
+            codes::ENOMEM => Errno::ENOMEM,
+            codes::EINVAL => Errno::EINVAL,
+            codes::ENOSYS => Errno::ENOSYS,
+            code => Errno::Other(code),
+        }
+    }
+
+    pub const fn code(&self) -> i32 {
+        match *self {
+            Errno::EPERM => codes::EPERM,
+            Errno::EAGAIN => codes::EAGAIN,
+            Errno::ENOMEM => codes::ENOMEM,
+            Errno::EINVAL => codes::EINVAL,
+            Errno::ENOSYS => codes::ENOSYS,
+            Errno::Other(code) => code,
+        }
+    }
+}
+
+impl fmt::Display for Errno {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Errno::Other(code) => write!(f, "errno {}", code),
+            named => fmt::Debug::fmt(named, f),
+        }
+    }
+}
+
+/// The system call that failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Mmap,
+    Munmap,
+    Mremap,
+    Mprotect,
+    Madvise,
+    Mbind,
+    SetMempolicy,
+    GetMempolicy,
+    Lseek,
+    Ftruncate,
+    Msync,
+    Brk,
+    /// None in particular, e.g. because the request was refused before any
+    /// system call was made.
+    Unknown,
+}
+
+impl Operation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Operation::Mmap => "mmap",
+            Operation::Munmap => "munmap",
+            Operation::Mremap => "mremap",
+            Operation::Mprotect => "mprotect",
+            Operation::Madvise => "madvise",
+            Operation::Mbind => "mbind",
+            Operation::SetMempolicy => "set_mempolicy",
+            Operation::GetMempolicy => "get_mempolicy",
+            Operation::Lseek => "lseek",
+            Operation::Ftruncate => "ftruncate",
+            Operation::Msync => "msync",
+            Operation::Brk => "brk",
+            Operation::Unknown => "system call",
+        }
+    }
+}
+
+/// Why an allocation failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocError {
+    /// The allocation would have gone past a limit: the heap's `Budget`, or a
+    /// `Limit` grower. Memory may well be available.
+    LimitExceeded {
+        requested: usize,
+        used: usize,
+        limit: usize,
+    },
+    /// A grower over a fixed amount of memory has handed out all of it.
+    Exhausted,
+    /// The system refused more memory.
+    System { op: Operation, errno: Errno },
+}
+
+impl AllocError {
+    /// Whether the system or the grower is out of memory, rather than a
+    /// limit having been hit.
+    pub fn is_out_of_memory(&self) -> bool {
+        match self {
+            AllocError::LimitExceeded { .. } => false,
+            AllocError::Exhausted => true,
+            AllocError::System { errno, .. } => *errno == Errno::ENOMEM,
+        }
+    }
+}
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AllocError::LimitExceeded {
+                requested,
+                used,
+                limit,
+            } => write!(
+                f,
+                "{} more bytes would exceed the limit of {} ({} in use)",
+                requested, limit, used
+            ),
+            AllocError::Exhausted => f.write_str("heap memory exhausted"),
+            AllocError::System { op, errno } => write!(f, "{} failed: {}", op.name(), errno),
+        }
+    }
+}
+
+impl From<StaticHeapOverflowError> for AllocError {
+    fn from(_: StaticHeapOverflowError) -> Self {
+        AllocError::Exhausted
+    }
+}
+
+#[cfg(any(feature = "alloc", test))]
+impl From<ToyHeapOverflowError> for AllocError {
+    fn from(_: ToyHeapOverflowError) -> Self {
+        AllocError::Exhausted
+    }
+}
+
+impl<E: Into<AllocError>> From<LimitError<E>> for AllocError {
+    fn from(error: LimitError<E>) -> Self {
+        match error {
+            LimitError::Exceeded {
+                requested,
+                used,
+                limit,
+            } => AllocError::LimitExceeded {
+                requested,
+                used,
+                limit,
+            },
+            LimitError::Inner(error) => error.into(),
+        }
+    }
+}
+
+/// A reservation that could not be recorded failed for want of memory for
+/// the record.
+#[cfg(target_os = "linux")]
+impl From<ReservingError> for AllocError {
+    fn from(error: ReservingError) -> Self {
+        match error {
+            ReservingError::Backend(error) => error,
+            ReservingError::Untracked => AllocError::System {
+                op: Operation::Mmap,
+                errno: Errno::ENOMEM,
+            },
+        }
+    }
+}
+
+/// The second grower's error, as the last thing that went wrong. A growth
+/// that could not be recorded failed for want of memory for the record.
+impl<EA, EB: Into<AllocError>> From<FallbackError<EA, EB>> for AllocError {
+    fn from(error: FallbackError<EA, EB>) -> Self {
+        match error.second {
+            Some(error) => error.into(),
+            None => AllocError::System {
+                op: Operation::Mmap,
+                errno: Errno::ENOMEM,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocators::{Limit, MemoryBackend, RawAlloc, RawBackend, ToyHeap};
+    use core::alloc::Layout;
+    use core::fmt::Write;
+    use crate::blocklist::BLOCK_ALIGN;
+    use test_log::test;
+
+    #[test]
+    fn test_try_alloc() {
+        let mut allocator = RawAlloc::new(Limit::new(ToyHeap::default(), 1024));
+        allocator.budget.set_hard_limit(Some(512));
+        let layout = Layout::from_size_align(400, BLOCK_ALIGN).unwrap();
+        let large = Layout::from_size_align(1000, BLOCK_ALIGN).unwrap();
+        let huge = Layout::from_size_align(1 << 20, BLOCK_ALIGN).unwrap();
+        unsafe {
+            let a = allocator.try_alloc(layout).unwrap();
+            let error = allocator.try_alloc(layout).unwrap_err();
+            assert_eq!(
+                error,
+                AllocError::LimitExceeded {
+                    requested: 400,
+                    used: 400,
+                    limit: 512
+                }
+            );
+            assert!(!error.is_out_of_memory());
+
+            allocator.budget.set_hard_limit(None);
+            let error = allocator.try_alloc(large).unwrap_err();
+            assert!(matches!(error, AllocError::LimitExceeded { limit: 1024, .. }));
+
+            allocator.grower.set_limit(usize::MAX);
+            assert_eq!(allocator.try_alloc(huge), Err(AllocError::Exhausted));
+            assert!(allocator.try_realloc(a.as_ptr(), layout, 1 << 20).unwrap_err().is_out_of_memory());
+            allocator.dealloc(a.as_ptr(), layout);
+        }
+    }
+
+    #[test]
+    fn test_decode() {
+        let error = AllocError::System {
+            op: Operation::Mmap,
+            errno: Errno::from_code(Errno::ENOMEM.code()),
+        };
+        assert!(error.is_out_of_memory());
+
+        let mut buf = [0; 64];
+        let mut text = crate::metrics::FixedBuf::new(&mut buf);
+        write!(text, "{}", error).unwrap();
+        assert_eq!(text.as_str(), "mmap failed: ENOMEM");
+        for errno in [Errno::EPERM, Errno::EAGAIN, Errno::EINVAL, Errno::ENOSYS] {
+            assert_eq!(Errno::from_code(errno.code()), errno);
+        }
+        assert_eq!(Errno::from_code(-1), Errno::Other(-1));
+
+        // A real failure: an address that is not page-aligned.
+        let error = unsafe { RawBackend.unmap(1 as *mut u8, 4096) }.unwrap_err();
+        assert_eq!(
+            error,
+            AllocError::System {
+                op: Operation::Munmap,
+                errno: Errno::EINVAL
+            }
+        );
+    }
+}
\ No newline at end of file
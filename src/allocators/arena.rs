@@ -0,0 +1,334 @@
+//! The arenas a `UnixAllocator` spreads its threads over: heaps of their own,
+//! each behind its own lock.
+
+use core::mem::size_of;
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicPtr, AtomicU32, AtomicU64, Ordering};
+
+use crate::allocators::backend::{DefaultBackend, MemoryBackend, Protection};
+use crate::allocators::generic_allocator::GenericAllocator;
+use crate::allocators::heap_grower::EnhancedHeapGrower;
+
+#[cfg(all(target_os = "linux", not(feature = "use_libc")))]
+use crate::mmap;
+
+/// The most arenas an allocator has. CPUs past these share.
+pub const MAX_ARENAS: usize = 64;
+
+/// Arenas per CPU, when threads are spread over arenas and no count is
+/// given, as jemalloc does.
+pub const ARENAS_PER_CPU: usize = 4;
+
+// Threads whose arena can be remembered at once. Those past it are given one
+// by their id.
+const THREAD_SLOTS: usize = 256;
+
+// How far past its place a thread's id is looked for.
+const MAX_PROBES: usize = 8;
+
+pub(crate) type Arena = GenericAllocator<EnhancedHeapGrower>;
+
+/// The CPU the calling thread is running on, as `getcpu` reports it, or
+/// `None` where that cannot be found. The thread may have moved on by the
+/// time it returns.
+#[inline(always)]
+pub fn current_cpu() -> Option<usize> {
+    cpu()
+}
+
+#[cfg(all(target_os = "linux", not(feature = "use_libc")))]
+#[inline(always)]
+fn cpu() -> Option<usize> {
+    let mut cpu = 0u32;
+    unsafe { mmap::getcpu(&mut cpu) }.ok().map(|()| cpu as usize)
+}
+
+#[cfg(all(target_os = "linux", feature = "use_libc"))]
+#[inline(always)]
+fn cpu() -> Option<usize> {
+    use core::convert::TryFrom;
+    usize::try_from(unsafe { libc::sched_getcpu() }).ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+#[inline(always)]
+fn cpu() -> Option<usize> {
+    None
+}
+
+/// The CPUs the calling thread may run on, at least one.
+pub fn cpu_count() -> usize {
+    cpus().max(1)
+}
+
+#[cfg(all(target_os = "linux", not(feature = "use_libc")))]
+fn cpus() -> usize {
+    // Room for 1024 CPUs.
+    let mut mask = [0u64; 16];
+    match unsafe { mmap::sched_getaffinity(mask.as_mut_ptr(), size_of::<[u64; 16]>()) } {
+        Ok(_) => mask.iter().map(|word| word.count_ones() as usize).sum(),
+        Err(_) => 1,
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "use_libc"))]
+fn cpus() -> usize {
+    unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) }.max(1) as usize
+}
+
+#[cfg(not(target_os = "linux"))]
+fn cpus() -> usize {
+    1
+}
+
+/// The calling thread's id, never 0, or `None` where that cannot be found.
+#[inline(always)]
+pub(crate) fn thread_id() -> Option<u32> {
+    #[cfg(all(target_os = "linux", not(feature = "use_libc")))]
+    let tid = unsafe { mmap::gettid() };
+
+    #[cfg(all(target_os = "linux", feature = "use_libc"))]
+    let tid = unsafe { libc::syscall(libc::SYS_gettid) } as u32;
+
+    #[cfg(not(target_os = "linux"))]
+    let tid = 0;
+
+    (tid != 0).then_some(tid)
+}
+
+// Process-wide, for each thread slot: the id of the thread holding it, or 0,
+// and how many times it has been given up.
+static HOLDERS: [AtomicU32; THREAD_SLOTS] = [const { AtomicU32::new(0) }; THREAD_SLOTS];
+static GENERATIONS: [AtomicU32; THREAD_SLOTS] = [const { AtomicU32::new(0) }; THREAD_SLOTS];
+
+/// A thread's place in every allocator's `Threads`, for as long as it holds
+/// it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Slot {
+    index: usize,
+    generation: u32,
+}
+
+impl Slot {
+    fn at(index: usize) -> Self {
+        Slot {
+            index,
+            generation: GENERATIONS[index].load(Ordering::Acquire),
+        }
+    }
+}
+
+/// The calling thread's slot. If it has none, one near its id is taken if
+/// `claim`; `None` if it may not, or all of those are taken.
+///
+/// With the `std` feature, the slot is given up when the thread exits.
+/// Without it, slots are kept for good, and a thread that comes to have the
+/// id of one that has exited takes over its slot.
+#[inline(always)]
+pub(crate) fn thread_slot(claim: bool) -> Option<Slot> {
+    #[cfg(feature = "std")]
+    if let Some(index) = tls::get() {
+        return Some(Slot::at(index));
+    }
+    let tid = thread_id()?;
+    let slots = || (0..MAX_PROBES).map(|probe| (tid as usize).wrapping_add(probe) % THREAD_SLOTS);
+    // Held already: from before, or, with `std`, by a call that was taking
+    // it and allocated meanwhile.
+    if let Some(index) = slots().find(|&index| HOLDERS[index].load(Ordering::Acquire) == tid) {
+        return Some(Slot::at(index));
+    }
+    if !claim {
+        return None;
+    }
+    let index = slots().find(|&index| {
+        HOLDERS[index]
+            .compare_exchange(0, tid, Ordering::AcqRel, Ordering::Relaxed)
+            .is_ok()
+    })?;
+    #[cfg(feature = "std")]
+    tls::set(index);
+    Some(Slot::at(index))
+}
+
+/// Gives up slot `index` for its exited thread. What every allocator
+/// remembered for it is forgotten with it.
+#[cfg(feature = "std")]
+fn release(index: usize) {
+    GENERATIONS[index].fetch_add(1, Ordering::Release);
+    HOLDERS[index].store(0, Ordering::Release);
+}
+
+/// The calling thread's slot, kept under a pthread key whose destructor
+/// gives it up.
+#[cfg(feature = "std")]
+mod tls {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    // The key plus one, or 0 until it is made.
+    static KEY: AtomicUsize = AtomicUsize::new(0);
+
+    unsafe extern "C" fn destroy(value: *mut libc::c_void) {
+        super::release(value as usize - 1);
+    }
+
+    fn key() -> Option<libc::pthread_key_t> {
+        if let Some(key) = KEY.load(Ordering::Acquire).checked_sub(1) {
+            return Some(key as libc::pthread_key_t);
+        }
+        let mut key = 0;
+        if unsafe { libc::pthread_key_create(&mut key, Some(destroy)) } != 0 {
+            return None;
+        }
+        match KEY.compare_exchange(0, key as usize + 1, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => Some(key),
+            Err(made) => {
+                unsafe { libc::pthread_key_delete(key) };
+                Some((made - 1) as libc::pthread_key_t)
+            }
+        }
+    }
+
+    #[inline(always)]
+    pub(super) fn get() -> Option<usize> {
+        let key = KEY.load(Ordering::Acquire).checked_sub(1)?;
+        let value = unsafe { libc::pthread_getspecific(key as libc::pthread_key_t) };
+        (value as usize).checked_sub(1)
+    }
+
+    /// Remembers slot `index` for the calling thread. If that fails, the
+    /// slot is never given up.
+    pub(super) fn set(index: usize) {
+        if let Some(key) = key() {
+            unsafe { libc::pthread_setspecific(key, (index + 1) as *const libc::c_void) };
+        }
+    }
+}
+
+/// Which arena each thread has, by its slot.
+///
+/// Only a thread itself looks up or changes its own entry. An entry only
+/// counts for the holder of the slot it was made for, so a thread taking a
+/// slot given up by another starts with no arena.
+pub(crate) struct Threads {
+    // The slot's generation in the top half, and the arena plus one in the
+    // bottom.
+    arenas: [AtomicU64; THREAD_SLOTS],
+}
+
+impl Default for Threads {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Threads {
+    pub(crate) const fn new() -> Self {
+        Threads {
+            arenas: [const { AtomicU64::new(0) }; THREAD_SLOTS],
+        }
+    }
+
+    /// The arena of the thread in `slot`, if it has one.
+    #[inline(always)]
+    pub(crate) fn get(&self, slot: Slot) -> Option<usize> {
+        let entry = self.arenas[slot.index].load(Ordering::Relaxed);
+        if entry >> 32 != slot.generation as u64 {
+            return None;
+        }
+        (entry as u32 as usize).checked_sub(1)
+    }
+
+    /// Gives the thread in `slot` arena `index`.
+    pub(crate) fn set(&self, slot: Slot, index: usize) {
+        let entry = (slot.generation as u64) << 32 | (index as u64 + 1);
+        self.arenas[slot.index].store(entry, Ordering::Relaxed);
+    }
+}
+
+/// Arenas `1..MAX_ARENAS`, each made on first use in memory mapped for it.
+/// They are never unmapped, as memory they handed out may still be in use.
+pub(crate) struct Arenas {
+    slots: [AtomicPtr<Arena>; MAX_ARENAS - 1],
+}
+
+impl Default for Arenas {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Arenas {
+    pub(crate) const fn new() -> Self {
+        Arenas {
+            slots: [const { AtomicPtr::new(null_mut()) }; MAX_ARENAS - 1],
+        }
+    }
+
+    /// Arena `index`, if it has been made.
+    #[inline(always)]
+    pub(crate) fn get(&self, index: usize) -> Option<&Arena> {
+        unsafe { self.slots[index - 1].load(Ordering::Acquire).as_ref() }
+    }
+
+    /// Arena `index`, made and passed to `setup` first if need be, or `None`
+    /// if there is no memory for it.
+    pub(crate) fn get_or_make(&self, index: usize, setup: impl FnOnce(&mut Arena)) -> Option<&Arena> {
+        if let Some(arena) = self.get(index) {
+            return Some(arena);
+        }
+        let mut backend = DefaultBackend {};
+        let len = size_of::<Arena>().next_multiple_of(backend.page_size());
+        unsafe {
+            let ptr = backend.map(len, Protection::ReadWrite).ok()? as *mut Arena;
+            ptr.write(Arena::new());
+            setup(&mut *ptr);
+            match self.slots[index - 1].compare_exchange(null_mut(), ptr, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => Some(&*ptr),
+                Err(made) => {
+                    // Another thread got there first. Ours has mapped nothing
+                    // yet.
+                    let _ = backend.unmap(ptr as *mut u8, len);
+                    Some(&*made)
+                }
+            }
+        }
+    }
+
+    /// The arenas made so far, with their indices.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (usize, &Arena)> {
+        (1..MAX_ARENAS).filter_map(move |index| Some((index, self.get(index)?)))
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use test_log::test;
+
+    #[test]
+    fn test_current_cpu() {
+        let cpu = current_cpu().expect("getcpu should work on Linux");
+        // A generous bound: no machine here has this many CPUs.
+        assert!(cpu < 1 << 16);
+        assert!(cpu_count() >= 1);
+    }
+
+    #[test]
+    fn test_threads() {
+        let threads = Threads::new();
+        let slot = thread_slot(true).expect("gettid should work on Linux");
+        assert_eq!(thread_slot(false), Some(slot));
+        assert_eq!(threads.get(slot), None);
+        threads.set(slot, 5);
+        assert_eq!(threads.get(slot), Some(5));
+        threads.set(slot, 7);
+        assert_eq!(threads.get(slot), Some(7));
+
+        // Whoever holds the slot next starts afresh.
+        let next = Slot {
+            generation: slot.generation + 1,
+            ..slot
+        };
+        assert_eq!(threads.get(next), None);
+    }
+}
@@ -1,5 +1,4 @@
 use core::sync::atomic::{AtomicU8, Ordering};
-extern crate alloc;
 use alloc::vec::Vec;
 
 #[derive(Default)]
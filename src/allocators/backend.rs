@@ -0,0 +1,814 @@
+//! The system calls growers get memory with, behind one trait, so a grower is
+//! written once and runs over either raw system calls or libc:
+//!
+//! ```rust
+//! use basic_allocator::allocators::{EnhancedHeapGrower, RawAlloc, RawBackend};
+//!
+//! let allocator = RawAlloc::new(EnhancedHeapGrower::with_backend(RawBackend));
+//! ```
+//!
+//! Without an OS (`target_os = "none"`) there are no system calls to make, so
+//! neither backend, nor `DefaultBackend`, exists.
+
+#[cfg(not(target_os = "none"))]
+use core::ptr::null_mut;
+#[cfg(not(target_os = "none"))]
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::allocators::alloc_error::{AllocError, Errno, Operation};
+use crate::allocators::decay::Advice;
+#[cfg(target_os = "linux")]
+use crate::allocators::numa::MAX_NODES;
+#[cfg(not(target_os = "none"))]
+use crate::mmap::{self, MmapError};
+
+/// How mapped memory may be accessed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protection {
+    /// Not at all: the address space is reserved, and faults if touched.
+    None,
+    ReadWrite,
+}
+
+/// A source of pages of private, anonymous memory.
+///
+/// Every length is a whole number of `page_size()` pages, and every pointer
+/// is on a page. Failures are reported as `AllocError::System`, naming the
+/// system call that failed.
+pub trait MemoryBackend {
+    /// Maps `len` bytes of new memory. With `Protection::None`, no memory is
+    /// committed to it until it is protected with `Protection::ReadWrite`.
+    ///
+    /// # Safety
+    ///
+    /// None beyond the trait's rules, but the memory is never freed unless
+    /// unmapped.
+    unsafe fn map(&mut self, len: usize, prot: Protection) -> Result<*mut u8, AllocError>;
+
+    /// Maps `len` bytes of new, read-write memory, with every page already
+    /// faulted in. By default, the pages are touched one by one.
+    ///
+    /// # Safety
+    ///
+    /// As for `map`.
+    unsafe fn map_populated(&mut self, len: usize) -> Result<*mut u8, AllocError> {
+        let ptr = self.map(len, Protection::ReadWrite)?;
+        touch(ptr, len, self.page_size());
+        Ok(ptr)
+    }
+
+    /// Unmaps `[ptr, ptr + len)`.
+    ///
+    /// # Safety
+    ///
+    /// The memory must have been mapped by this backend, and not be used
+    /// again.
+    unsafe fn unmap(&mut self, ptr: *mut u8, len: usize) -> Result<(), AllocError>;
+
+    /// Grows or shrinks the mapping `[ptr, ptr + old_len)` to `new_len` bytes
+    /// without moving it. Fails if the pages it would grow into are taken.
+    ///
+    /// # Safety
+    ///
+    /// As for `unmap`, if it shrinks.
+    unsafe fn remap(&mut self, ptr: *mut u8, old_len: usize, new_len: usize) -> Result<(), AllocError>;
+
+    /// Changes how `[ptr, ptr + len)` may be accessed.
+    ///
+    /// # Safety
+    ///
+    /// The memory must have been mapped by this backend, and not be in use
+    /// if it is made inaccessible.
+    unsafe fn protect(&mut self, ptr: *mut u8, len: usize, prot: Protection) -> Result<(), AllocError>;
+
+    /// Passes `advice` on to the system for `[ptr, ptr + len)`.
+    ///
+    /// # Safety
+    ///
+    /// The memory must have been mapped by this backend, and nothing may rely
+    /// on its contents.
+    unsafe fn advise(&mut self, ptr: *mut u8, len: usize, advice: Advice) -> Result<(), AllocError>;
+
+    fn page_size(&self) -> usize;
+
+    /// Like `map`, but preferably at `hint`. By default, the hint is ignored.
+    ///
+    /// # Safety
+    ///
+    /// As for `map`.
+    unsafe fn map_near(&mut self, hint: *mut u8, len: usize, prot: Protection) -> Result<*mut u8, AllocError> {
+        let _ = hint;
+        self.map(len, prot)
+    }
+
+    /// Maps `len` bytes of new, read-write memory backed by explicit 2 MiB
+    /// huge pages (`MAP_HUGETLB`). Unsupported by default.
+    ///
+    /// # Safety
+    ///
+    /// As for `map`.
+    unsafe fn map_huge(&mut self, len: usize) -> Result<*mut u8, AllocError> {
+        let _ = len;
+        Err(unsupported(Operation::Mmap))
+    }
+
+    /// Asks for `[ptr, ptr + len)` to be backed by transparent huge pages
+    /// (`MADV_HUGEPAGE`). Unsupported by default.
+    ///
+    /// # Safety
+    ///
+    /// The memory must have been mapped by this backend.
+    unsafe fn advise_huge(&mut self, ptr: *mut u8, len: usize) -> Result<(), AllocError> {
+        let _ = (ptr, len);
+        Err(unsupported(Operation::Madvise))
+    }
+
+    /// Places `[ptr, ptr + len)` on NUMA nodes (`mbind`), with `mode` one of
+    /// Linux's `MPOL_*` modes and bit `n` of `mask` standing for node `n`.
+    /// Unsupported by default.
+    ///
+    /// # Safety
+    ///
+    /// The memory must have been mapped by this backend.
+    unsafe fn bind(&mut self, ptr: *mut u8, len: usize, mode: u64, mask: u64) -> Result<(), AllocError> {
+        let _ = (ptr, len, mode, mask);
+        Err(unsupported(Operation::Mbind))
+    }
+
+    /// Sets the NUMA policy for everything the calling thread maps from now
+    /// on (`set_mempolicy`), as for `bind`. Unsupported by default.
+    fn set_thread_policy(&mut self, mode: u64, mask: u64) -> Result<(), AllocError> {
+        let _ = (mode, mask);
+        Err(unsupported(Operation::SetMempolicy))
+    }
+
+    /// The NUMA node holding the page at `ptr`, faulting it in if need be
+    /// (`get_mempolicy`). Unsupported by default.
+    fn node_of(&mut self, ptr: *const u8) -> Result<usize, AllocError> {
+        let _ = ptr;
+        Err(unsupported(Operation::GetMempolicy))
+    }
+
+    /// Maps `len` bytes of the file open on `fd`, from `offset`, shared and
+    /// read-write, over `[addr, addr + len)`, replacing what was there.
+    /// Unsupported by default.
+    ///
+    /// # Safety
+    ///
+    /// `[addr, addr + len)` must have been mapped by this backend, and not be
+    /// in use.
+    unsafe fn map_file(&mut self, addr: *mut u8, len: usize, fd: i32, offset: usize) -> Result<(), AllocError> {
+        let _ = (addr, len, fd, offset);
+        Err(unsupported(Operation::Mmap))
+    }
+
+    /// Writes `[ptr, ptr + len)`, mapped with `map_file`, back to the file
+    /// (`msync`). Unsupported by default.
+    ///
+    /// # Safety
+    ///
+    /// The memory must have been mapped with `map_file`.
+    unsafe fn sync(&mut self, ptr: *mut u8, len: usize) -> Result<(), AllocError> {
+        let _ = (ptr, len);
+        Err(unsupported(Operation::Msync))
+    }
+
+    /// The length of the file open on `fd`. Unsupported by default.
+    fn file_len(&mut self, fd: i32) -> Result<usize, AllocError> {
+        let _ = fd;
+        Err(unsupported(Operation::Lseek))
+    }
+
+    /// Truncates or extends the file open on `fd` to `len` bytes.
+    /// Unsupported by default.
+    fn set_file_len(&mut self, fd: i32, len: usize) -> Result<(), AllocError> {
+        let _ = (fd, len);
+        Err(unsupported(Operation::Ftruncate))
+    }
+
+    /// Moves the program break to `addr`, or just reads it if `addr` is null,
+    /// returning where it ends up. Unsupported by default.
+    ///
+    /// # Safety
+    ///
+    /// Lowering the break unmaps what was above it.
+    unsafe fn brk(&mut self, addr: *mut u8) -> Result<*mut u8, AllocError> {
+        let _ = addr;
+        Err(unsupported(Operation::Brk))
+    }
+}
+
+/// The error for an operation the backend or the system does not have.
+fn unsupported(op: Operation) -> AllocError {
+    AllocError::System {
+        op,
+        errno: Errno::ENOSYS,
+    }
+}
+
+// The NUMA policy calls take the length of the node mask in bits, plus one
+// for historical reasons.
+#[cfg(target_os = "linux")]
+const MAXNODE: u64 = MAX_NODES as u64 + 1;
+
+/// Faults in every page of `[ptr, ptr + len)` by writing to it, leaving its
+/// contents as they were.
+///
+/// # Safety
+///
+/// The memory must be mapped read-write, and not be used by another thread
+/// meanwhile.
+pub(crate) unsafe fn touch(ptr: *mut u8, len: usize, page_size: usize) {
+    for offset in (0..len).step_by(page_size) {
+        let byte = ptr.add(offset);
+        byte.write_volatile(byte.read_volatile());
+    }
+}
+
+/// The backend growers use unless told otherwise: `LibcBackend` with the
+/// `use_libc` feature, `RawBackend` without.
+#[cfg(all(not(feature = "use_libc"), not(target_os = "none")))]
+pub type DefaultBackend = RawBackend;
+
+#[cfg(feature = "use_libc")]
+pub type DefaultBackend = LibcBackend;
+
+/// Memory from raw system calls, needing no libc.
+#[cfg(not(target_os = "none"))]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RawBackend;
+
+#[cfg(not(target_os = "none"))]
+impl RawBackend {
+    fn prot_bits(prot: Protection) -> u64 {
+        match prot {
+            Protection::None => mmap::PROT_NONE,
+            Protection::ReadWrite => mmap::PROT_READ | mmap::PROT_WRITE,
+        }
+    }
+
+    /// Decodes the error from a failed `op`.
+    fn error(op: Operation) -> impl Fn(MmapError) -> AllocError {
+        move |error| AllocError::System {
+            op,
+            errno: Errno::from_code(error.code as i32),
+        }
+    }
+
+    fn system_page_size() -> usize {
+        #[cfg(target_os = "linux")]
+        return mmap::page_size().unwrap_or(4096);
+
+        #[cfg(target_arch = "wasm32")]
+        return 64 * 1024;
+
+        #[cfg(all(target_os = "macos", target_arch = "aarch64"))]
+        return 16 * 1024;
+
+        #[cfg(not(any(
+            target_os = "linux",
+            target_arch = "wasm32",
+            all(target_os = "macos", target_arch = "aarch64")
+        )))]
+        return 4096;
+    }
+}
+
+#[cfg(not(target_os = "none"))]
+impl MemoryBackend for RawBackend {
+    unsafe fn map(&mut self, len: usize, prot: Protection) -> Result<*mut u8, AllocError> {
+        let mut flags = mmap::MAP_ANON | mmap::MAP_PRIVATE;
+        #[cfg(target_os = "linux")]
+        if prot == Protection::None {
+            flags |= mmap::MAP_NORESERVE;
+        }
+        mmap::mmap(null_mut(), len, Self::prot_bits(prot), flags, u64::MAX, 0).map_err(Self::error(Operation::Mmap))
+    }
+
+    #[cfg(target_os = "linux")]
+    unsafe fn map_populated(&mut self, len: usize) -> Result<*mut u8, AllocError> {
+        let flags = mmap::MAP_ANON | mmap::MAP_PRIVATE | mmap::MAP_POPULATE;
+        mmap::mmap(null_mut(), len, Self::prot_bits(Protection::ReadWrite), flags, u64::MAX, 0)
+            .map_err(Self::error(Operation::Mmap))
+    }
+
+    unsafe fn unmap(&mut self, ptr: *mut u8, len: usize) -> Result<(), AllocError> {
+        mmap::munmap(ptr, len).map_err(Self::error(Operation::Munmap))
+    }
+
+    unsafe fn remap(&mut self, ptr: *mut u8, old_len: usize, new_len: usize) -> Result<(), AllocError> {
+        // Without MREMAP_MAYMOVE, mremap fails rather than move the mapping.
+        #[cfg(target_os = "linux")]
+        return mmap::mremap(ptr, old_len, new_len, 0)
+            .map(|_| ())
+            .map_err(Self::error(Operation::Mremap));
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = (ptr, old_len, new_len);
+            Err(unsupported(Operation::Mremap))
+        }
+    }
+
+    unsafe fn protect(&mut self, ptr: *mut u8, len: usize, prot: Protection) -> Result<(), AllocError> {
+        #[cfg(target_os = "linux")]
+        return mmap::mprotect(ptr, len, Self::prot_bits(prot)).map_err(Self::error(Operation::Mprotect));
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = (ptr, len, prot);
+            Err(unsupported(Operation::Mprotect))
+        }
+    }
+
+    unsafe fn advise(&mut self, ptr: *mut u8, len: usize, advice: Advice) -> Result<(), AllocError> {
+        #[cfg(target_os = "linux")]
+        {
+            let advice = match advice {
+                Advice::Free => mmap::MADV_FREE,
+                Advice::DontNeed => mmap::MADV_DONTNEED,
+            };
+            mmap::madvise(ptr, len, advice).map_err(Self::error(Operation::Madvise))
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = (ptr, len, advice);
+            Err(unsupported(Operation::Madvise))
+        }
+    }
+
+    /// Found out the first time, and remembered: on Linux, from the
+    /// auxiliary vector's `AT_PAGESZ`, as kernels for aarch64 may use 16 or 64
+    /// KiB pages.
+    fn page_size(&self) -> usize {
+        static PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+        match PAGE_SIZE.load(Ordering::Relaxed) {
+            0 => {
+                let page_size = Self::system_page_size();
+                PAGE_SIZE.store(page_size, Ordering::Relaxed);
+                page_size
+            }
+            page_size => page_size,
+        }
+    }
+
+    unsafe fn map_near(&mut self, hint: *mut u8, len: usize, prot: Protection) -> Result<*mut u8, AllocError> {
+        let mut flags = mmap::MAP_ANON | mmap::MAP_PRIVATE;
+        #[cfg(target_os = "linux")]
+        if prot == Protection::None {
+            flags |= mmap::MAP_NORESERVE;
+        }
+        mmap::mmap(hint, len, Self::prot_bits(prot), flags, u64::MAX, 0).map_err(Self::error(Operation::Mmap))
+    }
+
+    unsafe fn map_huge(&mut self, len: usize) -> Result<*mut u8, AllocError> {
+        #[cfg(target_os = "linux")]
+        {
+            let flags = mmap::MAP_ANON | mmap::MAP_PRIVATE | mmap::MAP_HUGETLB | mmap::MAP_HUGE_2MB;
+            mmap::mmap(null_mut(), len, Self::prot_bits(Protection::ReadWrite), flags, u64::MAX, 0)
+                .map_err(Self::error(Operation::Mmap))
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = len;
+            Err(unsupported(Operation::Mmap))
+        }
+    }
+
+    unsafe fn advise_huge(&mut self, ptr: *mut u8, len: usize) -> Result<(), AllocError> {
+        #[cfg(target_os = "linux")]
+        return mmap::madvise(ptr, len, mmap::MADV_HUGEPAGE).map_err(Self::error(Operation::Madvise));
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = (ptr, len);
+            Err(unsupported(Operation::Madvise))
+        }
+    }
+
+    unsafe fn bind(&mut self, ptr: *mut u8, len: usize, mode: u64, mask: u64) -> Result<(), AllocError> {
+        #[cfg(target_os = "linux")]
+        return mmap::mbind(ptr, len, mode, &mask, MAXNODE, 0).map_err(Self::error(Operation::Mbind));
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = (ptr, len, mode, mask);
+            Err(unsupported(Operation::Mbind))
+        }
+    }
+
+    fn set_thread_policy(&mut self, mode: u64, mask: u64) -> Result<(), AllocError> {
+        #[cfg(target_os = "linux")]
+        return unsafe { mmap::set_mempolicy(mode, &mask, MAXNODE) }.map_err(Self::error(Operation::SetMempolicy));
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = (mode, mask);
+            Err(unsupported(Operation::SetMempolicy))
+        }
+    }
+
+    fn node_of(&mut self, ptr: *const u8) -> Result<usize, AllocError> {
+        #[cfg(target_os = "linux")]
+        {
+            let mut node = 0;
+            let flags = mmap::MPOL_F_NODE | mmap::MPOL_F_ADDR;
+            unsafe { mmap::get_mempolicy(&mut node, null_mut(), 0, ptr as *mut u8, flags) }
+                .map_err(Self::error(Operation::GetMempolicy))?;
+            Ok(node as usize)
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = ptr;
+            Err(unsupported(Operation::GetMempolicy))
+        }
+    }
+
+    unsafe fn map_file(&mut self, addr: *mut u8, len: usize, fd: i32, offset: usize) -> Result<(), AllocError> {
+        let prot = Self::prot_bits(Protection::ReadWrite);
+        let flags = mmap::MAP_SHARED | mmap::MAP_FIXED;
+        mmap::mmap(addr, len, prot, flags, fd as u64, offset as i64)
+            .map(|_| ())
+            .map_err(Self::error(Operation::Mmap))
+    }
+
+    unsafe fn sync(&mut self, ptr: *mut u8, len: usize) -> Result<(), AllocError> {
+        #[cfg(target_os = "linux")]
+        return mmap::msync(ptr, len, mmap::MS_SYNC).map_err(Self::error(Operation::Msync));
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = (ptr, len);
+            Err(unsupported(Operation::Msync))
+        }
+    }
+
+    fn file_len(&mut self, fd: i32) -> Result<usize, AllocError> {
+        #[cfg(target_os = "linux")]
+        return unsafe { mmap::lseek(fd as u64, 0, mmap::SEEK_END) }
+            .map(|len| len as usize)
+            .map_err(Self::error(Operation::Lseek));
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = fd;
+            Err(unsupported(Operation::Lseek))
+        }
+    }
+
+    fn set_file_len(&mut self, fd: i32, len: usize) -> Result<(), AllocError> {
+        #[cfg(target_os = "linux")]
+        return unsafe { mmap::ftruncate(fd as u64, len as u64) }.map_err(Self::error(Operation::Ftruncate));
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = (fd, len);
+            Err(unsupported(Operation::Ftruncate))
+        }
+    }
+
+    /// The raw system call, which moves the break without telling libc; see
+    /// `BrkHeapGrower`.
+    unsafe fn brk(&mut self, addr: *mut u8) -> Result<*mut u8, AllocError> {
+        #[cfg(target_os = "linux")]
+        return Ok(mmap::brk(addr));
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = addr;
+            Err(unsupported(Operation::Brk))
+        }
+    }
+}
+
+/// Memory from libc.
+#[cfg(feature = "use_libc")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LibcBackend;
+
+#[cfg(feature = "use_libc")]
+impl LibcBackend {
+    fn prot_bits(prot: Protection) -> libc::c_int {
+        match prot {
+            Protection::None => libc::PROT_NONE,
+            Protection::ReadWrite => libc::PROT_READ | libc::PROT_WRITE,
+        }
+    }
+
+    /// The error from `op`, which just failed, setting `errno`.
+    fn error(op: Operation) -> AllocError {
+        AllocError::System {
+            op,
+            errno: Errno::from_code(errno::errno().0),
+        }
+    }
+
+    fn check(result: libc::c_int, op: Operation) -> Result<(), AllocError> {
+        if result != 0 {
+            return Err(Self::error(op));
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    fn check_syscall(result: libc::c_long, op: Operation) -> Result<(), AllocError> {
+        if result < 0 {
+            return Err(Self::error(op));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "use_libc")]
+impl MemoryBackend for LibcBackend {
+
+    unsafe fn map(&mut self, len: usize, prot: Protection) -> Result<*mut u8, AllocError> {
+        let mut flags = libc::MAP_ANON | libc::MAP_PRIVATE;
+        #[cfg(target_os = "linux")]
+        if prot == Protection::None {
+            flags |= libc::MAP_NORESERVE;
+        }
+        let ptr = libc::mmap(null_mut(), len, Self::prot_bits(prot), flags, -1, 0);
+        if ptr == libc::MAP_FAILED {
+            return Err(Self::error(Operation::Mmap));
+        }
+        Ok(ptr as *mut u8)
+    }
+
+    #[cfg(target_os = "linux")]
+    unsafe fn map_populated(&mut self, len: usize) -> Result<*mut u8, AllocError> {
+        let flags = libc::MAP_ANON | libc::MAP_PRIVATE | libc::MAP_POPULATE;
+        let ptr = libc::mmap(null_mut(), len, Self::prot_bits(Protection::ReadWrite), flags, -1, 0);
+        if ptr == libc::MAP_FAILED {
+            return Err(Self::error(Operation::Mmap));
+        }
+        Ok(ptr as *mut u8)
+    }
+
+    unsafe fn unmap(&mut self, ptr: *mut u8, len: usize) -> Result<(), AllocError> {
+        Self::check(libc::munmap(ptr as *mut _, len), Operation::Munmap)
+    }
+
+    unsafe fn remap(&mut self, ptr: *mut u8, old_len: usize, new_len: usize) -> Result<(), AllocError> {
+        #[cfg(target_os = "linux")]
+        {
+            if libc::mremap(ptr as *mut _, old_len, new_len, 0) == libc::MAP_FAILED {
+                return Err(Self::error(Operation::Mremap));
+            }
+            Ok(())
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = (ptr, old_len, new_len);
+            Err(unsupported(Operation::Mremap))
+        }
+    }
+
+    unsafe fn protect(&mut self, ptr: *mut u8, len: usize, prot: Protection) -> Result<(), AllocError> {
+        Self::check(libc::mprotect(ptr as *mut _, len, Self::prot_bits(prot)), Operation::Mprotect)
+    }
+
+    unsafe fn advise(&mut self, ptr: *mut u8, len: usize, advice: Advice) -> Result<(), AllocError> {
+        let advice = match advice {
+            Advice::Free => libc::MADV_FREE,
+            Advice::DontNeed => libc::MADV_DONTNEED,
+        };
+        Self::check(libc::madvise(ptr as *mut _, len, advice), Operation::Madvise)
+    }
+
+    fn page_size(&self) -> usize {
+        sysconf::page::pagesize()
+    }
+
+    unsafe fn map_near(&mut self, hint: *mut u8, len: usize, prot: Protection) -> Result<*mut u8, AllocError> {
+        let mut flags = libc::MAP_ANON | libc::MAP_PRIVATE;
+        #[cfg(target_os = "linux")]
+        if prot == Protection::None {
+            flags |= libc::MAP_NORESERVE;
+        }
+        let ptr = libc::mmap(hint as *mut _, len, Self::prot_bits(prot), flags, -1, 0);
+        if ptr == libc::MAP_FAILED {
+            return Err(Self::error(Operation::Mmap));
+        }
+        Ok(ptr as *mut u8)
+    }
+
+    #[cfg(target_os = "linux")]
+    unsafe fn map_huge(&mut self, len: usize) -> Result<*mut u8, AllocError> {
+        let flags = libc::MAP_ANON | libc::MAP_PRIVATE | libc::MAP_HUGETLB | libc::MAP_HUGE_2MB;
+        let ptr = libc::mmap(null_mut(), len, Self::prot_bits(Protection::ReadWrite), flags, -1, 0);
+        if ptr == libc::MAP_FAILED {
+            return Err(Self::error(Operation::Mmap));
+        }
+        Ok(ptr as *mut u8)
+    }
+
+    #[cfg(target_os = "linux")]
+    unsafe fn advise_huge(&mut self, ptr: *mut u8, len: usize) -> Result<(), AllocError> {
+        Self::check(libc::madvise(ptr as *mut _, len, libc::MADV_HUGEPAGE), Operation::Madvise)
+    }
+
+    // libc wraps none of the policy calls. Variadic arguments are passed as
+    // words, so nothing wider may go in.
+    #[cfg(target_os = "linux")]
+    unsafe fn bind(&mut self, ptr: *mut u8, len: usize, mode: u64, mask: u64) -> Result<(), AllocError> {
+        let result = libc::syscall(
+            libc::SYS_mbind,
+            ptr,
+            len,
+            mode as libc::c_ulong,
+            &mask as *const u64,
+            MAXNODE as libc::c_ulong,
+            0 as libc::c_uint,
+        );
+        Self::check_syscall(result, Operation::Mbind)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_thread_policy(&mut self, mode: u64, mask: u64) -> Result<(), AllocError> {
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_set_mempolicy,
+                mode as libc::c_int,
+                &mask as *const u64,
+                MAXNODE as libc::c_ulong,
+            )
+        };
+        Self::check_syscall(result, Operation::SetMempolicy)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn node_of(&mut self, ptr: *const u8) -> Result<usize, AllocError> {
+        let mut node: libc::c_int = 0;
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_get_mempolicy,
+                &mut node as *mut libc::c_int,
+                null_mut::<u64>(),
+                0 as libc::c_ulong,
+                ptr as usize,
+                (crate::mmap::MPOL_F_NODE | crate::mmap::MPOL_F_ADDR) as libc::c_ulong,
+            )
+        };
+        Self::check_syscall(result, Operation::GetMempolicy)?;
+        Ok(node as usize)
+    }
+
+    unsafe fn map_file(&mut self, addr: *mut u8, len: usize, fd: i32, offset: usize) -> Result<(), AllocError> {
+        let prot = Self::prot_bits(Protection::ReadWrite);
+        let flags = libc::MAP_SHARED | libc::MAP_FIXED;
+        let ptr = libc::mmap(addr as *mut _, len, prot, flags, fd, offset as libc::off_t);
+        if ptr == libc::MAP_FAILED {
+            return Err(Self::error(Operation::Mmap));
+        }
+        Ok(())
+    }
+
+    unsafe fn sync(&mut self, ptr: *mut u8, len: usize) -> Result<(), AllocError> {
+        Self::check(libc::msync(ptr as *mut _, len, libc::MS_SYNC), Operation::Msync)
+    }
+
+    fn file_len(&mut self, fd: i32) -> Result<usize, AllocError> {
+        let len = unsafe { libc::lseek(fd, 0, libc::SEEK_END) };
+        if len < 0 {
+            return Err(Self::error(Operation::Lseek));
+        }
+        Ok(len as usize)
+    }
+
+    fn set_file_len(&mut self, fd: i32, len: usize) -> Result<(), AllocError> {
+        Self::check(unsafe { libc::ftruncate(fd, len as libc::off_t) }, Operation::Ftruncate)
+    }
+
+    /// Through libc rather than the raw system call, so that the break it
+    /// caches for its own `sbrk` stays right.
+    unsafe fn brk(&mut self, addr: *mut u8) -> Result<*mut u8, AllocError> {
+        if !addr.is_null() && libc::brk(addr as *mut libc::c_void) == 0 {
+            return Ok(addr);
+        }
+        Ok(libc::sbrk(0) as *mut u8)
+    }
+}
+
+#[cfg(test)]
+pub(crate) use mock::{MockBackend, MockMemory, MOCK_PAGE};
+
+/// A backend over a fixed buffer, handing out pages from the front, for
+/// testing growers without the system.
+#[cfg(test)]
+mod mock {
+    use super::*;
+    use core::cell::{Cell, UnsafeCell};
+
+    pub(crate) const MOCK_PAGE: usize = 4096;
+    const MOCK_PAGES: usize = 64;
+
+    /// The memory and counters a `MockBackend` works on. It outlives the
+    /// backend, so a test can check what a grower left behind once dropped.
+    #[repr(C, align(4096))]
+    pub(crate) struct MockMemory {
+        pages: UnsafeCell<[[u8; MOCK_PAGE]; MOCK_PAGES]>,
+        // Pages handed out so far; only the last mapping can grow.
+        used: Cell<usize>,
+        pub(crate) fail_maps: Cell<bool>,
+        pub(crate) maps: Cell<usize>,
+        pub(crate) remaps: Cell<usize>,
+        pub(crate) advised: Cell<usize>,
+        pub(crate) mapped_bytes: Cell<usize>,
+    }
+
+    impl MockMemory {
+        pub(crate) fn new() -> Self {
+            MockMemory {
+                pages: UnsafeCell::new([[0; MOCK_PAGE]; MOCK_PAGES]),
+                used: Cell::new(0),
+                fail_maps: Cell::new(false),
+                maps: Cell::new(0),
+                remaps: Cell::new(0),
+                advised: Cell::new(0),
+                mapped_bytes: Cell::new(0),
+            }
+        }
+
+        fn base(&self) -> *mut u8 {
+            self.pages.get() as *mut u8
+        }
+
+        fn end(&self) -> *mut u8 {
+            self.base().wrapping_add(self.used.get() * MOCK_PAGE)
+        }
+
+        fn error(op: Operation, errno: Errno) -> AllocError {
+            AllocError::System { op, errno }
+        }
+
+        /// Checks that `[ptr, ptr + len)` is whole pages that were handed out.
+        fn check(&self, op: Operation, ptr: *mut u8, len: usize) -> Result<(), AllocError> {
+            let offset = (ptr as usize).wrapping_sub(self.base() as usize);
+            if offset % MOCK_PAGE != 0 || len % MOCK_PAGE != 0 || offset + len > self.used.get() * MOCK_PAGE {
+                return Err(Self::error(op, Errno::EINVAL));
+            }
+            Ok(())
+        }
+    }
+
+    #[derive(Clone, Copy)]
+    pub(crate) struct MockBackend<'a>(pub(crate) &'a MockMemory);
+
+    impl MemoryBackend for MockBackend<'_> {
+        unsafe fn map(&mut self, len: usize, _prot: Protection) -> Result<*mut u8, AllocError> {
+            let memory = self.0;
+            let pages = len / MOCK_PAGE;
+            if memory.fail_maps.get() || len % MOCK_PAGE != 0 || memory.used.get() + pages > MOCK_PAGES {
+                return Err(MockMemory::error(Operation::Mmap, Errno::ENOMEM));
+            }
+            let ptr = memory.end();
+            memory.used.set(memory.used.get() + pages);
+            memory.maps.set(memory.maps.get() + 1);
+            memory.mapped_bytes.set(memory.mapped_bytes.get() + len);
+            Ok(ptr)
+        }
+
+        unsafe fn unmap(&mut self, ptr: *mut u8, len: usize) -> Result<(), AllocError> {
+            let memory = self.0;
+            memory.check(Operation::Munmap, ptr, len)?;
+            memory.mapped_bytes.set(memory.mapped_bytes.get() - len);
+            Ok(())
+        }
+
+        unsafe fn remap(&mut self, ptr: *mut u8, old_len: usize, new_len: usize) -> Result<(), AllocError> {
+            let memory = self.0;
+            memory.check(Operation::Mremap, ptr, old_len)?;
+            let pages = (ptr.add(new_len) as usize - memory.base() as usize).div_ceil(MOCK_PAGE);
+            if ptr.add(old_len) != memory.end() || pages > MOCK_PAGES {
+                return Err(MockMemory::error(Operation::Mremap, Errno::ENOMEM));
+            }
+            memory.used.set(pages);
+            memory.remaps.set(memory.remaps.get() + 1);
+            memory.mapped_bytes.set(memory.mapped_bytes.get() + new_len - old_len);
+            Ok(())
+        }
+
+        unsafe fn protect(&mut self, ptr: *mut u8, len: usize, _prot: Protection) -> Result<(), AllocError> {
+            self.0.check(Operation::Mprotect, ptr, len)
+        }
+
+        unsafe fn advise(&mut self, ptr: *mut u8, len: usize, advice: Advice) -> Result<(), AllocError> {
+            let memory = self.0;
+            memory.check(Operation::Madvise, ptr, len)?;
+            if advice == Advice::DontNeed {
+                ptr.write_bytes(0, len);
+            }
+            memory.advised.set(memory.advised.get() + len);
+            Ok(())
+        }
+
+        fn page_size(&self) -> usize {
+            MOCK_PAGE
+        }
+    }
+}
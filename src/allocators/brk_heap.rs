@@ -0,0 +1,177 @@
+use core::ptr::null_mut;
+
+use crate::allocators::alloc_error::AllocError;
+use crate::allocators::backend::{DefaultBackend, MemoryBackend};
+use crate::allocators::decay::Advice;
+use crate::allocators::heap_grower::{EnhancedHeapGrower, GrowerStats, HeapGrower};
+
+// The same default as glibc's M_TRIM_THRESHOLD.
+const DEFAULT_TRIM_THRESHOLD: usize = 128 * 1024;
+
+/// A `HeapGrower` that extends the program break, like a classic `malloc`.
+///
+/// Successive growths are contiguous, so free blocks merge across them, and
+/// free memory at the top of the heap is given back by lowering the break.
+/// When the break cannot be moved, e.g. because another mapping is in the
+/// way, memory comes from `mmap` instead, for the rest of the grower's life.
+///
+/// The program break is process-wide, and libc keeps its own copy of it:
+/// glibc's `sbrk` (and so its `malloc`) moves the break relative to where it
+/// last left it, and can lower it over memory this grower handed out, even
+/// in a single-threaded program. Over `RawBackend`, the default without
+/// `use_libc`, the break is moved with raw system calls, so this grower must
+/// be the only user of the break in the process, e.g. in a program whose
+/// global allocator never calls `brk`. Over `LibcBackend` it goes through
+/// libc, which keeps that copy right, but nothing else may move the break
+/// from another thread while this grower is in use.
+pub struct BrkHeapGrower<B: MemoryBackend = DefaultBackend> {
+    // The contiguous run of memory below the break that is ours to give back.
+    base: *mut u8,
+    end: *mut u8,
+    // Bytes from earlier runs, below memory someone else took with brk.
+    stranded: usize,
+    brk_failed: bool,
+    trim_threshold: usize,
+    attempts: usize,
+    growths: usize,
+    peak: usize,
+    // Holds the backend, which moves the break too.
+    fallback: EnhancedHeapGrower<B>,
+}
+
+impl Default for BrkHeapGrower {
+    fn default() -> Self {
+        Self::with_backend(DefaultBackend {})
+    }
+}
+
+impl<B: MemoryBackend> BrkHeapGrower<B> {
+    pub const fn with_backend(backend: B) -> Self {
+        BrkHeapGrower {
+            base: null_mut(),
+            end: null_mut(),
+            stranded: 0,
+            brk_failed: false,
+            trim_threshold: DEFAULT_TRIM_THRESHOLD,
+            attempts: 0,
+            growths: 0,
+            peak: 0,
+            fallback: EnhancedHeapGrower::with_backend(backend),
+        }
+    }
+
+    /// Sets how much free memory at the top of the heap is given back as soon
+    /// as it is freed.
+    pub fn set_trim_threshold(&mut self, bytes: usize) {
+        self.trim_threshold = bytes;
+    }
+
+    /// Bytes currently obtained by moving the break.
+    pub fn brk_bytes(&self) -> usize {
+        self.stranded + (self.end as usize - self.base as usize)
+    }
+
+    /// Whether growths have fallen back to `mmap`.
+    pub fn brk_failed(&self) -> bool {
+        self.brk_failed
+    }
+
+    fn mapped_bytes(&self) -> usize {
+        self.brk_bytes() + self.fallback.stats().mapped_bytes
+    }
+
+    /// Moves the break to `addr`, or just reads it if `addr` is null.
+    unsafe fn brk(&mut self, addr: *mut u8) -> Option<*mut u8> {
+        self.fallback.backend_mut().brk(addr).ok()
+    }
+
+    unsafe fn grow_brk(&mut self, size: usize) -> Option<*mut u8> {
+        let current = self.brk(null_mut())?;
+        // Start on a page, so trimming can give back all of it.
+        let start = current.add(current.align_offset(self.page_size()));
+        let end = start.add(size);
+        if self.brk(end)? != end {
+            return None;
+        }
+        if current != self.end {
+            // Someone else moved the break since we last did (or this is our
+            // first growth). What we had stays ours, but the break can no
+            // longer be lowered into it.
+            self.stranded += self.end as usize - self.base as usize;
+            self.base = start;
+        }
+        self.end = end;
+        Some(start)
+    }
+}
+
+impl<B: MemoryBackend> HeapGrower for BrkHeapGrower<B> {
+    type Err = AllocError;
+
+    unsafe fn grow_heap(&mut self, size: usize) -> Result<(*mut u8, usize), Self::Err> {
+        self.attempts += 1;
+        let size = size.next_multiple_of(self.page_size());
+        let grown = if self.brk_failed {
+            None
+        } else {
+            self.grow_brk(size)
+        };
+        let (ptr, size) = match grown {
+            Some(ptr) => (ptr, size),
+            None => {
+                self.brk_failed = true;
+                self.fallback.grow_heap(size)?
+            }
+        };
+        self.growths += 1;
+        self.peak = self.peak.max(self.mapped_bytes());
+        Ok((ptr, size))
+    }
+
+    unsafe fn shrink_heap(&mut self, ptr: *mut u8, size: usize) -> usize {
+        let end = ptr.add(size);
+        // Only the top of our brk memory can go, and only while nothing else
+        // has moved the break above it.
+        if end != self.end || ptr < self.base || self.brk(null_mut()) != Some(end) {
+            return 0;
+        }
+        if self.brk(ptr) != Some(ptr) {
+            return 0;
+        }
+        self.end = ptr;
+        size
+    }
+
+    fn trim_threshold(&self) -> Option<usize> {
+        Some(self.trim_threshold)
+    }
+
+    fn page_size(&self) -> usize {
+        self.fallback.page_size()
+    }
+
+    /// The heap and fallback mappings are both anonymous memory.
+    unsafe fn advise(&mut self, ptr: *mut u8, size: usize, advice: Advice) -> bool {
+        self.fallback.backend_mut().advise(ptr, size, advice).is_ok()
+    }
+
+    fn stats(&self) -> GrowerStats {
+        GrowerStats {
+            attempts: self.attempts,
+            growths: self.growths,
+            mapped_bytes: self.mapped_bytes(),
+            peak_bytes: self.peak,
+            ..GrowerStats::default()
+        }
+    }
+}
+
+impl<B: MemoryBackend> Drop for BrkHeapGrower<B> {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.base.is_null() && self.brk(null_mut()) == Some(self.end) {
+                self.brk(self.base);
+            }
+        }
+    }
+}
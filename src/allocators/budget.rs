@@ -0,0 +1,254 @@
+use core::alloc::Layout;
+
+use crate::allocators::alloc_error::AllocError;
+
+/// What to do about an allocation that could not be satisfied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OomAction {
+    /// Memory has been freed; try the allocation again.
+    Retry,
+    /// Give up, and return null.
+    Fail,
+}
+
+/// An allocation that is about to fail, either because it would take live
+/// bytes past the hard limit, or because the grower could not grow.
+#[derive(Debug, Clone, Copy)]
+pub struct OomEvent {
+    pub layout: Layout,
+    /// Bytes handed out when the allocation failed.
+    pub live_bytes: usize,
+    pub hard_limit: Option<usize>,
+    /// How many times this allocation has failed, starting at 1.
+    pub attempt: usize,
+    /// Why it failed this time.
+    pub error: AllocError,
+}
+
+/// Called before a failed allocation returns null. It may free memory, e.g.
+/// by dropping caches, and ask for the allocation to be retried; it should
+/// return `Fail` once there is nothing left to free.
+///
+/// `GenericAllocator` calls it without the heap locked, so it may free and
+/// allocate through the same allocator.
+pub type OomHandler = fn(&OomEvent) -> OomAction;
+
+/// Called when live bytes go past the soft limit, with the live bytes and the
+/// limit. It is called again only after usage has dropped back to the limit
+/// and crossed it once more.
+pub type SoftLimitHandler = fn(usize, usize);
+
+/// Limits on the bytes a heap hands out, counted in live bytes (including
+/// block padding) rather than memory obtained from the grower, so a heap
+/// that has grown and then freed memory is not held against its limit.
+///
+/// Allocations that would take live bytes past the hard limit fail. Passing
+/// the soft limit only calls a handler, to e.g. shed caches early.
+#[derive(Debug, Default)]
+pub struct Budget {
+    hard_limit: Option<usize>,
+    soft_limit: Option<usize>,
+    oom_handler: Option<OomHandler>,
+    soft_limit_handler: Option<SoftLimitHandler>,
+    over_soft_limit: bool,
+    soft_limit_pending: bool,
+    denials: usize,
+    soft_limit_crossings: usize,
+}
+
+impl Budget {
+    /// A budget with no limits and no handlers.
+    pub const fn unlimited() -> Self {
+        Budget {
+            hard_limit: None,
+            soft_limit: None,
+            oom_handler: None,
+            soft_limit_handler: None,
+            over_soft_limit: false,
+            soft_limit_pending: false,
+            denials: 0,
+            soft_limit_crossings: 0,
+        }
+    }
+
+    pub fn hard_limit(&self) -> Option<usize> {
+        self.hard_limit
+    }
+
+    /// Sets the most live bytes the heap may hand out. Lowering it below the
+    /// bytes already live only stops further allocations.
+    pub fn set_hard_limit(&mut self, limit: Option<usize>) {
+        self.hard_limit = limit;
+    }
+
+    pub fn soft_limit(&self) -> Option<usize> {
+        self.soft_limit
+    }
+
+    pub fn set_soft_limit(&mut self, limit: Option<usize>) {
+        self.soft_limit = limit;
+        self.over_soft_limit = false;
+        self.soft_limit_pending = false;
+    }
+
+    pub fn oom_handler(&self) -> Option<OomHandler> {
+        self.oom_handler
+    }
+
+    pub fn set_oom_handler(&mut self, handler: Option<OomHandler>) {
+        self.oom_handler = handler;
+    }
+
+    pub fn soft_limit_handler(&self) -> Option<SoftLimitHandler> {
+        self.soft_limit_handler
+    }
+
+    pub fn set_soft_limit_handler(&mut self, handler: Option<SoftLimitHandler>) {
+        self.soft_limit_handler = handler;
+    }
+
+    /// Whether live bytes are currently past the soft limit.
+    pub fn over_soft_limit(&self) -> bool {
+        self.over_soft_limit
+    }
+
+    /// Allocations refused for going past the hard limit.
+    pub fn denials(&self) -> usize {
+        self.denials
+    }
+
+    /// Times live bytes have gone past the soft limit.
+    pub fn soft_limit_crossings(&self) -> usize {
+        self.soft_limit_crossings
+    }
+
+    /// Whether `size` more bytes fit under the hard limit.
+    #[inline]
+    pub(crate) fn admits(&self, live_bytes: usize, size: usize) -> bool {
+        match self.hard_limit {
+            Some(limit) => live_bytes.saturating_add(size) <= limit,
+            None => true,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn deny(&mut self) {
+        self.denials += 1;
+    }
+
+    /// Notes a change in live bytes, for the soft limit.
+    #[inline]
+    pub(crate) fn update(&mut self, live_bytes: usize) {
+        let Some(limit) = self.soft_limit else {
+            return;
+        };
+        let over = live_bytes > limit;
+        if over && !self.over_soft_limit {
+            self.soft_limit_crossings += 1;
+            self.soft_limit_pending = true;
+        }
+        self.over_soft_limit = over;
+    }
+
+    /// The soft limit handler and limit, if the limit has been crossed since
+    /// this was last called. The handler is left to the caller to call, so
+    /// that it can first release any lock on the heap.
+    pub fn take_soft_limit_crossing(&mut self) -> Option<(SoftLimitHandler, usize)> {
+        if !core::mem::take(&mut self.soft_limit_pending) {
+            return None;
+        }
+        Some((self.soft_limit_handler?, self.soft_limit?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocators::{GenericAllocator, RawAlloc, ToyHeap};
+    use core::alloc::GlobalAlloc;
+    use core::ptr::null_mut;
+    use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+    use crate::blocklist::BLOCK_ALIGN;
+    use test_log::test;
+
+    #[test]
+    fn test_limits() {
+        let mut allocator = RawAlloc::new(ToyHeap::default());
+        allocator.budget.set_hard_limit(Some(1024));
+        allocator.budget.set_soft_limit(Some(512));
+        let layout = Layout::from_size_align(400, BLOCK_ALIGN).unwrap();
+        unsafe {
+            let a = allocator.alloc(layout);
+            assert!(!a.is_null());
+            assert!(!allocator.budget.over_soft_limit());
+            let b = allocator.alloc(layout);
+            assert!(!b.is_null());
+            assert!(allocator.budget.over_soft_limit());
+            assert!(allocator.alloc(layout).is_null());
+            assert_eq!(allocator.budget.denials(), 1);
+            // Growing in place counts against the limit too.
+            assert!(allocator.realloc(b, layout, 800).is_null());
+
+            allocator.budget.set_hard_limit(Some(2048));
+            let c = allocator.alloc(layout);
+            assert!(!c.is_null());
+
+            for ptr in [a, b, c] {
+                allocator.dealloc(ptr, layout);
+            }
+            assert!(!allocator.budget.over_soft_limit());
+            // Live bytes, not mapped bytes, count: the freed memory is reused
+            // under a lower limit.
+            allocator.budget.set_hard_limit(Some(1024));
+            assert!(!allocator.alloc(layout).is_null());
+            assert_eq!(allocator.budget.soft_limit_crossings(), 1);
+        }
+    }
+
+    static ALLOCATOR: GenericAllocator<ToyHeap> = GenericAllocator::new();
+    static CACHE: AtomicPtr<u8> = AtomicPtr::new(null_mut());
+    static CROSSINGS: AtomicUsize = AtomicUsize::new(0);
+    const LAYOUT: Layout = unsafe { Layout::from_size_align_unchecked(4000, BLOCK_ALIGN) };
+
+    fn drop_cache(event: &OomEvent) -> OomAction {
+        assert_eq!(event.attempt, 1);
+        assert!(matches!(event.error, AllocError::LimitExceeded { .. }));
+        let cache = CACHE.swap(null_mut(), Ordering::Relaxed);
+        if cache.is_null() {
+            return OomAction::Fail;
+        }
+        // The heap is not locked here.
+        unsafe { ALLOCATOR.dealloc(cache, LAYOUT) };
+        OomAction::Retry
+    }
+
+    fn count_crossing(live: usize, limit: usize) {
+        assert!(live > limit);
+        CROSSINGS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn test_oom_handler() {
+        ALLOCATOR.set_hard_limit(Some(2 * LAYOUT.size()));
+        ALLOCATOR.set_soft_limit(Some(LAYOUT.size()), Some(count_crossing));
+        ALLOCATOR.set_oom_handler(Some(drop_cache));
+        unsafe {
+            CACHE.store(ALLOCATOR.alloc(LAYOUT), Ordering::Relaxed);
+            let a = ALLOCATOR.alloc(LAYOUT);
+            assert!(!a.is_null());
+            assert_eq!(CROSSINGS.load(Ordering::Relaxed), 1);
+
+            // Only fits once the cache is dropped.
+            let b = ALLOCATOR.alloc(LAYOUT);
+            assert!(!b.is_null());
+            assert!(CACHE.load(Ordering::Relaxed).is_null());
+
+            // Nothing left to drop.
+            assert!(ALLOCATOR.alloc(LAYOUT).is_null());
+            assert_eq!(ALLOCATOR.get_raw().budget().denials(), 2);
+
+            ALLOCATOR.dealloc(a, LAYOUT);
+            ALLOCATOR.dealloc(b, LAYOUT);
+        }
+    }
+}
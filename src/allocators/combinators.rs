@@ -0,0 +1,589 @@
+//! Wrappers that add one behaviour to any `HeapGrower`, so growth policies can
+//! be assembled from parts:
+//!
+//! ```rust
+//! use basic_allocator::allocators::{
+//!     Chunked, Counting, EnhancedHeapGrower, Fallback, Limit, RawAlloc, StaticHeap,
+//! };
+//!
+//! // Up to 1 GiB from the system in chunks of 1 MiB and up, then whatever
+//! // fixed buffers have been donated for emergencies.
+//! let grower = Counting::new(Fallback::new(
+//!     Limit::new(Chunked::new(EnhancedHeapGrower::default(), 1 << 20), 1 << 30),
+//!     StaticHeap::empty(),
+//! ));
+//! let allocator = RawAlloc::new(grower);
+//! ```
+
+use crate::allocators::decay::Advice;
+use crate::allocators::heap_grower::{GrowerStats, HeapGrower};
+use crate::allocators::regions::Regions;
+
+fn round_up(value: usize, increment: usize) -> usize {
+    value.div_ceil(increment) * increment
+}
+
+/// Caps the bytes a grower may hand out at once. Memory given back through
+/// `shrink_heap` can be handed out again.
+///
+/// If the inner grower hands out more than was asked for, e.g. a whole chunk,
+/// only what was asked for is passed on. The rest is given back to it, or,
+/// if it will not take it, kept for later growths.
+pub struct Limit<G> {
+    inner: G,
+    limit: usize,
+    used: usize,
+    // Memory from the inner grower past what was handed out, which it would
+    // not take back.
+    spare: Regions,
+}
+
+/// Why a [`Limit`] did not grow.
+#[derive(Debug)]
+pub enum LimitError<E> {
+    /// Growing by `requested` bytes would have gone past `limit`.
+    Exceeded { requested: usize, used: usize, limit: usize },
+    /// The inner grower failed.
+    Inner(E),
+}
+
+impl<G> Limit<G> {
+    pub const fn new(inner: G, limit: usize) -> Self {
+        Limit {
+            inner,
+            limit,
+            used: 0,
+            spare: Regions::new(),
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Changes the cap. Lowering it below `used` only stops further growth.
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+    }
+
+    /// Bytes handed out and not given back.
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    pub fn inner(&self) -> &G {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut G {
+        &mut self.inner
+    }
+}
+
+impl<G: HeapGrower> HeapGrower for Limit<G> {
+    type Err = LimitError<G::Err>;
+
+    unsafe fn grow_heap(&mut self, size: usize) -> Result<(*mut u8, usize), Self::Err> {
+        // Check what the inner grower will round the request up to, so the
+        // cap is not overshot by a page.
+        let requested = round_up(size, self.inner.page_size());
+        if requested > self.limit.saturating_sub(self.used) {
+            return Err(LimitError::Exceeded {
+                requested,
+                used: self.used,
+                limit: self.limit,
+            });
+        }
+        let fits = self
+            .spare
+            .iter()
+            .find(|range| range.end as usize - range.start as usize >= requested);
+        if let Some(range) = fits {
+            let ptr = range.start as *mut u8;
+            self.spare.remove(ptr, requested);
+            self.used += requested;
+            return Ok((ptr, requested));
+        }
+
+        let (ptr, size) = self.inner.grow_heap(size).map_err(LimitError::Inner)?;
+        if size > requested {
+            let (rest, rest_len) = (ptr.add(requested), size - requested);
+            let kept = rest_len - self.inner.shrink_heap(rest, rest_len);
+            if kept > 0 {
+                self.spare.insert(rest, kept);
+            }
+        }
+        let size = size.min(requested);
+        self.used += size;
+        Ok((ptr, size))
+    }
+
+    unsafe fn shrink_heap(&mut self, ptr: *mut u8, size: usize) -> usize {
+        // Memory just below spare memory can only go back to the inner
+        // grower together with it.
+        if let Some(spare) = self.spare.find(ptr.add(size), 1) {
+            let spare_len = spare.end as usize - spare.start as usize;
+            let released = self.inner.shrink_heap(ptr, size + spare_len);
+            let from_spare = released.min(spare_len);
+            self.spare.remove(spare.end.sub(from_spare), from_spare);
+            let released = released - from_spare;
+            self.used -= released;
+            return released;
+        }
+        let released = self.inner.shrink_heap(ptr, size);
+        self.used -= released;
+        released
+    }
+
+    fn trim_threshold(&self) -> Option<usize> {
+        self.inner.trim_threshold()
+    }
+
+    fn page_size(&self) -> usize {
+        self.inner.page_size()
+    }
+
+    unsafe fn advise(&mut self, ptr: *mut u8, size: usize, advice: Advice) -> bool {
+        self.inner.advise(ptr, size, advice)
+    }
+
+    fn stats(&self) -> GrowerStats {
+        self.inner.stats()
+    }
+}
+
+/// Grows from `A` while it can, and from `B` when `A` fails.
+pub struct Fallback<A, B> {
+    first: A,
+    second: B,
+    // Memory from `second`, so that `shrink_heap` goes to the right grower.
+    from_second: Regions,
+}
+
+/// Both growers of a [`Fallback`] failed.
+#[derive(Debug)]
+pub struct FallbackError<EA, EB> {
+    pub first: EA,
+    /// `None` if the second grower grew, but there was no memory to record
+    /// the growth in, so it was given back.
+    pub second: Option<EB>,
+}
+
+impl<A: Default, B: Default> Default for Fallback<A, B> {
+    fn default() -> Self {
+        Self::new(A::default(), B::default())
+    }
+}
+
+impl<A, B> Fallback<A, B> {
+    pub const fn new(first: A, second: B) -> Self {
+        Fallback {
+            first,
+            second,
+            from_second: Regions::new(),
+        }
+    }
+
+    pub fn first(&self) -> &A {
+        &self.first
+    }
+
+    pub fn first_mut(&mut self) -> &mut A {
+        &mut self.first
+    }
+
+    pub fn second(&self) -> &B {
+        &self.second
+    }
+
+    pub fn second_mut(&mut self) -> &mut B {
+        &mut self.second
+    }
+
+    /// Bytes currently handed out by the second grower.
+    pub fn fallback_bytes(&self) -> usize {
+        self.from_second.total_bytes()
+    }
+}
+
+impl<A: HeapGrower, B: HeapGrower> HeapGrower for Fallback<A, B> {
+    type Err = FallbackError<A::Err, B::Err>;
+
+    unsafe fn grow_heap(&mut self, size: usize) -> Result<(*mut u8, usize), Self::Err> {
+        let first = match self.first.grow_heap(size) {
+            Ok(grown) => return Ok(grown),
+            Err(error) => error,
+        };
+        match self.second.grow_heap(size) {
+            Ok((ptr, size)) => {
+                // Unrecorded, it would be given back to the wrong grower.
+                if !self.from_second.insert(ptr, size) {
+                    self.second.shrink_heap(ptr, size);
+                    return Err(FallbackError { first, second: None });
+                }
+                Ok((ptr, size))
+            }
+            Err(second) => Err(FallbackError {
+                first,
+                second: Some(second),
+            }),
+        }
+    }
+
+    unsafe fn shrink_heap(&mut self, ptr: *mut u8, size: usize) -> usize {
+        if !self.from_second.contains(ptr, size) {
+            return self.first.shrink_heap(ptr, size);
+        }
+        let released = self.second.shrink_heap(ptr, size);
+        self.from_second.remove(ptr.add(size - released), released);
+        released
+    }
+
+    /// The lower of the two thresholds, where both trim.
+    fn trim_threshold(&self) -> Option<usize> {
+        match (self.first.trim_threshold(), self.second.trim_threshold()) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// The larger of the two page sizes, which is a multiple of the other.
+    fn page_size(&self) -> usize {
+        self.first.page_size().max(self.second.page_size())
+    }
+
+    unsafe fn advise(&mut self, ptr: *mut u8, size: usize, advice: Advice) -> bool {
+        if self.from_second.contains(ptr, size) {
+            return self.second.advise(ptr, size, advice);
+        }
+        self.first.advise(ptr, size, advice)
+    }
+
+    /// Both growers' counters, added together.
+    fn stats(&self) -> GrowerStats {
+        let (a, b) = (self.first.stats(), self.second.stats());
+        GrowerStats {
+            attempts: a.attempts + b.attempts,
+            growths: a.growths + b.growths,
+            mapped_bytes: a.mapped_bytes + b.mapped_bytes,
+            peak_bytes: a.peak_bytes + b.peak_bytes,
+            hugetlb_bytes: a.hugetlb_bytes + b.hugetlb_bytes,
+            transparent_huge_bytes: a.transparent_huge_bytes + b.transparent_huge_bytes,
+        }
+    }
+}
+
+/// Counts the calls made to a grower, and the bytes that pass through it.
+#[derive(Default)]
+pub struct Counting<G> {
+    inner: G,
+    calls: usize,
+    failures: usize,
+    requested_bytes: usize,
+    granted_bytes: usize,
+    released_bytes: usize,
+}
+
+impl<G> Counting<G> {
+    pub const fn new(inner: G) -> Self {
+        Counting {
+            inner,
+            calls: 0,
+            failures: 0,
+            requested_bytes: 0,
+            granted_bytes: 0,
+            released_bytes: 0,
+        }
+    }
+
+    /// Calls to `grow_heap`.
+    pub fn calls(&self) -> usize {
+        self.calls
+    }
+
+    /// Calls to `grow_heap` that failed.
+    pub fn failures(&self) -> usize {
+        self.failures
+    }
+
+    /// Bytes asked for by `grow_heap`, whether or not they were granted.
+    pub fn requested_bytes(&self) -> usize {
+        self.requested_bytes
+    }
+
+    /// Bytes handed out by `grow_heap`, including any rounding up.
+    pub fn granted_bytes(&self) -> usize {
+        self.granted_bytes
+    }
+
+    /// Bytes given back through `shrink_heap`.
+    pub fn released_bytes(&self) -> usize {
+        self.released_bytes
+    }
+
+    pub fn inner(&self) -> &G {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut G {
+        &mut self.inner
+    }
+}
+
+impl<G: HeapGrower> HeapGrower for Counting<G> {
+    type Err = G::Err;
+
+    unsafe fn grow_heap(&mut self, size: usize) -> Result<(*mut u8, usize), G::Err> {
+        self.calls += 1;
+        self.requested_bytes += size;
+        let result = self.inner.grow_heap(size);
+        match &result {
+            Ok((_, size)) => self.granted_bytes += size,
+            Err(_) => self.failures += 1,
+        }
+        result
+    }
+
+    unsafe fn shrink_heap(&mut self, ptr: *mut u8, size: usize) -> usize {
+        let released = self.inner.shrink_heap(ptr, size);
+        self.released_bytes += released;
+        released
+    }
+
+    fn trim_threshold(&self) -> Option<usize> {
+        self.inner.trim_threshold()
+    }
+
+    fn page_size(&self) -> usize {
+        self.inner.page_size()
+    }
+
+    unsafe fn advise(&mut self, ptr: *mut u8, size: usize, advice: Advice) -> bool {
+        self.inner.advise(ptr, size, advice)
+    }
+
+    /// The inner grower's stats, with the counts made here, for growers that
+    /// do not keep their own.
+    fn stats(&self) -> GrowerStats {
+        GrowerStats {
+            attempts: self.calls,
+            growths: self.calls - self.failures,
+            ..self.inner.stats()
+        }
+    }
+}
+
+/// The smallest chunk a `Chunked` grows by, by default.
+pub const DEFAULT_MIN_CHUNK: usize = 64 * 1024;
+
+/// The largest chunk a `Chunked` grows by, by default, unless more is asked
+/// for.
+pub const DEFAULT_MAX_CHUNK: usize = 64 * 1024 * 1024;
+
+/// Grows by whole chunks rather than by what each allocation needs, so small
+/// allocations do not each cost a call to the system. Chunks double in size
+/// with each growth, from a minimum up to a maximum, so a heap that keeps
+/// growing needs few growths in all.
+pub struct Chunked<G> {
+    inner: G,
+    next_chunk: usize,
+    max_chunk: usize,
+}
+
+impl<G: Default> Default for Chunked<G> {
+    fn default() -> Self {
+        Self::new(G::default(), DEFAULT_MIN_CHUNK)
+    }
+}
+
+impl<G> Chunked<G> {
+    pub const fn new(inner: G, min_chunk: usize) -> Self {
+        Self::with_max_chunk(inner, min_chunk, DEFAULT_MAX_CHUNK)
+    }
+
+    pub const fn with_max_chunk(inner: G, min_chunk: usize, max_chunk: usize) -> Self {
+        Chunked {
+            inner,
+            next_chunk: min_chunk,
+            max_chunk,
+        }
+    }
+
+    /// The size the next growth will be, unless more is asked for.
+    pub fn next_chunk(&self) -> usize {
+        self.next_chunk
+    }
+
+    pub fn inner(&self) -> &G {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut G {
+        &mut self.inner
+    }
+}
+
+impl<G: HeapGrower> HeapGrower for Chunked<G> {
+    type Err = G::Err;
+
+    unsafe fn grow_heap(&mut self, size: usize) -> Result<(*mut u8, usize), G::Err> {
+        let grown = self.inner.grow_heap(size.max(self.next_chunk))?;
+        self.next_chunk = self.next_chunk.saturating_mul(2).min(self.max_chunk);
+        Ok(grown)
+    }
+
+    unsafe fn shrink_heap(&mut self, ptr: *mut u8, size: usize) -> usize {
+        self.inner.shrink_heap(ptr, size)
+    }
+
+    fn trim_threshold(&self) -> Option<usize> {
+        self.inner.trim_threshold()
+    }
+
+    fn page_size(&self) -> usize {
+        self.inner.page_size()
+    }
+
+    unsafe fn advise(&mut self, ptr: *mut u8, size: usize, advice: Advice) -> bool {
+        self.inner.advise(ptr, size, advice)
+    }
+
+    fn stats(&self) -> GrowerStats {
+        self.inner.stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocators::{EnhancedHeapGrower, RawAlloc, ReservingHeapGrower, StaticHeap, ToyHeap};
+    use core::alloc::Layout;
+    use core::ptr::addr_of_mut;
+    use crate::blocklist::BLOCK_ALIGN;
+    use test_log::test;
+
+    #[test]
+    fn test_limit() {
+        let mut allocator = RawAlloc::new(Counting::new(Limit::new(ToyHeap::default(), 1024)));
+        let layout = Layout::from_size_align(400, BLOCK_ALIGN).unwrap();
+        unsafe {
+            assert!(!allocator.alloc(layout).is_null());
+            assert!(!allocator.alloc(layout).is_null());
+            assert!(allocator.alloc(layout).is_null());
+            let error = allocator.grower.inner_mut().grow_heap(400).err().unwrap();
+            assert!(matches!(
+                error,
+                LimitError::Exceeded {
+                    requested: 448,
+                    used: 896,
+                    limit: 1024
+                }
+            ));
+        }
+        let counting = &allocator.grower;
+        assert_eq!((counting.calls(), counting.failures()), (3, 1));
+        assert_eq!(counting.requested_bytes(), 3 * 448);
+        assert_eq!(counting.granted_bytes(), 2 * 448);
+        let stats = counting.stats();
+        assert_eq!((stats.attempts, stats.growths, stats.mapped_bytes), (3, 2, 896));
+    }
+
+    #[test]
+    fn test_limit_chunked() {
+        // The system grower never gives memory back, so the rest of each
+        // chunk is kept for later.
+        let mut limit = Limit::new(Chunked::new(EnhancedHeapGrower::default(), 1 << 20), 64 << 10);
+        unsafe {
+            let (a, size) = limit.grow_heap(16).unwrap();
+            assert_eq!(size, 4096);
+            assert_eq!(limit.used(), 4096);
+            let (b, size) = limit.grow_heap(8192).unwrap();
+            assert_eq!((b, size), (a.add(4096), 8192));
+            assert!(matches!(
+                limit.grow_heap(60 << 10),
+                Err(LimitError::Exceeded {
+                    requested: 0xf000,
+                    used: 0x3000,
+                    limit: 0x10000
+                })
+            ));
+        }
+        assert_eq!(limit.used(), 12 << 10);
+        assert_eq!(limit.inner().next_chunk(), 2 << 20);
+
+        // One that does takes it back at once.
+        let reserving = ReservingHeapGrower::new(4 << 20);
+        let mut limit = Limit::new(Chunked::new(reserving, 1 << 20), 64 << 10);
+        unsafe {
+            let (a, size) = limit.grow_heap(16).unwrap();
+            assert_eq!(size, 4096);
+            assert_eq!(limit.inner().inner().committed_bytes(), 4096);
+            let (b, _) = limit.grow_heap(8192).unwrap();
+            assert_eq!(b, a.add(4096));
+            assert_eq!(limit.shrink_heap(b, 8192), 8192);
+        }
+        assert_eq!(limit.used(), 4096);
+        assert_eq!(limit.inner().inner().committed_bytes(), 4096);
+    }
+
+    #[test]
+    fn test_limit_spare() {
+        // Spare memory too small for one growth is kept for later ones.
+        let mut limit = Limit::new(Chunked::new(EnhancedHeapGrower::default(), 1 << 20), 4 << 20);
+        unsafe {
+            limit.grow_heap(16).unwrap();
+            let (_, size) = limit.grow_heap(1 << 20).unwrap();
+            assert_eq!(size, 1 << 20);
+            assert_eq!(limit.inner().next_chunk(), 4 << 20);
+            // What is left of both chunks.
+            limit.grow_heap((1 << 20) - 4096).unwrap();
+            limit.grow_heap((1 << 20) - 4096).unwrap();
+        }
+        assert_eq!(limit.inner().next_chunk(), 4 << 20);
+        assert_eq!(limit.used(), (3 << 20) - 4096);
+    }
+
+    #[test]
+    fn test_fallback() {
+        #[repr(align(16))]
+        struct Spare([u8; 1024]);
+        static mut SPARE: Spare = Spare([0; 1024]);
+        let spare = StaticHeap::new(unsafe { &mut (*addr_of_mut!(SPARE)).0 });
+        let mut allocator = RawAlloc::new(Fallback::new(Limit::new(ToyHeap::default(), 512), spare));
+        let layout = Layout::from_size_align(400, BLOCK_ALIGN).unwrap();
+        unsafe {
+            let a = allocator.alloc(layout);
+            let b = allocator.alloc(layout);
+            let c = allocator.alloc(layout);
+            assert!(!a.is_null() && !b.is_null() && !c.is_null());
+            assert_eq!(b, addr_of_mut!(SPARE) as *mut u8);
+            assert_eq!(allocator.grower.fallback_bytes(), 2 * 448);
+            assert!(allocator.alloc(layout).is_null());
+
+            let error = allocator.grower.grow_heap(400).err().unwrap();
+            assert!(matches!(error.first, LimitError::Exceeded { .. }));
+            for ptr in [a, b, c] {
+                allocator.dealloc(ptr, layout);
+            }
+            assert!(allocator.verify().is_valid(), "{}", allocator.verify());
+        }
+    }
+
+    #[test]
+    fn test_chunked() {
+        let mut allocator = RawAlloc::new(Counting::new(Chunked::with_max_chunk(ToyHeap::default(), 1024, 4096)));
+        let layout = Layout::from_size_align(256, BLOCK_ALIGN).unwrap();
+        let mut growths = [0; 5];
+        unsafe {
+            for n in &mut growths {
+                // Fill what the last growth gave, then grow once more.
+                while allocator.grower.calls() == 0 || !allocator.blocks.is_empty() {
+                    assert!(!allocator.alloc(layout).is_null());
+                }
+                assert!(!allocator.alloc(layout).is_null());
+                *n = allocator.grower.granted_bytes();
+            }
+        }
+        assert_eq!(growths, [1024 + 2048, 3072 + 4096, 7168 + 4096, 11264 + 4096, 15360 + 4096]);
+    }
+}
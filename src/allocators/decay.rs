@@ -0,0 +1,391 @@
+use crate::allocators::heap_grower::HeapGrower;
+use crate::blocklist::BlockList;
+
+// Free memory is expected to be in few, large runs of pages; pages that do not
+// fit are picked up again at a later step.
+const MAX_SPANS: usize = 64;
+
+/// What to tell the system about free pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Advice {
+    /// The system may reclaim the pages if it runs short of memory; until
+    /// then they stay mapped and cost nothing to reuse (`MADV_FREE`).
+    Free,
+    /// The system reclaims the pages now; they read as zeroes afterwards
+    /// (`MADV_DONTNEED`).
+    DontNeed,
+}
+
+/// How long free pages wait before each stage of purging, in ticks. A tick
+/// is an allocation or a free.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecayDelays {
+    /// Ticks a free page stays dirty before it is advised with
+    /// `Advice::Free`, and becomes muzzy.
+    pub dirty_ticks: u64,
+    /// Ticks a page stays muzzy before it is advised with
+    /// `Advice::DontNeed`, and is released.
+    pub muzzy_ticks: u64,
+}
+
+impl DecayDelays {
+    // Pages move on at most a quarter of a delay late.
+    fn step_ticks(&self) -> u64 {
+        (self.dirty_ticks.min(self.muzzy_ticks) / 4).max(1)
+    }
+}
+
+/// Whole pages of free memory, by how far they have been purged, as of the
+/// last decay step. Pages freed since then are not counted until the next.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DecayStats {
+    /// Free pages still backed by memory.
+    pub dirty_bytes: usize,
+    /// Free pages the system may reclaim when it needs to.
+    pub muzzy_bytes: usize,
+    /// Free pages given back to the system.
+    pub released_bytes: usize,
+}
+
+impl DecayStats {
+    fn bytes_mut(&mut self, state: State) -> &mut usize {
+        match state {
+            State::Dirty => &mut self.dirty_bytes,
+            State::Muzzy => &mut self.muzzy_bytes,
+            State::Released => &mut self.released_bytes,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Dirty,
+    Muzzy,
+    Released,
+}
+
+/// A run of free pages in one state, since the tick it got there.
+#[derive(Debug, Clone, Copy)]
+struct Span {
+    start: usize,
+    end: usize,
+    state: State,
+    since: u64,
+}
+
+const EMPTY_SPAN: Span = Span {
+    start: 0,
+    end: 0,
+    state: State::Dirty,
+    since: 0,
+};
+
+#[derive(Debug, Clone, Copy)]
+struct Spans {
+    spans: [Span; MAX_SPANS],
+    len: usize,
+}
+
+impl Spans {
+    const fn new() -> Self {
+        Spans {
+            spans: [EMPTY_SPAN; MAX_SPANS],
+            len: 0,
+        }
+    }
+
+    fn as_slice(&self) -> &[Span] {
+        &self.spans[..self.len]
+    }
+
+    /// Appends `span`, which must come after every span so far, merging it
+    /// into the last one if it continues it in the same state, since the same
+    /// tick. Returns false if there is no room for it.
+    fn push(&mut self, span: Span) -> bool {
+        if let Some(last) = self.spans[..self.len].last_mut() {
+            if last.end == span.start && (last.state, last.since) == (span.state, span.since) {
+                last.end = span.end;
+                return true;
+            }
+        }
+        if self.len == MAX_SPANS {
+            return false;
+        }
+        self.spans[self.len] = span;
+        self.len += 1;
+        true
+    }
+}
+
+/// jemalloc-style decay of free memory. Whole pages inside free blocks start
+/// out dirty; once they have stayed free for `dirty_ticks`, they are advised
+/// with `MADV_FREE` and become muzzy, and after another `muzzy_ticks`, with
+/// `MADV_DONTNEED`, and are released. Pages that are reused go back to
+/// being ordinary memory.
+///
+/// There is no background thread: the work is done on allocations and frees,
+/// every few ticks, or all at once by `RawAlloc::purge`. Only growers that
+/// implement `HeapGrower::advise` have their pages purged.
+#[derive(Debug)]
+pub struct Decay {
+    delays: Option<DecayDelays>,
+    ticks: u64,
+    next_step: u64,
+    spans: Spans,
+    stats: DecayStats,
+}
+
+impl Default for Decay {
+    fn default() -> Self {
+        Self::disabled()
+    }
+}
+
+impl Decay {
+    /// Decay that never runs; free pages stay dirty until `RawAlloc::purge`.
+    pub const fn disabled() -> Self {
+        Decay {
+            delays: None,
+            ticks: 0,
+            next_step: 0,
+            spans: Spans::new(),
+            stats: DecayStats {
+                dirty_bytes: 0,
+                muzzy_bytes: 0,
+                released_bytes: 0,
+            },
+        }
+    }
+
+    pub fn delays(&self) -> Option<DecayDelays> {
+        self.delays
+    }
+
+    /// Sets the delays, or turns decay off. The next tick runs a step.
+    pub fn set_delays(&mut self, delays: Option<DecayDelays>) {
+        self.delays = delays;
+        self.next_step = self.ticks;
+    }
+
+    /// Allocations and frees seen while decay was on.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    pub fn stats(&self) -> DecayStats {
+        self.stats
+    }
+
+    /// Counts a tick, returning whether a step is due.
+    #[inline(always)]
+    pub(crate) fn tick(&mut self) -> bool {
+        let Some(delays) = self.delays else {
+            return false;
+        };
+        self.ticks += 1;
+        if self.ticks < self.next_step {
+            return false;
+        }
+        self.next_step = self.ticks + delays.step_ticks();
+        true
+    }
+
+    /// Stops tracking the pages in `[start, end)`, which are about to be
+    /// written to or given back to the system.
+    pub(crate) fn forget(&mut self, start: usize, end: usize) {
+        let overlaps = |s: &Span| s.start < end && start < s.end;
+        if !self.spans.as_slice().iter().any(overlaps) {
+            return;
+        }
+        let old = self.spans;
+        self.spans = Spans::new();
+        for &span in old.as_slice() {
+            if !overlaps(&span) {
+                self.spans.push(span);
+                continue;
+            }
+            let (lo, hi) = (span.start.max(start), span.end.min(end));
+            *self.stats.bytes_mut(span.state) -= hi - lo;
+            for piece in [Span { end: lo, ..span }, Span { start: hi, ..span }] {
+                if piece.start < piece.end && !self.spans.push(piece) {
+                    *self.stats.bytes_mut(span.state) -= piece.end - piece.start;
+                }
+            }
+        }
+    }
+
+    /// The pages advised away, and so no longer backed by memory for sure.
+    pub(crate) fn purged(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.spans
+            .as_slice()
+            .iter()
+            .filter(|span| span.state != State::Dirty)
+            .map(|span| (span.start, span.end))
+    }
+
+    /// Moves the free pages in `blocks` along, and returns the new stats.
+    /// With `force`, every free page is released at once, whatever the
+    /// delays.
+    ///
+    /// # Safety
+    ///
+    /// `blocks` must be the free list of memory from `grower`.
+    pub(crate) unsafe fn step<G: HeapGrower>(&mut self, blocks: &BlockList, grower: &mut G, force: bool) -> DecayStats {
+        let page = grower.page_size();
+        let now = self.ticks;
+        let old = self.spans;
+        let mut old = old.as_slice().iter().peekable();
+        self.spans = Spans::new();
+        self.stats = DecayStats::default();
+
+        for block in blocks.iter() {
+            // The page holding the header stays, as the header is still
+            // needed.
+            let range = block.as_range();
+            let start = (range.start as usize + BlockList::header_size()).next_multiple_of(page);
+            let end = range.end as usize / page * page;
+            if start >= end {
+                continue;
+            }
+
+            // Both lists are sorted, so the spans seen at the last step that
+            // overlap this block come next. Anything else is newly dirty.
+            let mut at = start;
+            while let Some(&&span) = old.peek() {
+                if span.start >= end {
+                    break;
+                }
+                if span.end > at {
+                    let lo = span.start.max(at);
+                    if at < lo {
+                        self.settle(grower, Span { start: at, end: lo, state: State::Dirty, since: now }, force);
+                    }
+                    let hi = span.end.min(end);
+                    self.settle(grower, Span { start: lo, end: hi, ..span }, force);
+                    at = hi;
+                }
+                if span.end > end {
+                    break;
+                }
+                old.next();
+            }
+            if at < end {
+                self.settle(grower, Span { start: at, end, state: State::Dirty, since: now }, force);
+            }
+        }
+        self.stats
+    }
+
+    /// Advances `span` as far as its delays allow, and records it.
+    unsafe fn settle<G: HeapGrower>(&mut self, grower: &mut G, mut span: Span, force: bool) {
+        let delays = self.delays.unwrap_or(DecayDelays {
+            dirty_ticks: u64::MAX,
+            muzzy_ticks: u64::MAX,
+        });
+        let now = self.ticks;
+        let ptr = span.start as *mut u8;
+        let len = span.end - span.start;
+
+        if span.state == State::Dirty && (force || now - span.since >= delays.dirty_ticks) {
+            if !force && grower.advise(ptr, len, Advice::Free) {
+                span.state = State::Muzzy;
+            } else if grower.advise(ptr, len, Advice::DontNeed) {
+                // Without MADV_FREE, pages are released directly.
+                span.state = State::Released;
+            }
+            span.since = now;
+        }
+        if span.state == State::Muzzy
+            && (force || now - span.since >= delays.muzzy_ticks)
+            && grower.advise(ptr, len, Advice::DontNeed)
+        {
+            span.state = State::Released;
+            span.since = now;
+        }
+
+        // Pages that are not tracked are still counted, and are picked up as
+        // newly dirty at the next step.
+        self.spans.push(span);
+        *self.stats.bytes_mut(span.state) += len;
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use crate::allocators::{EnhancedHeapGrower, RawAlloc};
+    use crate::blocklist::BLOCK_ALIGN;
+    use core::alloc::Layout;
+    use test_log::test;
+
+    /// A heap with one free 16-page block, and the page after it in use.
+    unsafe fn heap_with_free_block() -> (RawAlloc<EnhancedHeapGrower>, Layout, usize) {
+        let mut allocator = RawAlloc::new(EnhancedHeapGrower::default());
+        let page = allocator.grower.page_size();
+        // Both grow the heap by exactly their size, so start on a page.
+        let layout = Layout::from_size_align(16 * page, BLOCK_ALIGN).unwrap();
+        let block = allocator.alloc(layout);
+        // A whole page, so it leaves no free tail for the block to merge with.
+        allocator.alloc(Layout::from_size_align(page, BLOCK_ALIGN).unwrap());
+        allocator.dealloc(block, layout);
+        (allocator, layout, page)
+    }
+
+    #[test]
+    fn test_decay() {
+        let small = Layout::from_size_align(64, BLOCK_ALIGN).unwrap();
+        unsafe {
+            let (mut allocator, layout, page) = heap_with_free_block();
+            allocator.decay.set_delays(Some(DecayDelays {
+                dirty_ticks: 8,
+                muzzy_ticks: 8,
+            }));
+            let mut ticks = |allocator: &mut RawAlloc<EnhancedHeapGrower>, n: u64| {
+                for _ in 0..n / 2 {
+                    let ptr = allocator.alloc(small);
+                    allocator.dealloc(ptr, small);
+                }
+            };
+            // All but the page holding the header, and the last page, which
+            // the small allocations are split from.
+            let interior = 14 * page;
+
+            ticks(&mut allocator, 2);
+            let stats = allocator.metrics().decay;
+            assert_eq!((stats.dirty_bytes, stats.muzzy_bytes), (interior, 0));
+
+            ticks(&mut allocator, 8);
+            let stats = allocator.metrics().decay;
+            assert_eq!((stats.dirty_bytes, stats.muzzy_bytes), (0, interior));
+
+            ticks(&mut allocator, 8);
+            let stats = allocator.metrics().decay;
+            assert_eq!((stats.muzzy_bytes, stats.released_bytes), (0, interior));
+            assert_eq!(allocator.decay.ticks(), 18);
+
+            // Reusing the pages stops them being counted.
+            let ptr = allocator.alloc(layout);
+            ptr.write_bytes(0x5A, layout.size());
+            assert_eq!(allocator.metrics().decay, DecayStats::default());
+            allocator.dealloc(ptr, layout);
+            assert!(allocator.verify().is_valid(), "{}", allocator.verify());
+        }
+    }
+
+    #[test]
+    fn test_purge() {
+        unsafe {
+            let (mut allocator, layout, page) = heap_with_free_block();
+            assert_eq!(allocator.decay.delays(), None);
+            let stats = allocator.purge();
+            assert_eq!(stats.released_bytes, 15 * page);
+            assert_eq!(stats.dirty_bytes + stats.muzzy_bytes, 0);
+
+            // Released pages read as zeroes, and can be used again.
+            let ptr = allocator.alloc(layout);
+            assert_eq!(*ptr.add(8 * page), 0);
+            ptr.write_bytes(0x5A, layout.size());
+            assert_eq!(allocator.metrics().decay, DecayStats::default());
+        }
+    }
+}
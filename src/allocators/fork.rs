@@ -0,0 +1,139 @@
+//! Keeping allocators usable in the child of a `fork`.
+//!
+//! `fork` copies only the thread that calls it. Had another thread been inside
+//! an allocator at that moment, the child would inherit the allocator's lock
+//! held by a thread that no longer exists, and its heap half-updated.
+//!
+//! With the `std` feature, every `GenericAllocator` operation holds a `Pass`
+//! through a process-wide gate, counted on one of several cache lines picked
+//! by the allocator's address, so arenas do not all contend on one. Handlers
+//! registered with `pthread_atfork` the first time an allocator is used close
+//! the gate before `fork`, waiting for operations in progress to finish, and
+//! open it again after, in both the parent and the child. Without it,
+//! `GenericAllocator::prepare_fork` and its counterparts do the same for a
+//! single allocator, and must be registered by hand.
+
+#[cfg(feature = "std")]
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+// Each stripe counts the passes held through it, with the top bit set while
+// the gate is closed. Stripe 0 is closed first and opened last, so it also
+// keeps two forking threads apart.
+#[cfg(feature = "std")]
+static GATE: [Stripe; STRIPES] = [const { Stripe(AtomicUsize::new(0)) }; STRIPES];
+
+#[cfg(feature = "std")]
+const STRIPES: usize = 64;
+
+#[cfg(feature = "std")]
+#[repr(align(64))]
+struct Stripe(AtomicUsize);
+
+#[cfg(feature = "std")]
+const CLOSED: usize = 1 << (usize::BITS - 1);
+
+#[cfg(feature = "std")]
+static REGISTERED: AtomicBool = AtomicBool::new(false);
+
+/// Permission to use an allocator, held for the length of an operation. Without
+/// the `std` feature it costs nothing.
+pub(crate) struct Pass {
+    #[cfg(feature = "std")]
+    stripe: &'static AtomicUsize,
+}
+
+impl Pass {
+    /// Waits for the gate to be open, and goes through on behalf of the
+    /// allocator at `addr`.
+    #[inline(always)]
+    pub(crate) fn enter(addr: usize) -> Self {
+        #[cfg(feature = "std")]
+        {
+            // Allocators are 64-byte aligned, and arenas page-aligned.
+            let hash = ((addr >> 6) as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+            let stripe = &GATE[(hash >> (64 - STRIPES.trailing_zeros())) as usize].0;
+            while stripe.fetch_add(1, Ordering::Acquire) & CLOSED != 0 {
+                stripe.fetch_sub(1, Ordering::Relaxed);
+                while stripe.load(Ordering::Relaxed) & CLOSED != 0 {
+                    core::hint::spin_loop();
+                }
+            }
+            Pass { stripe }
+        }
+
+        #[cfg(not(feature = "std"))]
+        {
+            let _ = addr;
+            Pass {}
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Drop for Pass {
+    #[inline(always)]
+    fn drop(&mut self) {
+        self.stripe.fetch_sub(1, Ordering::Release);
+    }
+}
+
+/// Closes the gate, and waits for every pass to be given back.
+#[cfg(feature = "std")]
+pub(crate) fn close() {
+    // Another thread may be forking too; it opens the gate when it is done.
+    while GATE[0]
+        .0
+        .fetch_update(Ordering::Acquire, Ordering::Relaxed, |gate| {
+            (gate & CLOSED == 0).then_some(gate | CLOSED)
+        })
+        .is_err()
+    {
+        core::hint::spin_loop();
+    }
+    for stripe in &GATE[1..] {
+        stripe.0.fetch_or(CLOSED, Ordering::Acquire);
+    }
+    for stripe in &GATE {
+        while stripe.0.load(Ordering::Acquire) != CLOSED {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Opens the gate after `close`. Threads turned away meanwhile may still be
+/// backing out, so only the bit is cleared.
+#[cfg(feature = "std")]
+pub(crate) fn open() {
+    for stripe in GATE.iter().rev() {
+        stripe.0.fetch_and(!CLOSED, Ordering::Release);
+    }
+}
+
+/// Opens the gate in a child, which has no other threads to back out.
+#[cfg(feature = "std")]
+pub(crate) fn reset() {
+    for stripe in &GATE {
+        stripe.0.store(0, Ordering::Release);
+    }
+}
+
+/// Registers the gate with `pthread_atfork`, once per process.
+#[cfg(feature = "std")]
+#[inline(always)]
+pub(crate) fn register() {
+    if REGISTERED.load(Ordering::Relaxed) || REGISTERED.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    extern "C" fn prepare() {
+        close();
+    }
+    extern "C" fn parent() {
+        open();
+    }
+    extern "C" fn child() {
+        reset();
+    }
+    // pthread_atfork may allocate, through this allocator; the gate is open
+    // and no lock is held, so that is fine.
+    unsafe { libc::pthread_atfork(Some(prepare), Some(parent), Some(child)) };
+}
@@ -1,42 +1,111 @@
+use crate::allocators::alloc_error::AllocError;
+use crate::allocators::budget::{Budget, OomAction, OomEvent, OomHandler, SoftLimitHandler};
+use crate::allocators::decay::{Decay, DecayDelays, DecayStats};
+#[cfg(feature = "std")]
+use crate::allocators::fork;
+use crate::allocators::fork::Pass;
 use crate::allocators::raw_alloc::RawAlloc;
-use crate::allocators::HeapGrower;
+use crate::allocators::{HeapReport, HeapGrower};
 use crate::blocklist::{Stats, Validity};
+use crate::metrics::AllocatorMetrics;
 use core::mem::MaybeUninit;
+use core::ptr::{null_mut, NonNull};
 use core::sync::atomic::{AtomicU8, Ordering};
-use core::alloc::Layout;
+use core::alloc::{GlobalAlloc, Layout};
+use spin::{Mutex, MutexGuard};
 
 #[repr(align(64))]
 pub struct GenericAllocator<G: HeapGrower + Default> {
     init: AtomicU8,
+    lock: Mutex<()>,
     raw: MaybeUninit<RawAlloc<G>>,
 }
 
-pub struct AllocGuard<'a, G: HeapGrower + Default>(&'a mut RawAlloc<G>);
+/// Exclusive access to the heap; the lock is released when it is dropped.
+pub struct AllocGuard<'a, G: HeapGrower + Default> {
+    raw: &'a mut RawAlloc<G>,
+    _lock: MutexGuard<'a, ()>,
+    // Given back after the lock.
+    _pass: Pass,
+}
 
 impl<'a, G: HeapGrower + Default> AllocGuard<'a, G> {
+    /// The grower, e.g. to donate more memory to a `StaticHeap`.
+    #[inline(always)]
+    pub fn grower(&mut self) -> &mut G {
+        &mut self.raw.grower
+    }
+
+    /// The heap's limits. Handlers registered here are only called by
+    /// `GenericAllocator`'s own `GlobalAlloc` methods, not through the guard.
+    #[inline(always)]
+    pub fn budget(&mut self) -> &mut Budget {
+        &mut self.raw.budget
+    }
+
+    #[inline(always)]
+    pub fn decay(&mut self) -> &mut Decay {
+        &mut self.raw.decay
+    }
+
+    #[inline(always)]
+    pub fn purge(&mut self) -> DecayStats {
+        self.raw.purge()
+    }
+
+    #[inline(always)]
+    pub fn reserve(&mut self, bytes: usize) -> Result<usize, AllocError> {
+        self.raw.reserve(bytes)
+    }
+
     #[inline(always)]
     pub fn stats(&self) -> (Validity, Stats) {
-        self.0.stats()
+        self.raw.stats()
+    }
+
+    #[inline(always)]
+    pub fn verify(&self) -> HeapReport {
+        self.raw.verify()
+    }
+
+    #[inline(always)]
+    pub fn metrics(&self) -> AllocatorMetrics {
+        self.raw.metrics()
     }
 
     #[inline(always)]
     pub unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
-        self.0.alloc(layout)
+        self.raw.alloc(layout)
+    }
+
+    #[inline(always)]
+    pub unsafe fn try_alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.raw.try_alloc(layout)
     }
 
     #[inline(always)]
     pub unsafe fn calloc(&mut self, layout: Layout) -> *mut u8 {
-        self.0.calloc(layout)
+        self.raw.calloc(layout)
     }
 
     #[inline(always)]
     pub unsafe fn realloc(&mut self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
-        self.0.realloc(ptr, layout, new_size)
+        self.raw.realloc(ptr, layout, new_size)
+    }
+
+    #[inline(always)]
+    pub unsafe fn try_realloc(
+        &mut self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<NonNull<u8>, AllocError> {
+        self.raw.try_realloc(ptr, layout, new_size)
     }
 
     #[inline(always)]
     pub unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
-        self.0.dealloc(ptr, layout)
+        self.raw.dealloc(ptr, layout)
     }
 }
 
@@ -45,33 +114,239 @@ impl<G: HeapGrower + Default> GenericAllocator<G> {
     pub const fn new() -> Self {
         Self {
             init: AtomicU8::new(0),
+            lock: Mutex::new(()),
             raw: MaybeUninit::uninit(),
         }
     }
 
+    /// An allocator that is already initialized with `grower`, rather than
+    /// lazily with `G::default()`. Usable in a `static`.
+    #[inline(always)]
+    pub const fn with_grower(grower: G) -> Self {
+        Self {
+            init: AtomicU8::new(2),
+            lock: Mutex::new(()),
+            raw: MaybeUninit::new(RawAlloc::new(grower)),
+        }
+    }
+
+    /// The heap, without locking, as nothing else can reach it; initialized
+    /// first if need be.
+    pub fn get_mut(&mut self) -> &mut RawAlloc<G> {
+        if *self.init.get_mut() != 2 {
+            self.raw.write(RawAlloc::default());
+            *self.init.get_mut() = 2;
+        }
+        unsafe { self.raw.assume_init_mut() }
+    }
+
+    /// Whether `[ptr, ptr + size)` is in memory this allocator got from its
+    /// grower. An allocator that has not been used owns nothing, and is not
+    /// initialized by asking.
+    pub fn owns(&self, ptr: *const u8, size: usize) -> bool {
+        self.init.load(Ordering::Acquire) == 2 && unsafe { self.get_raw().raw.regions.contains(ptr, size) }
+    }
+
+    /// Frees `ptr` if it is in memory this allocator got from its grower,
+    /// and returns whether it was.
+    ///
+    /// # Safety
+    ///
+    /// As for `GlobalAlloc::dealloc`, were `ptr` from this allocator.
+    pub unsafe fn try_dealloc(&self, ptr: *mut u8, layout: Layout) -> bool {
+        if self.init.load(Ordering::Acquire) != 2 {
+            return false;
+        }
+        let mut raw = self.get_raw();
+        if !raw.raw.regions.contains(ptr, RawAlloc::<G>::block_size(layout)) {
+            return false;
+        }
+        raw.dealloc(ptr, layout);
+        true
+    }
+
     #[inline(always)]
     pub fn stats(&self) -> (Validity, Stats) {
         unsafe { self.get_raw().stats() }
     }
 
+    #[inline(always)]
+    pub fn verify(&self) -> HeapReport {
+        unsafe { self.get_raw().verify() }
+    }
+
+    #[inline(always)]
+    pub fn metrics(&self) -> AllocatorMetrics {
+        unsafe { self.get_raw().metrics() }
+    }
+
+    /// Sets the most live bytes the heap may hand out, or removes the limit.
+    pub fn set_hard_limit(&self, limit: Option<usize>) {
+        unsafe { self.get_raw().budget().set_hard_limit(limit) }
+    }
+
+    /// Sets the live bytes past which `handler` is called.
+    pub fn set_soft_limit(&self, limit: Option<usize>, handler: Option<SoftLimitHandler>) {
+        let mut raw = unsafe { self.get_raw() };
+        raw.budget().set_soft_limit(limit);
+        raw.budget().set_soft_limit_handler(handler);
+    }
+
+    /// Sets the handler called, with the heap unlocked, before an allocation
+    /// returns null.
+    pub fn set_oom_handler(&self, handler: Option<OomHandler>) {
+        unsafe { self.get_raw().budget().set_oom_handler(handler) }
+    }
+
+    /// Sets how long free pages wait before they are purged, or turns
+    /// purging off.
+    pub fn set_decay(&self, delays: Option<DecayDelays>) {
+        unsafe { self.get_raw().decay().set_delays(delays) }
+    }
+
+    /// Releases every whole free page to the system now.
+    pub fn purge(&self) -> DecayStats {
+        unsafe { self.get_raw().purge() }
+    }
+
+    /// Warms the heap up with at least `bytes` of free memory, faulted in; see
+    /// `RawAlloc::reserve`.
+    pub fn reserve(&self, bytes: usize) -> Result<usize, AllocError> {
+        unsafe { self.get_raw().reserve(bytes) }
+    }
+
+    /// Gets the heap ready for `fork`: waits for operations in progress to
+    /// finish, and holds off new ones until `after_fork_parent` or
+    /// `after_fork_child`.
+    ///
+    /// With the `std` feature, this is done for every allocator in the
+    /// process at once, by handlers registered with `pthread_atfork` the first
+    /// time one is used, and these do nothing: closing the gate a second time
+    /// would leave those handlers waiting on it forever. Without it, they are
+    /// meant to be registered by hand, e.g. for a `static` allocator:
+    ///
+    /// ```rust,ignore
+    /// extern "C" fn prepare() { ALLOCATOR.prepare_fork() }
+    /// extern "C" fn parent() { unsafe { ALLOCATOR.after_fork_parent() } }
+    /// extern "C" fn child() { unsafe { ALLOCATOR.after_fork_child() } }
+    /// unsafe { libc::pthread_atfork(Some(prepare), Some(parent), Some(child)) };
+    /// ```
+    pub fn prepare_fork(&self) {
+        #[cfg(not(feature = "std"))]
+        core::mem::forget(unsafe { self.get_raw() });
+    }
+
+    /// Lets operations continue in the parent after `fork`.
+    ///
+    /// # Safety
+    ///
+    /// Must follow `prepare_fork`, in the thread that called it.
+    pub unsafe fn after_fork_parent(&self) {
+        #[cfg(not(feature = "std"))]
+        self.lock.force_unlock();
+    }
+
+    /// Lets operations continue in the child after `fork`. The heap is as the
+    /// forking thread left it, which was between operations.
+    ///
+    /// # Safety
+    ///
+    /// Must follow `prepare_fork`, in the child.
+    pub unsafe fn after_fork_child(&self) {
+        #[cfg(not(feature = "std"))]
+        self.lock.force_unlock();
+    }
+
+    /// Like `GlobalAlloc::alloc`, OOM handler included, but says why the
+    /// allocation failed.
+    ///
+    /// # Safety
+    ///
+    /// As for `GlobalAlloc::alloc`.
+    #[inline(always)]
+    pub unsafe fn try_alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.with_budget(layout, |raw| raw.try_alloc(layout))
+    }
+
+    /// Like `GlobalAlloc::realloc`, OOM handler included, but says why the
+    /// allocation failed. The old allocation is untouched on failure.
+    ///
+    /// # Safety
+    ///
+    /// As for `GlobalAlloc::realloc`.
+    #[inline(always)]
+    pub unsafe fn try_realloc(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<NonNull<u8>, AllocError> {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        self.with_budget(new_layout, |raw| raw.try_realloc(ptr, layout, new_size))
+    }
+
+    /// Runs `op` on the heap, then the budget's handlers with the heap
+    /// unlocked, retrying `op` for as long as the OOM handler asks.
+    #[inline(always)]
+    unsafe fn with_budget(
+        &self,
+        layout: Layout,
+        mut op: impl FnMut(&mut AllocGuard<G>) -> Result<NonNull<u8>, AllocError>,
+    ) -> Result<NonNull<u8>, AllocError> {
+        let mut attempt = 0;
+        loop {
+            let mut raw = self.get_raw();
+            let result = op(&mut raw);
+            let live_bytes = raw.raw.live_bytes();
+            let budget = raw.budget();
+            let crossing = budget.take_soft_limit_crossing();
+            let hard_limit = budget.hard_limit();
+            let oom_handler = budget.oom_handler();
+            drop(raw);
+
+            if let Some((handler, limit)) = crossing {
+                handler(live_bytes, limit);
+            }
+            let (Err(error), Some(handler)) = (result, oom_handler) else {
+                return result;
+            };
+            attempt += 1;
+            let event = OomEvent {
+                layout,
+                live_bytes,
+                hard_limit,
+                attempt,
+                error,
+            };
+            if handler(&event) == OomAction::Fail {
+                return result;
+            }
+        }
+    }
+
     #[inline(always)]
     pub unsafe fn get_raw(&self) -> AllocGuard<G> {
+        #[cfg(feature = "std")]
+        fork::register();
+        // Taken before initializing, so a fork cannot catch that half done.
+        let pass = Pass::enter(self as *const Self as usize);
+
         // Fast path: Check initialization state.
         let state = self.init.load(Ordering::Relaxed);
         if state == 2 {
-            return AllocGuard(&mut *(self.raw.as_ptr() as *mut RawAlloc<G>));
+            return self.lock_raw(pass);
         }
-        self.ensure_initialized()
+        self.ensure_initialized(pass)
     }
 
     #[inline(always)]
-    unsafe fn ensure_initialized(&self) -> AllocGuard<G> {
+    unsafe fn ensure_initialized(&self, pass: Pass) -> AllocGuard<G> {
         // Attempt initialization.
         if self.init.compare_exchange(0, 1, Ordering::Acquire, Ordering::Relaxed).is_ok() {
             let raw_ptr = self.raw.as_ptr() as *mut RawAlloc<G>;
             raw_ptr.write(RawAlloc::default());
             self.init.store(2, Ordering::Release);
-            return AllocGuard(&mut *raw_ptr);
+            return self.lock_raw(pass);
         }
 
         // Wait for another thread to finish initialization.
@@ -79,7 +354,7 @@ impl<G: HeapGrower + Default> GenericAllocator<G> {
         loop {
             if self.init.load(Ordering::Acquire) == 2 {
                 // Initialization complete.
-                return AllocGuard(&mut *(self.raw.as_ptr() as *mut RawAlloc<G>));
+                return self.lock_raw(pass);
             }
 
             // Cooperative waiting with dynamic step size based on backoff
@@ -90,6 +365,16 @@ impl<G: HeapGrower + Default> GenericAllocator<G> {
 
         }
     }
+
+    #[inline(always)]
+    unsafe fn lock_raw(&self, pass: Pass) -> AllocGuard<'_, G> {
+        let lock = self.lock.lock();
+        AllocGuard {
+            raw: &mut *(self.raw.as_ptr() as *mut RawAlloc<G>),
+            _lock: lock,
+            _pass: pass,
+        }
+    }
 }
 
 impl<G: HeapGrower + Default> Default for GenericAllocator<G> {
@@ -100,4 +385,28 @@ impl<G: HeapGrower + Default> Default for GenericAllocator<G> {
 }
 
 unsafe impl<G: HeapGrower + Default> Send for GenericAllocator<G> {}
-unsafe impl<G: HeapGrower + Default> Sync for GenericAllocator<G> {}
\ No newline at end of file
+unsafe impl<G: HeapGrower + Default> Sync for GenericAllocator<G> {}
+
+unsafe impl<G: HeapGrower + Default> GlobalAlloc for GenericAllocator<G> {
+    #[inline(always)]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.try_alloc(layout).map_or(null_mut(), NonNull::as_ptr)
+    }
+    #[inline(always)]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let result = self.with_budget(layout, |raw| {
+            let ptr = raw.try_alloc(layout)?;
+            ptr.as_ptr().write_bytes(0, layout.size());
+            Ok(ptr)
+        });
+        result.map_or(null_mut(), NonNull::as_ptr)
+    }
+    #[inline(always)]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.try_realloc(ptr, layout, new_size).map_or(null_mut(), NonNull::as_ptr)
+    }
+    #[inline(always)]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.get_raw().dealloc(ptr, layout)
+    }
+}
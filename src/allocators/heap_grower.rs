@@ -1,110 +1,161 @@
 use core::ptr::null_mut;
 use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
 
-#[cfg(feature = "use_libc")]
-use errno::Errno;
-
-#[cfg(not(feature = "use_libc"))]
-use crate::mmap::{self, MmapError};
+use crate::allocators::alloc_error::AllocError;
+#[cfg(not(target_os = "none"))]
+use crate::allocators::backend::DefaultBackend;
+use crate::allocators::backend::{self, MemoryBackend, Protection};
+use crate::allocators::decay::Advice;
+use crate::allocators::regions::Regions;
+use crate::blocklist::BLOCK_ALIGN;
 
 pub trait HeapGrower {
-    type Err;
+    type Err: Into<AllocError>;
     unsafe fn grow_heap(&mut self, size: usize) -> Result<(*mut u8, usize), Self::Err>;
+
+    /// Counters describing the grower's activity, for growers that keep them.
+    fn stats(&self) -> GrowerStats {
+        GrowerStats::default()
+    }
+
+    /// Gives the free memory `[ptr, ptr + size)` back to the system, if the
+    /// grower is able to. Returns how many bytes were given back, always from
+    /// the end of the range; the rest is still the caller's.
+    ///
+    /// # Safety
+    ///
+    /// `[ptr, ptr + size)` must be memory from this grower that nothing uses.
+    unsafe fn shrink_heap(&mut self, _ptr: *mut u8, _size: usize) -> usize {
+        0
+    }
+
+    /// How much free memory at the end of a region is worth giving back with
+    /// `shrink_heap` as soon as it is freed, or `None` if this grower never
+    /// gives memory back. `RawAlloc::trim` gives back any amount.
+    fn trim_threshold(&self) -> Option<usize> {
+        None
+    }
+
+    /// The granularity in which this grower hands out and takes back memory.
+    /// `RawAlloc` asks for whole pages, and only offers whole pages to
+    /// `shrink_heap`. Defaults to `BLOCK_ALIGN`, the alignment of every
+    /// block.
+    fn page_size(&self) -> usize {
+        BLOCK_ALIGN
+    }
+
+    /// Passes `advice` on to the system for the free pages `[ptr, ptr +
+    /// size)`, which stay the grower's. Returns whether the system took it;
+    /// by default it is never given.
+    ///
+    /// # Safety
+    ///
+    /// `[ptr, ptr + size)` must be whole pages of memory from this grower that
+    /// nothing uses.
+    unsafe fn advise(&mut self, _ptr: *mut u8, _size: usize, _advice: Advice) -> bool {
+        false
+    }
 }
 
-#[derive(Default)]
-pub struct EnhancedHeapGrower {
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct GrowerStats {
+    /// Calls to `grow_heap`.
+    pub attempts: usize,
+    /// Calls to `grow_heap` that returned memory.
+    pub growths: usize,
+    /// Bytes currently obtained from the system.
+    pub mapped_bytes: usize,
+    /// The most bytes ever obtained from the system at once.
+    pub peak_bytes: usize,
+    /// Of `mapped_bytes`, those backed by explicit (hugetlbfs) huge pages.
+    pub hugetlb_bytes: usize,
+    /// Of `mapped_bytes`, those advised to become transparent huge pages.
+    pub transparent_huge_bytes: usize,
+}
+
+/// When the pages a grower hands out are faulted in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Prefault {
+    /// On first touch, by whoever uses them.
+    #[default]
+    Lazy,
+    /// By the system, before the mapping returns (`MAP_POPULATE`). Pages
+    /// added to a mapping in place are touched instead.
+    Populate,
+    /// By the grower, touching each page before handing it out.
+    Touch,
+}
+
+/// A grower that maps memory from a `MemoryBackend`, extending the last
+/// mapping in place where it can. Without an OS there is no `DefaultBackend`,
+/// and the backend must be given.
+pub struct EnhancedHeapGrower<
+    #[cfg(not(target_os = "none"))] B: MemoryBackend = DefaultBackend,
+    #[cfg(target_os = "none")] B: MemoryBackend,
+> {
+    backend: B,
+    prefault: Prefault,
     // Enhanced tracking mechanisms
     pages: AtomicUsize,
     growths: AtomicUsize,
+    // The most recent mapping, which the next growth tries to extend.
     base: AtomicPtr<u8>,
+    base_len: AtomicUsize,
+    in_place_growths: AtomicUsize,
     total_allocated: AtomicUsize,
     peak_allocation: AtomicUsize,
     allocation_attempts: AtomicUsize,
+    mappings: Regions,
 }
 
-impl EnhancedHeapGrower {
-    #[inline(always)]
-    const fn round_up(value: usize, increment: usize) -> usize {
-        (value + increment - 1) & !(increment - 1)
+#[cfg(not(target_os = "none"))]
+impl Default for EnhancedHeapGrower {
+    fn default() -> Self {
+        Self::with_backend(DefaultBackend {})
     }
+}
 
-    #[inline(always)]
-    fn get_page_size() -> usize {
-        // Standard page size for most architectures
-        const DEFAULT_PAGE_SIZE: usize = 4096;
-
-        // Architecture-specific page size detection
-        #[cfg(target_arch = "x86_64")]
-        {
-            return DEFAULT_PAGE_SIZE;
-        }
-
-        #[cfg(target_arch = "aarch64")]
-        {
-            return DEFAULT_PAGE_SIZE;
-        }
-
-        #[cfg(target_arch = "arm")]
-        {
-            return DEFAULT_PAGE_SIZE;
-        }
-
-        #[cfg(target_os = "windows")]
-        {
-            // Ultra-low-level Windows page size detection
-            unsafe {
-                // Direct system call approach (no libc)
-                const SYSTEM_INFO_OFFSET: usize = 44; // Offset to dwPageSize in SYSTEM_INFO structure
-                let mut system_info = core::mem::zeroed::<[u8; 64]>();
-                
-                // Windows-specific system call to get system information
-                #[cfg(target_arch = "x86_64")]
-                core::arch::asm!(
-                    "syscall",
-                    in("rax") 0x34, // GetSystemInfo syscall number
-                    in("rcx") system_info.as_mut_ptr(),
-                    options(nostack)
-                );
-
-                // Extract page size from the system info structure
-                return *(system_info.as_ptr().add(SYSTEM_INFO_OFFSET) as *const usize);
-            }
+impl<B: MemoryBackend> EnhancedHeapGrower<B> {
+    pub const fn with_backend(backend: B) -> Self {
+        EnhancedHeapGrower {
+            backend,
+            prefault: Prefault::Lazy,
+            pages: AtomicUsize::new(0),
+            growths: AtomicUsize::new(0),
+            base: AtomicPtr::new(null_mut()),
+            base_len: AtomicUsize::new(0),
+            in_place_growths: AtomicUsize::new(0),
+            total_allocated: AtomicUsize::new(0),
+            peak_allocation: AtomicUsize::new(0),
+            allocation_attempts: AtomicUsize::new(0),
+            mappings: Regions::new(),
         }
+    }
 
-        #[cfg(unix)]
-        {
-            // Low-level Unix page size detection
-            unsafe {
-                // Direct system call for page size
-                let mut page_size: usize = 0;
-                
-                #[cfg(target_arch = "x86_64")]
-                core::arch::asm!(
-                    "syscall",
-                    in("rax") 0xc0, // sysconf syscall
-                    in("rdi") 30,   // _SC_PAGESIZE constant
-                    out("rcx") page_size,
-                    options(nostack)
-                );
-
-                return page_size.max(DEFAULT_PAGE_SIZE);
-            }
-        }
+    /// Has the pages of every growth faulted in before they are handed out,
+    /// so nothing faults on first touch.
+    pub const fn with_prefault(mut self, prefault: Prefault) -> Self {
+        self.prefault = prefault;
+        self
+    }
 
-        // Fallback to default page size
-        DEFAULT_PAGE_SIZE
+    pub fn backend(&self) -> &B {
+        &self.backend
     }
 
+    #[cfg(target_os = "linux")]
+    pub(crate) fn backend_mut(&mut self) -> &mut B {
+        &mut self.backend
+    }
 
+    #[inline(always)]
+    const fn round_up(value: usize, increment: usize) -> usize {
+        (value + increment - 1) & !(increment - 1)
+    }
 }
 
-impl HeapGrower for EnhancedHeapGrower {
-    #[cfg(not(feature = "use_libc"))]
-    type Err = MmapError;
-    
-    #[cfg(feature = "use_libc")]
-    type Err = Errno;
+impl<B: MemoryBackend> HeapGrower for EnhancedHeapGrower<B> {
+    type Err = AllocError;
 
     unsafe fn grow_heap(&mut self, size: usize) -> Result<(*mut u8, usize), Self::Err> {
         // Increment allocation attempts
@@ -114,69 +165,71 @@ impl HeapGrower for EnhancedHeapGrower {
             return Ok((null_mut(), 0));
         }
 
-        let page_size = Self::get_page_size();
+        let page_size = self.backend.page_size();
         let to_allocate = Self::round_up(size, page_size);
 
-        // Allocation with platform-specific method
-        #[cfg(not(feature = "use_libc"))]
-        let ptr = mmap::mmap(
-            null_mut(),
-            to_allocate,
-            mmap::PROT_WRITE | mmap::PROT_READ,
-            mmap::MAP_ANON | mmap::MAP_PRIVATE,
-            u64::MAX,
-            0,
-        )?;
-
-        #[cfg(feature = "use_libc")]
-        let ptr = libc::mmap(
-            null_mut(),
-            to_allocate,
-            libc::PROT_WRITE | libc::PROT_READ,
-            libc::MAP_ANON | libc::MAP_PRIVATE,
-            -1,
-            0,
-        );
-
-        // Check allocation success
-        #[cfg(feature = "use_libc")]
-        if ptr == libc::MAP_FAILED {
-            return Err(errno::errno());
+        // Extending the last mapping keeps the heap in one piece, so the new
+        // memory can merge with free blocks at the end of the old.
+        if let Some(tail) = self.extend_in_place(to_allocate) {
+            if self.prefault != Prefault::Lazy {
+                backend::touch(tail, to_allocate, page_size);
+            }
+            self.in_place_growths.fetch_add(1, Ordering::Relaxed);
+            self.record_growth(tail, to_allocate, page_size);
+            return Ok((tail, to_allocate));
         }
 
-        // Update tracking metrics
-        let current_total = self.total_allocated.fetch_add(to_allocate, Ordering::Relaxed);
-        self.peak_allocation.fetch_max(current_total.wrapping_add(to_allocate), Ordering::Relaxed);
-        
-        self.pages.fetch_add(to_allocate.wrapping_div(page_size), Ordering::Relaxed);
-        self.growths.fetch_add(1, Ordering::Relaxed);
-        self.base.store(ptr as *mut u8, Ordering::Relaxed);
+        let ptr = match self.prefault {
+            Prefault::Lazy => self.backend.map(to_allocate, Protection::ReadWrite)?,
+            Prefault::Populate => self.backend.map_populated(to_allocate)?,
+            Prefault::Touch => {
+                let ptr = self.backend.map(to_allocate, Protection::ReadWrite)?;
+                backend::touch(ptr, to_allocate, page_size);
+                ptr
+            }
+        };
+
+        self.base.store(ptr, Ordering::Relaxed);
+        self.base_len.store(to_allocate, Ordering::Relaxed);
+        self.record_growth(ptr, to_allocate, page_size);
 
-        Ok((ptr as *mut u8, to_allocate))
+        Ok((ptr, to_allocate))
+    }
+
+    fn stats(&self) -> GrowerStats {
+        GrowerStats {
+            attempts: self.allocation_attempts(),
+            growths: self.growths.load(Ordering::Relaxed),
+            mapped_bytes: self.total_allocated(),
+            peak_bytes: self.peak_allocation(),
+            ..GrowerStats::default()
+        }
+    }
+
+    fn page_size(&self) -> usize {
+        self.backend.page_size()
+    }
+
+    unsafe fn advise(&mut self, ptr: *mut u8, size: usize, advice: Advice) -> bool {
+        self.backend.advise(ptr, size, advice).is_ok()
     }
 }
 
-impl Drop for EnhancedHeapGrower {
+impl<B: MemoryBackend> Drop for EnhancedHeapGrower<B> {
     fn drop(&mut self) {
-        unsafe {
-            let page_size = EnhancedHeapGrower::get_page_size();
-            let pages = self.pages.load(Ordering::Relaxed);
-            
-            if pages > 0 {
-                let size = pages * page_size;
-                
-                #[cfg(not(feature = "use_libc"))]
-                let _ = mmap::munmap(self.base.load(Ordering::Relaxed) as *mut _, size);
-                
-                #[cfg(feature = "use_libc")]
-                libc::munmap(self.base.load(Ordering::Relaxed) as *mut _, size);
+        // Adjacent mappings are coalesced in `mappings`; a single munmap may
+        // span several of them.
+        for range in self.mappings.iter() {
+            let size = range.end as usize - range.start as usize;
+            unsafe {
+                let _ = self.backend.unmap(range.start as *mut u8, size);
             }
         }
     }
 }
 
 // Optional: Expose tracking methods
-impl EnhancedHeapGrower {
+impl<B: MemoryBackend> EnhancedHeapGrower<B> {
     pub fn total_allocated(&self) -> usize {
         self.total_allocated.load(Ordering::Relaxed)
     }
@@ -188,4 +241,158 @@ impl EnhancedHeapGrower {
     pub fn allocation_attempts(&self) -> usize {
         self.allocation_attempts.load(Ordering::Relaxed)
     }
+
+    /// Growths that extended the previous mapping rather than making a new
+    /// one.
+    pub fn in_place_growths(&self) -> usize {
+        self.in_place_growths.load(Ordering::Relaxed)
+    }
+
+    fn record_growth(&mut self, ptr: *mut u8, size: usize, page_size: usize) {
+        let current_total = self.total_allocated.fetch_add(size, Ordering::Relaxed);
+        self.peak_allocation.fetch_max(current_total.wrapping_add(size), Ordering::Relaxed);
+
+        self.pages.fetch_add(size.wrapping_div(page_size), Ordering::Relaxed);
+        self.growths.fetch_add(1, Ordering::Relaxed);
+        self.mappings.insert(ptr as *const u8, size);
+    }
+
+    /// Grows the most recent mapping by `size` bytes without moving it, and
+    /// returns the start of the new tail, or `None` if the pages after it are
+    /// taken.
+    unsafe fn extend_in_place(&mut self, size: usize) -> Option<*mut u8> {
+        let base = self.base.load(Ordering::Relaxed);
+        let len = self.base_len.load(Ordering::Relaxed);
+        if base.is_null() {
+            return None;
+        }
+        self.backend.remap(base, len, len + size).ok()?;
+        self.base_len.store(len + size, Ordering::Relaxed);
+        Some(base.add(len))
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use crate::allocators::alloc_error::{Errno, Operation};
+    use crate::allocators::backend::{MockBackend, MockMemory, MOCK_PAGE};
+    use crate::allocators::RawAlloc;
+    use core::alloc::Layout;
+    use core::ptr::NonNull;
+    use test_log::test;
+
+    /// A grower whose one mapping, a page long, has a wide gap after it.
+    /// Mappings made meanwhile by other threads go at the top of the gap, out
+    /// of the way.
+    unsafe fn grower_with_gap(page: usize) -> EnhancedHeapGrower {
+        let mut grower = EnhancedHeapGrower::default();
+        let (ptr, len) = grower.grow_heap(256 * page).unwrap();
+        grower.backend.remap(ptr, len, page).unwrap();
+        grower.base_len.store(page, Ordering::Relaxed);
+        grower.total_allocated.store(page, Ordering::Relaxed);
+        grower.mappings.remove(ptr.add(page), len - page);
+        grower
+    }
+
+    #[test]
+    fn test_grow_in_place() {
+        let page = DefaultBackend {}.page_size();
+        let mut allocator = RawAlloc::new(unsafe { grower_with_gap(page) });
+        let base = allocator.grower.base.load(Ordering::Relaxed);
+        let layout = Layout::from_size_align(2 * page, BLOCK_ALIGN).unwrap();
+        unsafe {
+            // The first page is free, but too small.
+            allocator.regions.insert(base, page);
+            allocator.blocks.add_block(NonNull::new(base).unwrap(), page);
+
+            let ptr = allocator.alloc(layout);
+            assert_eq!(ptr, base.add(page));
+            ptr.write_bytes(0x5A, layout.size());
+            assert_eq!(allocator.grower.in_place_growths(), 1);
+            assert_eq!(allocator.grower.stats().mapped_bytes, 3 * page);
+            assert_eq!(allocator.regions.len(), 1);
+
+            allocator.dealloc(ptr, layout);
+            assert_eq!(allocator.blocks.len(), 1, "the tail should merge with the old mapping");
+            assert!(allocator.verify().is_valid(), "{}", allocator.verify());
+        }
+    }
+
+    #[test]
+    fn test_mock_backend() {
+        let memory = MockMemory::new();
+        let mut allocator = RawAlloc::new(EnhancedHeapGrower::with_backend(MockBackend(&memory)));
+        let layout = Layout::from_size_align(3 * MOCK_PAGE, BLOCK_ALIGN).unwrap();
+        unsafe {
+            let a = allocator.alloc(layout);
+            let b = allocator.alloc(layout);
+            assert_eq!(b, a.add(3 * MOCK_PAGE));
+            assert_eq!((memory.maps.get(), memory.remaps.get()), (1, 1));
+            assert_eq!(allocator.regions.len(), 1);
+
+            // There is room neither after the mapping nor anywhere else.
+            let huge = Layout::from_size_align(64 * MOCK_PAGE, BLOCK_ALIGN).unwrap();
+            assert_eq!(
+                allocator.try_alloc(huge),
+                Err(AllocError::System {
+                    op: Operation::Mmap,
+                    errno: Errno::ENOMEM
+                })
+            );
+
+            allocator.dealloc(a, layout);
+            allocator.dealloc(b, layout);
+            assert_eq!(allocator.blocks.len(), 1);
+            // All but the page holding the header.
+            assert_eq!(allocator.purge().released_bytes, 5 * MOCK_PAGE);
+            assert_eq!(memory.advised.get(), 5 * MOCK_PAGE);
+        }
+        drop(allocator);
+        assert_eq!(memory.mapped_bytes.get(), 0, "dropping the grower should unmap everything");
+    }
+
+    #[test]
+    fn test_reserve() {
+        let memory = MockMemory::new();
+        let mut allocator = RawAlloc::new(EnhancedHeapGrower::with_backend(MockBackend(&memory)));
+        let page = Layout::from_size_align(MOCK_PAGE, BLOCK_ALIGN).unwrap();
+        assert_eq!(allocator.reserve(8 * MOCK_PAGE), Ok(8 * MOCK_PAGE));
+        unsafe {
+            let pointers = [(); 4].map(|_| allocator.alloc(page));
+            assert!(pointers.iter().all(|p| !p.is_null()));
+            assert_eq!((memory.maps.get(), memory.remaps.get()), (1, 0), "warm allocations should not grow");
+            assert_eq!(allocator.prefaulted_bytes(), 4 * MOCK_PAGE);
+            assert_eq!(allocator.metrics().prefaulted_bytes, 4 * MOCK_PAGE);
+
+            // Freed memory was handed out, so no longer counts.
+            for ptr in pointers {
+                allocator.dealloc(ptr, page);
+            }
+            assert_eq!(allocator.prefaulted_bytes(), 4 * MOCK_PAGE);
+        }
+        // Purged pages, all but the one holding the header, are no longer
+        // faulted in, until reserved again.
+        allocator.purge();
+        assert_eq!(allocator.prefaulted_bytes(), MOCK_PAGE);
+        assert_eq!(allocator.reserve(0), Ok(8 * MOCK_PAGE));
+        assert_eq!(memory.maps.get(), 1);
+    }
+
+    #[test]
+    fn test_prefault() {
+        for prefault in [Prefault::Populate, Prefault::Touch] {
+            let mut allocator = RawAlloc::new(EnhancedHeapGrower::default().with_prefault(prefault));
+            let layout = Layout::from_size_align(1 << 20, BLOCK_ALIGN).unwrap();
+            unsafe {
+                for _ in 0..4 {
+                    let ptr = allocator.alloc(layout);
+                    assert!(!ptr.is_null());
+                    assert_eq!(*ptr.add(layout.size() - 1), 0);
+                    ptr.write_bytes(0x5A, layout.size());
+                }
+            }
+            assert!(allocator.verify().is_valid(), "{}", allocator.verify());
+        }
+    }
 }
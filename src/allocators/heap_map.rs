@@ -0,0 +1,188 @@
+use core::fmt::{self, Write};
+use core::ops::Range;
+
+use super::regions::Regions;
+use crate::blocklist::BlockList;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeapMapFormat {
+    /// One line per region and segment, e.g. `free 0x1000 0x1040 64`.
+    Text,
+    /// A single JSON object; addresses are hex strings.
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentKind {
+    Used,
+    Free,
+}
+
+impl SegmentKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            SegmentKind::Used => "used",
+            SegmentKind::Free => "free",
+        }
+    }
+}
+
+/// Calls `f` with every used and free segment of `region`, in address order.
+/// Free segments come from `blocks`; the gaps between them are in use.
+pub fn for_each_segment<F: FnMut(SegmentKind, Range<*const u8>) -> fmt::Result>(
+    region: Range<*const u8>,
+    blocks: &BlockList,
+    mut f: F,
+) -> fmt::Result {
+    let mut cursor = region.start;
+    for block in blocks {
+        let range = block.as_range();
+        if range.start < region.start || range.start >= region.end {
+            continue;
+        }
+        if cursor < range.start {
+            f(SegmentKind::Used, cursor..range.start)?;
+        }
+        let end = if range.end > region.end { region.end } else { range.end };
+        f(SegmentKind::Free, range.start..end)?;
+        cursor = end;
+    }
+    if cursor < region.end {
+        f(SegmentKind::Used, cursor..region.end)?;
+    }
+    Ok(())
+}
+
+pub fn write_heap_map<W: Write>(
+    w: &mut W,
+    regions: &Regions,
+    blocks: &BlockList,
+    format: HeapMapFormat,
+) -> fmt::Result {
+    let mapped = regions.total_bytes();
+    let free = blocks
+        .iter()
+        .filter(|block| regions.contains(block.as_range().start, block.size()))
+        .fold(0usize, |acc, block| acc.wrapping_add(block.size()));
+    let used = mapped.wrapping_sub(free);
+
+    match format {
+        HeapMapFormat::Text => {
+            writeln!(
+                w,
+                "heap_map regions={} mapped={} free={} used={} untracked={}",
+                regions.len(),
+                mapped,
+                free,
+                used,
+                regions.untracked_bytes()
+            )?;
+            for region in regions.iter() {
+                writeln!(
+                    w,
+                    "region {:#x} {:#x} {}",
+                    region.start as usize,
+                    region.end as usize,
+                    region.end as usize - region.start as usize
+                )?;
+                for_each_segment(region, blocks, |kind, seg| {
+                    writeln!(
+                        w,
+                        "  {} {:#x} {:#x} {}",
+                        kind.name(),
+                        seg.start as usize,
+                        seg.end as usize,
+                        seg.end as usize - seg.start as usize
+                    )
+                })?;
+            }
+            Ok(())
+        }
+        HeapMapFormat::Json => {
+            write!(
+                w,
+                "{{\"mapped\":{},\"free\":{},\"used\":{},\"untracked\":{},\"regions\":[",
+                mapped,
+                free,
+                used,
+                regions.untracked_bytes()
+            )?;
+            for (i, region) in regions.iter().enumerate() {
+                if i > 0 {
+                    w.write_char(',')?;
+                }
+                write!(
+                    w,
+                    "{{\"start\":\"{:#x}\",\"end\":\"{:#x}\",\"segments\":[",
+                    region.start as usize, region.end as usize
+                )?;
+                let mut first = true;
+                for_each_segment(region, blocks, |kind, seg| {
+                    if !first {
+                        w.write_char(',')?;
+                    }
+                    first = false;
+                    write!(
+                        w,
+                        "{{\"kind\":\"{}\",\"start\":\"{:#x}\",\"end\":\"{:#x}\",\"size\":{}}}",
+                        kind.name(),
+                        seg.start as usize,
+                        seg.end as usize,
+                        seg.end as usize - seg.start as usize
+                    )
+                })?;
+                w.write_str("]}")?;
+            }
+            w.write_str("]}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocators::{RawAlloc, ToyHeap};
+    use core::alloc::Layout;
+    use crate::blocklist::BLOCK_ALIGN;
+    use test_log::test;
+
+    extern crate alloc;
+    use alloc::string::String;
+
+    #[test]
+    fn test_text_map() {
+        let mut allocator = RawAlloc::new(ToyHeap::default());
+        let layout = Layout::from_size_align(32, BLOCK_ALIGN).unwrap();
+        let (a, b) = unsafe { (allocator.alloc(layout), allocator.alloc(layout)) };
+        unsafe { allocator.dealloc(a, layout) };
+
+        let mut out = String::new();
+        allocator.write_heap_map(&mut out, HeapMapFormat::Text).unwrap();
+        log::info!("{}", out);
+
+        let mut lines = out.lines();
+        assert_eq!(
+            lines.next().unwrap(),
+            "heap_map regions=1 mapped=64 free=32 used=32 untracked=0"
+        );
+        let start = a as usize;
+        assert_eq!(
+            lines.next().unwrap(),
+            alloc::format!("region {:#x} {:#x} 64", start, start + 64)
+        );
+        assert_eq!(
+            lines.next().unwrap(),
+            alloc::format!("  free {:#x} {:#x} 32", start, start + 32)
+        );
+        assert_eq!(
+            lines.next().unwrap(),
+            alloc::format!("  used {:#x} {:#x} 32", b as usize, b as usize + 32)
+        );
+        assert_eq!(lines.next(), None);
+
+        let mut json = String::new();
+        allocator.write_heap_map(&mut json, HeapMapFormat::Json).unwrap();
+        assert!(json.starts_with("{\"mapped\":64,\"free\":32,\"used\":32,"));
+        assert!(json.ends_with("\"size\":32}]}]}"));
+    }
+}
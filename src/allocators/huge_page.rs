@@ -0,0 +1,264 @@
+use crate::allocators::alloc_error::AllocError;
+use crate::allocators::backend::{DefaultBackend, MemoryBackend, Protection};
+use crate::allocators::decay::Advice;
+use crate::allocators::heap_grower::{GrowerStats, HeapGrower};
+use crate::allocators::regions::Regions;
+
+/// The size of the huge pages a `HugePageGrower` asks for.
+pub const HUGE_PAGE_SIZE: usize = 2 * 1024 * 1024;
+
+/// How the memory from a growth is backed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HugePageMode {
+    /// Explicit huge pages, from the pool reserved in hugetlbfs.
+    HugeTlb,
+    /// Ordinary pages, aligned and advised to be merged into transparent huge
+    /// pages.
+    Transparent,
+    /// Ordinary pages, aligned to huge pages, where the kernel does not
+    /// support transparent huge pages.
+    Regular,
+}
+
+/// A `HeapGrower` that backs the heap with 2 MiB pages, so that large heaps
+/// need far fewer TLB entries.
+///
+/// Each growth first asks for explicit huge pages with `MAP_HUGETLB`. These
+/// only exist if the administrator has reserved some (see
+/// `/proc/sys/vm/nr_hugepages`); once they run out, growths instead map
+/// ordinary memory aligned to 2 MiB and advise it with `MADV_HUGEPAGE`.
+/// Which of these each byte got is reported in the grower's stats.
+///
+/// Memory is handed out, given back by `RawAlloc::trim`, and purged by
+/// decay, in whole huge pages.
+pub struct HugePageGrower<B: MemoryBackend = DefaultBackend> {
+    backend: B,
+    hugetlb: Regions,
+    transparent: Regions,
+    regular: Regions,
+    hugetlb_failed: bool,
+    mode: Option<HugePageMode>,
+    attempts: usize,
+    growths: usize,
+    peak: usize,
+}
+
+impl Default for HugePageGrower {
+    fn default() -> Self {
+        Self::with_backend(DefaultBackend {})
+    }
+}
+
+impl HugePageGrower {
+    /// A grower that goes straight to transparent huge pages, leaving the
+    /// hugetlbfs pool to others.
+    pub fn transparent() -> Self {
+        let mut grower = Self::default();
+        grower.hugetlb_failed = true;
+        grower
+    }
+}
+
+impl<B: MemoryBackend> HugePageGrower<B> {
+    pub const fn with_backend(backend: B) -> Self {
+        HugePageGrower {
+            backend,
+            hugetlb: Regions::new(),
+            transparent: Regions::new(),
+            regular: Regions::new(),
+            hugetlb_failed: false,
+            mode: None,
+            attempts: 0,
+            growths: 0,
+            peak: 0,
+        }
+    }
+
+    /// How the most recent growth was backed, or `None` before the first.
+    pub fn mode(&self) -> Option<HugePageMode> {
+        self.mode
+    }
+
+    fn mapped_bytes(&self) -> usize {
+        self.hugetlb.total_bytes() + self.transparent.total_bytes() + self.regular.total_bytes()
+    }
+
+    /// Maps `size` bytes of ordinary memory on a huge page boundary, by
+    /// mapping a huge page more and unmapping what is out of line.
+    unsafe fn map_aligned(&mut self, size: usize) -> Result<(*mut u8, HugePageMode), AllocError> {
+        let ptr = self.backend.map(size + HUGE_PAGE_SIZE, Protection::ReadWrite)?;
+        let lead = ptr.align_offset(HUGE_PAGE_SIZE);
+        if lead > 0 {
+            let _ = self.backend.unmap(ptr, lead);
+        }
+        let aligned = ptr.add(lead);
+        if lead < HUGE_PAGE_SIZE {
+            let _ = self.backend.unmap(aligned.add(size), HUGE_PAGE_SIZE - lead);
+        }
+        // Fails where the kernel does not support transparent huge pages.
+        if self.backend.advise_huge(aligned, size).is_ok() {
+            Ok((aligned, HugePageMode::Transparent))
+        } else {
+            Ok((aligned, HugePageMode::Regular))
+        }
+    }
+}
+
+impl<B: MemoryBackend> HeapGrower for HugePageGrower<B> {
+    type Err = AllocError;
+
+    unsafe fn grow_heap(&mut self, size: usize) -> Result<(*mut u8, usize), AllocError> {
+        self.attempts += 1;
+        let size = size.div_ceil(HUGE_PAGE_SIZE) * HUGE_PAGE_SIZE;
+        let hugetlb = if self.hugetlb_failed {
+            None
+        } else {
+            self.backend.map_huge(size).ok()
+        };
+        let (ptr, mode) = match hugetlb {
+            Some(ptr) => (ptr, HugePageMode::HugeTlb),
+            None => {
+                self.hugetlb_failed = true;
+                self.map_aligned(size)?
+            }
+        };
+
+        let regions = match mode {
+            HugePageMode::HugeTlb => &mut self.hugetlb,
+            HugePageMode::Transparent => &mut self.transparent,
+            HugePageMode::Regular => &mut self.regular,
+        };
+        regions.insert(ptr, size);
+        self.mode = Some(mode);
+        self.growths += 1;
+        self.peak = self.peak.max(self.mapped_bytes());
+        Ok((ptr, size))
+    }
+
+    unsafe fn shrink_heap(&mut self, ptr: *mut u8, size: usize) -> usize {
+        // Hugetlb mappings can only be unmapped in whole huge pages.
+        if ptr.align_offset(HUGE_PAGE_SIZE) != 0 || !size.is_multiple_of(HUGE_PAGE_SIZE) {
+            return 0;
+        }
+        for regions in [&mut self.hugetlb, &mut self.transparent, &mut self.regular] {
+            if regions.contains(ptr, size) {
+                let _ = self.backend.unmap(ptr, size);
+                regions.remove(ptr, size);
+                return size;
+            }
+        }
+        0
+    }
+
+    fn page_size(&self) -> usize {
+        HUGE_PAGE_SIZE
+    }
+
+    unsafe fn advise(&mut self, ptr: *mut u8, size: usize, advice: Advice) -> bool {
+        // As with unmapping, hugetlb pages are only given back whole.
+        if ptr.align_offset(HUGE_PAGE_SIZE) != 0 || !size.is_multiple_of(HUGE_PAGE_SIZE) {
+            return false;
+        }
+        let ours = [&self.hugetlb, &self.transparent, &self.regular]
+            .iter()
+            .any(|regions| regions.contains(ptr, size));
+        ours && self.backend.advise(ptr, size, advice).is_ok()
+    }
+
+    fn stats(&self) -> GrowerStats {
+        GrowerStats {
+            attempts: self.attempts,
+            growths: self.growths,
+            mapped_bytes: self.mapped_bytes(),
+            peak_bytes: self.peak,
+            hugetlb_bytes: self.hugetlb.total_bytes(),
+            transparent_huge_bytes: self.transparent.total_bytes(),
+        }
+    }
+}
+
+impl<B: MemoryBackend> Drop for HugePageGrower<B> {
+    fn drop(&mut self) {
+        for regions in [&self.hugetlb, &self.transparent, &self.regular] {
+            for range in regions.iter() {
+                let size = range.end as usize - range.start as usize;
+                unsafe {
+                    let _ = self.backend.unmap(range.start as *mut u8, size);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocators::RawAlloc;
+    use core::alloc::Layout;
+    use test_log::test;
+
+    fn check_growth(grower: HugePageGrower) {
+        let mut allocator = RawAlloc::new(grower);
+        let large = Layout::from_size_align(3 * 1024 * 1024, 16).unwrap();
+        let small = Layout::from_size_align(64, 16).unwrap();
+        unsafe {
+            let a = allocator.alloc(large);
+            assert!(!a.is_null());
+            assert_eq!(a.align_offset(HUGE_PAGE_SIZE), 0);
+            a.write_bytes(0xA5, large.size());
+            let b = allocator.alloc(small);
+            assert!(!b.is_null());
+            assert_eq!(allocator.regions.total_bytes(), 2 * HUGE_PAGE_SIZE);
+
+            let stats = allocator.grower.stats();
+            log::info!("{:?} {:?}", allocator.grower.mode(), stats);
+            assert_eq!(stats.mapped_bytes, 2 * HUGE_PAGE_SIZE);
+            let expected = match allocator.grower.mode() {
+                Some(HugePageMode::HugeTlb) => (2 * HUGE_PAGE_SIZE, 0),
+                Some(HugePageMode::Transparent) => (0, 2 * HUGE_PAGE_SIZE),
+                Some(HugePageMode::Regular) => (0, 0),
+                None => panic!("grew without a mode"),
+            };
+            assert_eq!((stats.hugetlb_bytes, stats.transparent_huge_bytes), expected);
+
+            // The free tail is less than a huge page, so none of it goes back.
+            allocator.dealloc(a, large);
+            assert_eq!(allocator.trim(), 0);
+            allocator.dealloc(b, small);
+            assert_eq!(allocator.trim(), 2 * HUGE_PAGE_SIZE);
+            assert_eq!(allocator.grower.stats().mapped_bytes, 0);
+            assert!(allocator.regions.is_empty());
+            assert!(allocator.verify().is_valid(), "{}", allocator.verify());
+        }
+    }
+
+    #[test]
+    fn test_huge_pages() {
+        check_growth(HugePageGrower::default());
+    }
+
+    #[test]
+    fn test_transparent() {
+        check_growth(HugePageGrower::transparent());
+    }
+
+    #[test]
+    fn test_advise() {
+        let mut grower = HugePageGrower::default();
+        unsafe {
+            let (ptr, size) = grower.grow_heap(2 * HUGE_PAGE_SIZE).unwrap();
+            ptr.write_bytes(0xA5, size);
+            assert!(!grower.advise(ptr.add(4096), HUGE_PAGE_SIZE, Advice::DontNeed));
+            assert!(!grower.advise(ptr, 4096, Advice::DontNeed));
+            assert!(!grower.advise(ptr.add(size), HUGE_PAGE_SIZE, Advice::DontNeed));
+
+            let page = ptr.add(HUGE_PAGE_SIZE);
+            if grower.advise(page, HUGE_PAGE_SIZE, Advice::DontNeed) {
+                assert_eq!(page.read(), 0);
+                assert_eq!(page.add(HUGE_PAGE_SIZE - 1).read(), 0);
+            }
+            assert_eq!(ptr.read(), 0xA5);
+            assert_eq!(grower.stats().mapped_bytes, size);
+        }
+    }
+}
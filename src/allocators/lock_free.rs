@@ -0,0 +1,153 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{null_mut, NonNull};
+
+use crate::allocators::generic_allocator::GenericAllocator;
+use crate::allocators::heap_grower::{EnhancedHeapGrower, HeapGrower};
+use crate::allocators::raw_alloc::RawAlloc;
+use crate::blocklist::{AtomicStack, BLOCK_ALIGN};
+
+/// Blocks up to this size are kept in size classes.
+pub const MAX_CLASS_SIZE: usize = 1024;
+
+/// One size class per multiple of `BLOCK_ALIGN`, up to `MAX_CLASS_SIZE`.
+pub const SIZE_CLASSES: usize = MAX_CLASS_SIZE / BLOCK_ALIGN;
+
+// How much a size class takes from the heap at a time, at most.
+const REFILL_BYTES: usize = 4096;
+
+/// A `GenericAllocator` with a lock-free stack of free blocks per size class
+/// in front of it.
+///
+/// Small allocations are popped from their class's stack, and freed blocks
+/// are pushed back, without taking the heap's lock. Only when a stack is
+/// empty is the heap locked, to carve a batch of blocks for it. Larger or
+/// overaligned allocations go to the heap as usual.
+///
+/// Blocks in the stacks are never given back to the heap, as another
+/// thread's pop may still read them; to the heap, they stay live, so neither
+/// trimming nor decay touches them.
+pub struct LockFreeAllocator<G: HeapGrower + Default = EnhancedHeapGrower> {
+    heap: GenericAllocator<G>,
+    classes: [AtomicStack; SIZE_CLASSES],
+}
+
+impl<G: HeapGrower + Default> LockFreeAllocator<G> {
+    pub const fn new() -> Self {
+        Self::with_heap(GenericAllocator::new())
+    }
+
+    pub const fn with_heap(heap: GenericAllocator<G>) -> Self {
+        LockFreeAllocator {
+            heap,
+            classes: [const { AtomicStack::new() }; SIZE_CLASSES],
+        }
+    }
+
+    /// The heap behind the size classes. Its live bytes include the blocks
+    /// in the stacks.
+    pub fn heap(&self) -> &GenericAllocator<G> {
+        &self.heap
+    }
+
+    /// Bytes in the stacks, ready to be handed out without a lock.
+    pub fn cached_bytes(&self) -> usize {
+        self.classes
+            .iter()
+            .enumerate()
+            .map(|(class, stack)| stack.len() * Self::class_size(class))
+            .sum()
+    }
+
+    /// The size class for `layout`, if it has one.
+    #[inline(always)]
+    fn class(layout: Layout) -> Option<usize> {
+        if layout.align() > BLOCK_ALIGN {
+            return None;
+        }
+        let size = RawAlloc::<G>::block_size(layout).max(BLOCK_ALIGN);
+        let class = size / BLOCK_ALIGN - 1;
+        (class < SIZE_CLASSES).then_some(class)
+    }
+
+    #[inline(always)]
+    const fn class_size(class: usize) -> usize {
+        (class + 1) * BLOCK_ALIGN
+    }
+
+    /// Carves a batch of blocks for `class` from the heap, keeping one and
+    /// pushing the rest.
+    #[cold]
+    unsafe fn refill(&self, class: usize) -> *mut u8 {
+        let size = Self::class_size(class);
+        let count = (REFILL_BYTES / size).max(1);
+        let batch = self.heap.alloc(Layout::from_size_align_unchecked(count * size, BLOCK_ALIGN));
+        if batch.is_null() {
+            // Perhaps there is room for just the one.
+            return self.heap.alloc(Layout::from_size_align_unchecked(size, BLOCK_ALIGN));
+        }
+        for i in 1..count {
+            self.release(class, batch.add(i * size));
+        }
+        batch
+    }
+
+    /// Pushes a block of `class` onto its stack, or frees it to the heap if
+    /// the stack cannot hold it. The heap frees any range it handed out,
+    /// not just whole allocations, so part of a batch is fine.
+    #[inline(always)]
+    unsafe fn release(&self, class: usize, ptr: *mut u8) {
+        let block = NonNull::new_unchecked(ptr);
+        if !self.classes[class].push(block) {
+            let size = Self::class_size(class);
+            self.heap.dealloc(ptr, Layout::from_size_align_unchecked(size, BLOCK_ALIGN));
+        }
+    }
+}
+
+impl<G: HeapGrower + Default> Default for LockFreeAllocator<G> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+unsafe impl<G: HeapGrower + Default> Send for LockFreeAllocator<G> {}
+unsafe impl<G: HeapGrower + Default> Sync for LockFreeAllocator<G> {}
+
+unsafe impl<G: HeapGrower + Default> GlobalAlloc for LockFreeAllocator<G> {
+    #[inline(always)]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let Some(class) = Self::class(layout) else {
+            return self.heap.alloc(layout);
+        };
+        match self.classes[class].pop() {
+            Some(ptr) => ptr.as_ptr(),
+            None => self.refill(class),
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        match (Self::class(layout), Self::class(new_layout)) {
+            (None, None) => self.heap.realloc(ptr, layout, new_size),
+            (old, new) if old == new => ptr,
+            _ => {
+                let new_ptr = self.alloc(new_layout);
+                if new_ptr.is_null() {
+                    return null_mut();
+                }
+                core::ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+                self.dealloc(ptr, layout);
+                new_ptr
+            }
+        }
+    }
+
+    #[inline(always)]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        match Self::class(layout) {
+            Some(class) => self.release(class, ptr),
+            None => self.heap.dealloc(ptr, layout),
+        }
+    }
+}
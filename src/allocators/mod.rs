@@ -1,16 +1,81 @@
+mod alloc_error;
+#[cfg(not(target_os = "none"))]
+mod arena;
+#[cfg(any(feature = "alloc", test))]
 mod atomic_array;
+mod backend;
+#[cfg(target_os = "linux")]
+mod brk_heap;
+mod budget;
+mod combinators;
+mod decay;
+mod fork;
 mod generic_allocator;
 mod heap_grower;
+mod heap_map;
+#[cfg(all(target_has_atomic = "64", not(target_os = "none")))]
+mod lock_free;
+#[cfg(target_os = "linux")]
+mod huge_page;
+#[cfg(target_os = "linux")]
+mod numa;
+#[cfg(target_os = "linux")]
+mod persistent;
+#[cfg(target_has_atomic = "64")]
+mod profiler;
 mod raw_alloc;
+mod regions;
+#[cfg(target_os = "linux")]
+mod reserving;
+mod static_heap;
+#[cfg(any(feature = "alloc", test))]
 mod toy_heap;
+#[cfg(not(target_os = "none"))]
 mod unix_allocator;
+mod verify;
 
+pub use alloc_error::{AllocError, Errno, Operation};
+#[cfg(not(target_os = "none"))]
+pub use arena::{cpu_count, current_cpu, ARENAS_PER_CPU, MAX_ARENAS};
+#[cfg(any(feature = "alloc", test))]
 pub use atomic_array::AtomicArray;
+#[cfg(feature = "use_libc")]
+pub use backend::LibcBackend;
+pub use backend::{MemoryBackend, Protection};
+#[cfg(not(target_os = "none"))]
+pub use backend::{DefaultBackend, RawBackend};
+#[cfg(target_os = "linux")]
+pub use brk_heap::BrkHeapGrower;
+pub use budget::{Budget, OomAction, OomEvent, OomHandler, SoftLimitHandler};
+pub use combinators::{
+    Chunked, Counting, Fallback, FallbackError, Limit, LimitError, DEFAULT_MAX_CHUNK, DEFAULT_MIN_CHUNK,
+};
+pub use decay::{Advice, Decay, DecayDelays, DecayStats};
 pub use generic_allocator::GenericAllocator;
 pub use raw_alloc::RawAlloc;
-pub use heap_grower::{HeapGrower, EnhancedHeapGrower};
+pub use regions::Regions;
+#[cfg(target_os = "linux")]
+pub use reserving::{ReservingError, ReservingHeapGrower, DEFAULT_RESERVATION};
+pub use heap_grower::{HeapGrower, EnhancedHeapGrower, GrowerStats, Prefault};
+pub use heap_map::{HeapMapFormat, SegmentKind};
+#[cfg(all(target_has_atomic = "64", not(target_os = "none")))]
+pub use lock_free::{LockFreeAllocator, MAX_CLASS_SIZE, SIZE_CLASSES};
+#[cfg(target_os = "linux")]
+pub use huge_page::{HugePageGrower, HugePageMode, HUGE_PAGE_SIZE};
+#[cfg(target_os = "linux")]
+pub use numa::{node_of, NumaHeapGrower, NumaPolicy, MAX_NODES};
+#[cfg(target_os = "linux")]
+pub use persistent::{FileHeapGrower, PersistentAlloc, PersistentHeapError, SystemError};
+#[cfg(target_has_atomic = "64")]
+pub use profiler::{
+    frame_pointer_backtrace, ProfiledAllocator, Sample, SamplingProfiler, StackCapture,
+};
+pub use static_heap::{StaticHeap, StaticHeapOverflowError, MAX_SPANS};
+#[cfg(any(feature = "alloc", test))]
 pub use toy_heap::{ToyHeap, ToyHeapOverflowError};
+#[cfg(not(target_os = "none"))]
 pub use unix_allocator::UnixAllocator;
+pub use verify::{HeapError, HeapReport};
 
 pub fn round_up(value: usize, increment: usize) -> usize {
     (value + increment - 1) / increment * increment
@@ -0,0 +1,255 @@
+use crate::allocators::alloc_error::{AllocError, Errno};
+use crate::allocators::backend::{DefaultBackend, MemoryBackend, Protection};
+use crate::allocators::decay::Advice;
+use crate::allocators::heap_grower::{GrowerStats, HeapGrower};
+use crate::allocators::regions::Regions;
+use crate::mmap::{MPOL_BIND, MPOL_DEFAULT, MPOL_INTERLEAVE, MPOL_PREFERRED};
+
+/// Node masks are a single word, so nodes `0..MAX_NODES` can be named.
+pub const MAX_NODES: usize = 64;
+
+/// Kernels built without NUMA support have none of the policy syscalls.
+fn is_unsupported(error: &AllocError) -> bool {
+    matches!(error, AllocError::System { errno: Errno::ENOSYS, .. })
+}
+
+/// Where memory should be placed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumaPolicy {
+    /// The system default, usually the node of the CPU that first touches
+    /// each page.
+    Default,
+    /// Only `node`, failing when it is out of memory.
+    Bind(usize),
+    /// `node` where it has memory to spare, otherwise anywhere.
+    Preferred(usize),
+    /// Pages spread round-robin over a set of nodes, with bit `n` of the
+    /// mask standing for node `n`.
+    Interleave(u64),
+}
+
+impl NumaPolicy {
+    fn mode_and_mask(&self) -> (u64, u64) {
+        let node_mask = |node: usize| 1u64.checked_shl(node as u32).unwrap_or(0);
+        match *self {
+            NumaPolicy::Default => (MPOL_DEFAULT, 0),
+            NumaPolicy::Bind(node) => (MPOL_BIND, node_mask(node)),
+            NumaPolicy::Preferred(node) => (MPOL_PREFERRED, node_mask(node)),
+            NumaPolicy::Interleave(mask) => (MPOL_INTERLEAVE, mask),
+        }
+    }
+
+    /// Makes this the policy for everything the calling thread allocates from
+    /// now on, not just through a `NumaHeapGrower`. Returns false, having done
+    /// nothing, if the kernel has no NUMA support.
+    pub fn apply_to_thread(&self) -> Result<bool, AllocError> {
+        let (mode, mask) = self.mode_and_mask();
+        let result = DefaultBackend {}.set_thread_policy(mode, mask);
+        match result {
+            Ok(()) => Ok(true),
+            Err(error) if is_unsupported(&error) => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+/// The node holding the page at `ptr`, as reported by `get_mempolicy`, or
+/// `None` if it cannot be found. On a kernel without NUMA support,
+/// everything is on node 0.
+///
+/// A page not yet touched is faulted in to find out where it goes.
+pub fn node_of(ptr: *const u8) -> Option<usize> {
+    let result = DefaultBackend {}.node_of(ptr);
+    match result {
+        Ok(node) => Some(node),
+        Err(error) if is_unsupported(&error) => Some(0),
+        Err(_) => None,
+    }
+}
+
+/// A `HeapGrower` that places new memory according to a [`NumaPolicy`],
+/// binding each mapping with `mbind` as it is made, so memory comes from the
+/// node that will use it, or is spread evenly over several.
+///
+/// On a kernel without NUMA support, mappings are left as they are, which on
+/// a single-node machine is where they would have gone anyway.
+pub struct NumaHeapGrower<B: MemoryBackend = DefaultBackend> {
+    backend: B,
+    policy: NumaPolicy,
+    mappings: Regions,
+    numa_available: bool,
+    bound_bytes: usize,
+    attempts: usize,
+    growths: usize,
+    peak: usize,
+}
+
+impl Default for NumaHeapGrower {
+    fn default() -> Self {
+        Self::new(NumaPolicy::Default)
+    }
+}
+
+impl NumaHeapGrower {
+    pub const fn new(policy: NumaPolicy) -> Self {
+        Self::with_backend(policy, DefaultBackend {})
+    }
+}
+
+impl<B: MemoryBackend> NumaHeapGrower<B> {
+    pub const fn with_backend(policy: NumaPolicy, backend: B) -> Self {
+        NumaHeapGrower {
+            backend,
+            policy,
+            mappings: Regions::new(),
+            numa_available: true,
+            bound_bytes: 0,
+            attempts: 0,
+            growths: 0,
+            peak: 0,
+        }
+    }
+
+    pub fn policy(&self) -> NumaPolicy {
+        self.policy
+    }
+
+    /// Changes the policy for memory mapped from now on. Memory already
+    /// mapped stays where it is.
+    pub fn set_policy(&mut self, policy: NumaPolicy) {
+        self.policy = policy;
+    }
+
+    /// False once `mbind` has been found not to be supported.
+    pub fn numa_available(&self) -> bool {
+        self.numa_available
+    }
+
+    /// Bytes mapped with the policy applied.
+    pub fn bound_bytes(&self) -> usize {
+        self.bound_bytes
+    }
+}
+
+impl<B: MemoryBackend> HeapGrower for NumaHeapGrower<B> {
+    type Err = AllocError;
+
+    unsafe fn grow_heap(&mut self, size: usize) -> Result<(*mut u8, usize), AllocError> {
+        self.attempts += 1;
+        let size = size.next_multiple_of(self.backend.page_size());
+        let ptr = self.backend.map(size, Protection::ReadWrite)?;
+        if self.numa_available {
+            let (mode, mask) = self.policy.mode_and_mask();
+            match self.backend.bind(ptr, size, mode, mask) {
+                Ok(()) => self.bound_bytes += size,
+                Err(error) if is_unsupported(&error) => self.numa_available = false,
+                Err(error) => {
+                    // E.g. a node that does not exist.
+                    let _ = self.backend.unmap(ptr, size);
+                    return Err(error);
+                }
+            }
+        }
+        self.mappings.insert(ptr, size);
+        self.growths += 1;
+        self.peak = self.peak.max(self.mappings.total_bytes());
+        Ok((ptr, size))
+    }
+
+    fn page_size(&self) -> usize {
+        self.backend.page_size()
+    }
+
+    /// Pages faulted back in after being reclaimed follow the same policy.
+    unsafe fn advise(&mut self, ptr: *mut u8, size: usize, advice: Advice) -> bool {
+        self.backend.advise(ptr, size, advice).is_ok()
+    }
+
+    fn stats(&self) -> GrowerStats {
+        GrowerStats {
+            attempts: self.attempts,
+            growths: self.growths,
+            mapped_bytes: self.mappings.total_bytes(),
+            peak_bytes: self.peak,
+            ..GrowerStats::default()
+        }
+    }
+}
+
+impl<B: MemoryBackend> Drop for NumaHeapGrower<B> {
+    fn drop(&mut self) {
+        for range in self.mappings.iter() {
+            let size = range.end as usize - range.start as usize;
+            unsafe {
+                let _ = self.backend.unmap(range.start as *mut u8, size);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocators::RawAlloc;
+    use core::alloc::Layout;
+    use test_log::test;
+
+    #[test]
+    fn test_placement() {
+        let page = DefaultBackend {}.page_size();
+        let layout = Layout::from_size_align(3 * page, 16).unwrap();
+        for policy in [
+            NumaPolicy::Default,
+            NumaPolicy::Bind(0),
+            NumaPolicy::Preferred(0),
+            NumaPolicy::Interleave(1),
+        ] {
+            let mut allocator = RawAlloc::new(NumaHeapGrower::new(policy));
+            unsafe {
+                let ptr = allocator.alloc(layout);
+                assert!(!ptr.is_null(), "{:?}", policy);
+                ptr.write_bytes(0x5A, layout.size());
+                // Only binding is strict: the other policies may place pages
+                // on other nodes, e.g. when node 0 is short of memory.
+                let node = node_of(ptr);
+                if policy == NumaPolicy::Bind(0) {
+                    assert_eq!(node, Some(0), "{:?}", policy);
+                } else {
+                    assert!(node.is_some(), "{:?}", policy);
+                }
+                allocator.dealloc(ptr, layout);
+            }
+            let grower = &allocator.grower;
+            if grower.numa_available() {
+                assert_eq!(grower.bound_bytes(), grower.stats().mapped_bytes);
+            }
+        }
+    }
+
+    #[test]
+    fn test_missing_node() {
+        let mut allocator = RawAlloc::new(NumaHeapGrower::new(NumaPolicy::Bind(MAX_NODES - 1)));
+        let layout = Layout::from_size_align(64, 16).unwrap();
+        let ptr = unsafe { allocator.alloc(layout) };
+        if allocator.grower.numa_available() {
+            assert!(ptr.is_null());
+            assert_eq!(allocator.grower.stats().mapped_bytes, 0);
+        } else {
+            assert!(!ptr.is_null());
+        }
+    }
+
+    #[test]
+    fn test_thread_policy() {
+        // Each test runs on a thread of its own.
+        let applied = NumaPolicy::Bind(0).apply_to_thread().unwrap();
+        let mut backend = DefaultBackend {};
+        unsafe {
+            let page = backend.map(backend.page_size(), Protection::ReadWrite).unwrap();
+            page.write(1);
+            assert_eq!(node_of(page), Some(0));
+            backend.unmap(page, backend.page_size()).unwrap();
+        }
+        assert_eq!(NumaPolicy::Default.apply_to_thread().unwrap(), applied);
+    }
+}
@@ -0,0 +1,393 @@
+use core::alloc::Layout;
+use core::fmt;
+use core::ptr::{null_mut, NonNull};
+
+use crate::allocators::alloc_error::AllocError;
+use crate::allocators::backend::{DefaultBackend, MemoryBackend, Protection};
+use crate::allocators::heap_grower::{GrowerStats, HeapGrower};
+use crate::allocators::raw_alloc::RawAlloc;
+use crate::allocators::regions::Regions;
+use crate::allocators::verify::HeapReport;
+use crate::blocklist::BlockList;
+
+/// The error type of the system calls underneath, as for `EnhancedHeapGrower`.
+pub type SystemError = AllocError;
+
+const MAGIC: [u8; 8] = *b"bsalloc\0";
+const VERSION: u32 = 2;
+
+/// The start of the file. All pointers are stored as offsets from the start
+/// of the file, except `base`, which is where the file was last mapped.
+///
+/// The header has a page to itself, so the heap starts page-aligned, and the
+/// file can only be opened where pages are the same size.
+#[repr(C)]
+struct Header {
+    magic: [u8; 8],
+    version: u32,
+    /// Non-zero while the heap has changed since it was last synced.
+    dirty: u32,
+    page_size: usize,
+    base: usize,
+    /// Bytes of the file in use, including the header.
+    len: usize,
+    /// Offset of the first free block, or 0.
+    free: usize,
+    /// Offset of the user's root object, or 0.
+    root: usize,
+}
+
+/// A `HeapGrower` over a file, so that the heap can outlive the process.
+///
+/// A range of address space is reserved up front, and the file is mapped
+/// shared over the start of it, so that growing the heap (by extending the
+/// file) always gives memory contiguous with what came before.
+pub struct FileHeapGrower<B: MemoryBackend = DefaultBackend> {
+    backend: B,
+    fd: i32,
+    base: *mut u8,
+    reserved: usize,
+    /// Bytes of the file mapped, including the header.
+    len: usize,
+    attempts: usize,
+    growths: usize,
+}
+
+impl<B: MemoryBackend> FileHeapGrower<B> {
+    /// Maps the first `len` bytes of `fd` into `reserved` bytes of address
+    /// space, at `hint` if possible.
+    unsafe fn map(mut backend: B, fd: i32, len: usize, reserved: usize, hint: *mut u8) -> Result<Self, SystemError> {
+        let base = backend.map_near(hint, reserved, Protection::None)?;
+        let mut grower = FileHeapGrower {
+            backend,
+            fd,
+            base,
+            reserved,
+            len,
+            attempts: 0,
+            growths: 0,
+        };
+        // From here on, dropping the grower releases the reservation.
+        grower.backend.map_file(base, len, fd, 0)?;
+        Ok(grower)
+    }
+
+    /// The address the start of the file is mapped at.
+    #[inline]
+    pub fn base(&self) -> *mut u8 {
+        self.base
+    }
+
+    /// Bytes of the file in use, including the header.
+    #[inline]
+    pub fn file_len(&self) -> usize {
+        self.len
+    }
+
+    /// Writes the mapped file back to disk.
+    pub fn sync(&mut self) -> Result<(), SystemError> {
+        unsafe { self.backend.sync(self.base, self.len) }
+    }
+}
+
+impl<B: MemoryBackend> HeapGrower for FileHeapGrower<B> {
+    type Err = SystemError;
+
+    unsafe fn grow_heap(&mut self, size: usize) -> Result<(*mut u8, usize), SystemError> {
+        self.attempts += 1;
+        let size = size.next_multiple_of(self.backend.page_size());
+        if size > self.reserved - self.len {
+            return Err(AllocError::Exhausted);
+        }
+        let ptr = self.base.add(self.len);
+        self.backend.set_file_len(self.fd, self.len + size)?;
+        self.backend.map_file(ptr, size, self.fd, self.len)?;
+        self.len += size;
+        self.growths += 1;
+        Ok((ptr, size))
+    }
+
+    fn stats(&self) -> GrowerStats {
+        GrowerStats {
+            attempts: self.attempts,
+            growths: self.growths,
+            mapped_bytes: self.len,
+            peak_bytes: self.len,
+            ..GrowerStats::default()
+        }
+    }
+
+    fn page_size(&self) -> usize {
+        self.backend.page_size()
+    }
+}
+
+impl<B: MemoryBackend> Drop for FileHeapGrower<B> {
+    fn drop(&mut self) {
+        // The file mappings replaced part of the reservation, so this
+        // releases both. The file descriptor belongs to the caller.
+        unsafe {
+            let _ = self.backend.unmap(self.base, self.reserved);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PersistentHeapError {
+    /// A system call failed.
+    System(SystemError),
+    /// The file is not empty, but does not hold a heap of this version, or was
+    /// made where pages are a different size.
+    NotAHeap,
+    /// The heap was changed and not synced before it was last closed, so
+    /// its free list cannot be trusted.
+    Unclean,
+    /// The file is larger than the address space reserved for it.
+    TooLarge { file: usize, reserved: usize },
+}
+
+impl From<SystemError> for PersistentHeapError {
+    fn from(error: SystemError) -> Self {
+        PersistentHeapError::System(error)
+    }
+}
+
+impl fmt::Display for PersistentHeapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PersistentHeapError::System(error) => write!(f, "{}", error),
+            PersistentHeapError::NotAHeap => f.write_str("file does not hold a heap"),
+            PersistentHeapError::Unclean => f.write_str("heap was not synced before it was closed"),
+            PersistentHeapError::TooLarge { file, reserved } => write!(
+                f,
+                "file of {} bytes does not fit in {} reserved bytes",
+                file, reserved
+            ),
+        }
+    }
+}
+
+/// A heap kept in a file, which can be closed and reopened later, or by
+/// another process.
+///
+/// The file may be mapped at a different address each time it is opened.
+/// The allocator's own state is relocated to match, but pointers stored in
+/// the heap are not, so data structures in it should link to each other by
+/// offset, with [`offset_of`](Self::offset_of) and [`at`](Self::at). The
+/// [`root`](Self::root) object, from which the rest can be found, is saved
+/// in the file's header.
+///
+/// The file is only consistent once [`sync`](Self::sync)ed, which happens
+/// on drop. A heap that was changed after its last sync is rejected when
+/// reopened.
+pub struct PersistentAlloc<B: MemoryBackend = DefaultBackend> {
+    raw: RawAlloc<FileHeapGrower<B>>,
+}
+
+impl PersistentAlloc {
+    /// Opens the heap in the file open for reading and writing on `fd`,
+    /// setting up a new one if the file is empty. The heap may grow to at
+    /// most `reserve` bytes, including a page for its header.
+    ///
+    /// # Safety
+    ///
+    /// Nothing else may use the file while it is open here.
+    pub unsafe fn open(fd: i32, reserve: usize) -> Result<Self, PersistentHeapError> {
+        Self::open_with_backend(fd, reserve, DefaultBackend {})
+    }
+}
+
+impl<B: MemoryBackend> PersistentAlloc<B> {
+    /// Like `open`, mapping the file through `backend`.
+    ///
+    /// # Safety
+    ///
+    /// As for `open`.
+    pub unsafe fn open_with_backend(fd: i32, reserve: usize, mut backend: B) -> Result<Self, PersistentHeapError> {
+        let page_size = backend.page_size();
+        let reserve = reserve.next_multiple_of(page_size);
+        let file_len = backend.file_len(fd)?;
+        if file_len == 0 {
+            return Self::create(backend, fd, reserve);
+        }
+        if file_len < page_size {
+            return Err(PersistentHeapError::NotAHeap);
+        }
+        if file_len > reserve {
+            return Err(PersistentHeapError::TooLarge {
+                file: file_len,
+                reserved: reserve,
+            });
+        }
+
+        // Read the header through a first mapping, to learn where the file
+        // would like to be.
+        let probe = backend.map_near(null_mut(), page_size, Protection::None)?;
+        let header = backend.map_file(probe, page_size, fd, 0).map(|()| (probe as *const Header).read());
+        let _ = backend.unmap(probe, page_size);
+        let header = header?;
+        if header.magic != MAGIC
+            || header.version != VERSION
+            || header.page_size != page_size
+            || header.len != file_len
+        {
+            return Err(PersistentHeapError::NotAHeap);
+        }
+        if header.dirty != 0 {
+            return Err(PersistentHeapError::Unclean);
+        }
+        let old_base = header.base as *mut u8;
+
+        let grower = FileHeapGrower::map(backend, fd, file_len, reserve, old_base)?;
+        let base = grower.base;
+        let header = &mut *(base as *mut Header);
+        let delta = (base as isize).wrapping_sub(old_base as isize);
+        let first = match header.free {
+            0 => None,
+            offset => Some(NonNull::new_unchecked(base.add(offset))),
+        };
+        let blocks = BlockList::adopt(first, delta);
+        let mut regions = Regions::new();
+        regions.insert(base.add(page_size), file_len - page_size);
+        header.base = base as usize;
+
+        Ok(PersistentAlloc {
+            raw: RawAlloc::adopt(grower, blocks, regions),
+        })
+    }
+
+    unsafe fn create(mut backend: B, fd: i32, reserve: usize) -> Result<Self, PersistentHeapError> {
+        let page_size = backend.page_size();
+        if reserve < page_size {
+            return Err(PersistentHeapError::TooLarge {
+                file: page_size,
+                reserved: reserve,
+            });
+        }
+        backend.set_file_len(fd, page_size)?;
+        let grower = FileHeapGrower::map(backend, fd, page_size, reserve, null_mut())?;
+        (grower.base as *mut Header).write(Header {
+            magic: MAGIC,
+            version: VERSION,
+            dirty: 1,
+            page_size,
+            base: grower.base as usize,
+            len: page_size,
+            free: 0,
+            root: 0,
+        });
+        let mut heap = PersistentAlloc {
+            raw: RawAlloc::new(grower),
+        };
+        heap.sync()?;
+        Ok(heap)
+    }
+
+    fn header(&self) -> &Header {
+        unsafe { &*(self.raw.grower.base as *const Header) }
+    }
+
+    fn header_mut(&mut self) -> &mut Header {
+        unsafe { &mut *(self.raw.grower.base as *mut Header) }
+    }
+
+    /// Marks the file as changed since the last sync.
+    #[inline]
+    fn touch(&mut self) {
+        if self.header().dirty == 0 {
+            self.header_mut().dirty = 1;
+        }
+    }
+
+    /// Saves the allocator's state in the header and writes the whole file
+    /// back to disk.
+    pub fn sync(&mut self) -> Result<(), PersistentHeapError> {
+        let base = self.raw.grower.base;
+        let free = self.raw.blocks.first_ptr();
+        let len = self.raw.grower.len;
+        let header = self.header_mut();
+        header.base = base as usize;
+        header.len = len;
+        header.free = free.map_or(0, |ptr| ptr.as_ptr() as usize - base as usize);
+        self.raw.grower.sync()?;
+
+        // Only mark the file clean once everything else is on disk.
+        self.header_mut().dirty = 0;
+        let page_size = self.raw.grower.page_size();
+        unsafe { self.raw.grower.backend.sync(base, page_size)? };
+        Ok(())
+    }
+
+    /// The offset of `ptr`, which must point into the heap, from the start of
+    /// the file. Offsets stay valid when the file is reopened elsewhere.
+    #[inline]
+    pub fn offset_of(&self, ptr: *const u8) -> usize {
+        ptr as usize - self.raw.grower.base as usize
+    }
+
+    /// The address of `offset` bytes into the file, as mapped now.
+    #[inline]
+    pub fn at(&self, offset: usize) -> *mut u8 {
+        self.raw.grower.base.wrapping_add(offset)
+    }
+
+    /// The root object, or null if none has been set.
+    pub fn root(&self) -> *mut u8 {
+        match self.header().root {
+            0 => null_mut(),
+            offset => self.at(offset),
+        }
+    }
+
+    /// Sets the root object, from which everything else in the heap should
+    /// be reachable. It is saved in the file's header.
+    pub fn set_root(&mut self, ptr: *mut u8) {
+        self.touch();
+        let offset = if ptr.is_null() { 0 } else { self.offset_of(ptr) };
+        self.header_mut().root = offset;
+    }
+
+    /// The allocator itself. Any use of it counts as a change to the heap.
+    pub fn allocator(&mut self) -> &mut RawAlloc<FileHeapGrower<B>> {
+        self.touch();
+        &mut self.raw
+    }
+
+    pub fn verify(&self) -> HeapReport {
+        self.raw.verify()
+    }
+
+    /// # Safety
+    ///
+    /// As for [`GlobalAlloc::alloc`](core::alloc::GlobalAlloc::alloc).
+    pub unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        self.allocator().alloc(layout)
+    }
+
+    /// # Safety
+    ///
+    /// As for [`GlobalAlloc::alloc_zeroed`](core::alloc::GlobalAlloc::alloc_zeroed).
+    pub unsafe fn calloc(&mut self, layout: Layout) -> *mut u8 {
+        self.allocator().calloc(layout)
+    }
+
+    /// # Safety
+    ///
+    /// As for [`GlobalAlloc::realloc`](core::alloc::GlobalAlloc::realloc).
+    pub unsafe fn realloc(&mut self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.allocator().realloc(ptr, layout, new_size)
+    }
+
+    /// # Safety
+    ///
+    /// As for [`GlobalAlloc::dealloc`](core::alloc::GlobalAlloc::dealloc).
+    pub unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        self.allocator().dealloc(ptr, layout)
+    }
+}
+
+impl<B: MemoryBackend> Drop for PersistentAlloc<B> {
+    fn drop(&mut self) {
+        let _ = self.sync();
+    }
+}
@@ -0,0 +1,669 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt;
+use core::sync::atomic::{AtomicIsize, AtomicU64, AtomicUsize, Ordering};
+
+use spin::Mutex;
+
+use crate::allocators::generic_allocator::GenericAllocator;
+use crate::allocators::HeapGrower;
+use crate::blocklist::BLOCK_ALIGN;
+
+/// Frames recorded per sample.
+pub const MAX_DEPTH: usize = 24;
+/// Samples that can be live at once; further samples are dropped and counted.
+pub const MAX_SAMPLES: usize = 512;
+/// Mean bytes between samples, matching tcmalloc and Go.
+pub const DEFAULT_PERIOD: usize = 512 * 1024;
+
+// Key values in `SamplingProfiler::keys` that are not addresses.
+const EMPTY: usize = 0;
+const REMOVED: usize = 1;
+// Slots a sample may be placed away from its hash; frees never probe further.
+const MAX_PROBES: usize = 32;
+
+/// Fills `frames` with return addresses, innermost first, returning how many
+/// were written. It must not allocate.
+pub type StackCapture = unsafe fn(&mut [usize]) -> usize;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    pub addr: usize,
+    pub size: usize,
+    pub depth: usize,
+    pub stack: [usize; MAX_DEPTH],
+}
+
+impl Sample {
+    const EMPTY: Sample = Sample {
+        addr: EMPTY,
+        size: 0,
+        depth: 0,
+        stack: [0; MAX_DEPTH],
+    };
+
+    pub fn frames(&self) -> &[usize] {
+        &self.stack[..self.depth]
+    }
+}
+
+/// Walks the frame pointer chain of the calling thread.
+///
+/// This is only reliable when every frame on the stack keeps a frame pointer,
+/// i.e. the whole program is built with `-C force-frame-pointers=yes`;
+/// otherwise the walk may stop early or read arbitrary memory.
+///
+/// # Safety
+///
+/// Every frame on the calling thread's stack, including those of the
+/// standard library and any foreign code, must keep a frame pointer, so that
+/// the chain only leads to readable stack memory.
+#[inline(never)]
+pub unsafe fn frame_pointer_backtrace(frames: &mut [usize]) -> usize {
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    unsafe {
+        // Frames are never this large; anything bigger is not a frame pointer.
+        const MAX_FRAME: usize = 1 << 20;
+
+        let mut fp: usize;
+        #[cfg(target_arch = "x86_64")]
+        core::arch::asm!("mov {}, rbp", out(reg) fp, options(nomem, nostack));
+        #[cfg(target_arch = "aarch64")]
+        core::arch::asm!("mov {}, x29", out(reg) fp, options(nomem, nostack));
+
+        let mut depth = 0;
+        while depth < frames.len() && fp != 0 && fp.is_multiple_of(core::mem::align_of::<usize>()) {
+            let record = fp as *const usize;
+            let next = *record;
+            let ret = *record.add(1);
+            if ret == 0 {
+                break;
+            }
+            frames[depth] = ret;
+            depth += 1;
+            if next <= fp || next - fp > MAX_FRAME {
+                break;
+            }
+            fp = next;
+        }
+        depth
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        let _ = frames;
+        0
+    }
+}
+
+/// Samples roughly one allocation per `period` bytes, on a Poisson schedule,
+/// and remembers each sampled allocation until it is freed.
+pub struct SamplingProfiler {
+    period: AtomicUsize,
+    countdown: AtomicIsize,
+    rng: AtomicU64,
+    capture: AtomicUsize,
+    live: AtomicUsize,
+    dropped: AtomicUsize,
+    // Open-addressed by address, so frees can check for a sample without
+    // taking the lock.
+    keys: [AtomicUsize; MAX_SAMPLES],
+    samples: Mutex<[Sample; MAX_SAMPLES]>,
+}
+
+impl Default for SamplingProfiler {
+    fn default() -> Self {
+        Self::new(DEFAULT_PERIOD)
+    }
+}
+
+impl SamplingProfiler {
+    /// Creates a profiler sampling once per `period` bytes on average; a
+    /// period of 0 disables sampling.
+    pub const fn new(period: usize) -> Self {
+        SamplingProfiler {
+            period: AtomicUsize::new(period),
+            // The first allocation draws a real interval.
+            countdown: AtomicIsize::new(0),
+            rng: AtomicU64::new(0x2545_F491_4F6C_DD1D),
+            capture: AtomicUsize::new(0),
+            live: AtomicUsize::new(0),
+            dropped: AtomicUsize::new(0),
+            keys: [const { AtomicUsize::new(EMPTY) }; MAX_SAMPLES],
+            samples: Mutex::new([Sample::EMPTY; MAX_SAMPLES]),
+        }
+    }
+
+    #[inline]
+    pub fn period(&self) -> usize {
+        self.period.load(Ordering::Relaxed)
+    }
+
+    pub fn set_period(&self, period: usize) {
+        self.period.store(period, Ordering::Relaxed);
+        self.countdown.store(0, Ordering::Relaxed);
+    }
+
+    /// Sets the function used to record stacks. Without one, samples are
+    /// recorded with empty stacks.
+    ///
+    /// # Safety
+    ///
+    /// `capture` must be safe to call from any allocation that reaches this
+    /// profiler, e.g. `frame_pointer_backtrace` only if its own requirements
+    /// hold for every thread that allocates.
+    pub unsafe fn set_stack_capture(&self, capture: Option<StackCapture>) {
+        self.capture
+            .store(capture.map_or(0, |f| f as usize), Ordering::Relaxed);
+    }
+
+    /// Number of sampled allocations that have not been freed.
+    #[inline]
+    pub fn live_samples(&self) -> usize {
+        self.live.load(Ordering::Relaxed)
+    }
+
+    /// Number of samples discarded because the sample table was full.
+    #[inline]
+    pub fn dropped_samples(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    #[inline(always)]
+    pub fn record_alloc(&self, ptr: *mut u8, size: usize) {
+        let size = size as isize;
+        if self.countdown.fetch_sub(size, Ordering::Relaxed) > size {
+            return;
+        }
+        self.sample(ptr, size as usize);
+    }
+
+    #[inline(always)]
+    pub fn record_dealloc(&self, ptr: *mut u8) {
+        if self.live.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        if let Some(slot) = self.find(ptr as usize) {
+            self.remove(slot, ptr as usize);
+        }
+    }
+
+    /// Copies out the sample in `slot`, if there is one.
+    pub fn sample_at(&self, slot: usize) -> Option<Sample> {
+        if self.keys[slot].load(Ordering::Relaxed) <= REMOVED {
+            return None;
+        }
+        let sample = self.samples.lock()[slot];
+        if sample.addr <= REMOVED {
+            None
+        } else {
+            Some(sample)
+        }
+    }
+
+    #[cold]
+    fn sample(&self, ptr: *mut u8, size: usize) {
+        let period = self.period();
+        if period == 0 {
+            self.countdown.store(isize::MAX, Ordering::Relaxed);
+            return;
+        }
+        self.countdown
+            .store(self.next_interval(period) as isize, Ordering::Relaxed);
+        if ptr.is_null() {
+            return;
+        }
+
+        let mut sample = Sample {
+            addr: ptr as usize,
+            size,
+            ..Sample::EMPTY
+        };
+        let capture = self.capture.load(Ordering::Relaxed);
+        if capture != 0 {
+            let capture: StackCapture = unsafe { core::mem::transmute(capture) };
+            // Safe to call here by the contract of `set_stack_capture`.
+            sample.depth = unsafe { capture(&mut sample.stack) }.min(MAX_DEPTH);
+        }
+
+        let mut samples = self.samples.lock();
+        let start = Self::hash(sample.addr);
+        for probe in 0..MAX_PROBES {
+            let slot = (start + probe) % MAX_SAMPLES;
+            if self.keys[slot].load(Ordering::Relaxed) <= REMOVED {
+                samples[slot] = sample;
+                self.keys[slot].store(sample.addr, Ordering::Release);
+                self.live.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+        }
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn find(&self, addr: usize) -> Option<usize> {
+        let start = Self::hash(addr);
+        for probe in 0..MAX_PROBES {
+            let slot = (start + probe) % MAX_SAMPLES;
+            match self.keys[slot].load(Ordering::Acquire) {
+                EMPTY => return None,
+                key if key == addr => return Some(slot),
+                _ => {}
+            }
+        }
+        None
+    }
+
+    #[cold]
+    fn remove(&self, slot: usize, addr: usize) {
+        let mut samples = self.samples.lock();
+        if self.keys[slot].load(Ordering::Relaxed) != addr {
+            return;
+        }
+        samples[slot] = Sample::EMPTY;
+        self.keys[slot].store(REMOVED, Ordering::Release);
+        self.live.fetch_sub(1, Ordering::Relaxed);
+
+        // Tombstones only keep later keys in the same run reachable. If the
+        // run ends here, this one and those just before it can be emptied.
+        if self.keys[(slot + 1) % MAX_SAMPLES].load(Ordering::Relaxed) == EMPTY {
+            let mut slot = slot;
+            while self.keys[slot].load(Ordering::Relaxed) == REMOVED {
+                self.keys[slot].store(EMPTY, Ordering::Release);
+                slot = (slot + MAX_SAMPLES - 1) % MAX_SAMPLES;
+            }
+        }
+    }
+
+    #[inline]
+    fn hash(addr: usize) -> usize {
+        // Blocks are aligned, so the low bits carry no information.
+        ((addr >> BLOCK_ALIGN.trailing_zeros()) as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) as usize % MAX_SAMPLES
+    }
+
+    /// Draws the number of bytes until the next sample from an exponential
+    /// distribution with mean `period`.
+    fn next_interval(&self, period: usize) -> usize {
+        // xorshift64*; races between threads only make it more random.
+        let mut x = self.rng.load(Ordering::Relaxed);
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.rng.store(x, Ordering::Relaxed);
+        let r = x.wrapping_mul(0x2545_F491_4F6C_DD1D);
+
+        // Uniform in (0, 1).
+        let u = ((r >> 11) as f64 + 0.5) / (1u64 << 53) as f64;
+        (-ln(u) * period as f64) as usize
+    }
+
+    /// Writes the live samples in the legacy gperftools heap profile format
+    /// (`heap_v2`), which `pprof` reads and unsamples itself. The
+    /// `MAPPED_LIBRARIES:` section is left for the caller to fill in (e.g.
+    /// from `/proc/self/maps`) if symbolization needs it.
+    pub fn write_heap_profile<W: fmt::Write>(&self, w: &mut W) -> fmt::Result {
+        let (count, bytes) = {
+            let samples = self.samples.lock();
+            samples
+                .iter()
+                .filter(|s| s.addr > REMOVED)
+                .fold((0usize, 0usize), |(c, b), s| (c + 1, b + s.size))
+        };
+        writeln!(
+            w,
+            "heap profile: {}: {} [{}: {}] @ heap_v2/{}",
+            count,
+            bytes,
+            count,
+            bytes,
+            self.period()
+        )?;
+
+        // Copy each sample out so `w` never runs with the lock held; it may
+        // well allocate through this profiler.
+        for sample in (0..MAX_SAMPLES).filter_map(|slot| self.sample_at(slot)) {
+            write!(w, "1: {} [1: {}] @", sample.size, sample.size)?;
+            for frame in sample.frames() {
+                write!(w, " {:#x}", frame)?;
+            }
+            writeln!(w)?;
+        }
+        writeln!(w)?;
+        writeln!(w, "MAPPED_LIBRARIES:")
+    }
+
+    /// Writes the live samples as an uncompressed pprof `Profile` protobuf,
+    /// with `inuse_objects` and `inuse_space` values already unsampled.
+    pub fn write_pprof<F: FnMut(&[u8])>(&self, out: &mut F) {
+        const STRINGS: [&str; 6] = ["", "inuse_objects", "count", "inuse_space", "bytes", "space"];
+        let mut proto = Proto(out);
+
+        for (kind, unit) in [(1, 2), (3, 4)] {
+            proto.message(1, &[(1, kind), (2, unit)]);
+        }
+
+        let period = self.period();
+        for (slot, sample) in (0..MAX_SAMPLES).filter_map(|slot| Some((slot, self.sample_at(slot)?))) {
+            let location_id = |frame: usize| (slot * MAX_DEPTH + frame + 1) as u64;
+
+            // An allocation of `size` bytes is sampled with probability
+            // 1 - exp(-size / period).
+            let scale = if period == 0 {
+                1.0
+            } else {
+                1.0 / (1.0 - exp(-(sample.size as f64) / period as f64))
+            };
+            let values = [(scale + 0.5) as u64, (sample.size as f64 * scale + 0.5) as u64];
+
+            let ids_len: usize = (0..sample.depth).map(|f| varint_len(location_id(f))).sum();
+            let values_len: usize = values.iter().map(|&v| varint_len(v)).sum();
+            let sample_len = 1 + varint_len(ids_len as u64) + ids_len
+                + 1 + varint_len(values_len as u64) + values_len;
+
+            proto.key(2, 2);
+            proto.varint(sample_len as u64);
+            proto.key(1, 2);
+            proto.varint(ids_len as u64);
+            for frame in 0..sample.depth {
+                proto.varint(location_id(frame));
+            }
+            proto.key(2, 2);
+            proto.varint(values_len as u64);
+            for &value in &values {
+                proto.varint(value);
+            }
+
+            for (frame, &address) in sample.frames().iter().enumerate() {
+                proto.message(4, &[(1, location_id(frame)), (3, address as u64)]);
+            }
+        }
+
+        for string in STRINGS {
+            proto.key(6, 2);
+            proto.varint(string.len() as u64);
+            (proto.0)(string.as_bytes());
+        }
+        proto.message(11, &[(1, 5), (2, 4)]);
+        proto.key(12, 0);
+        proto.varint(period as u64);
+    }
+}
+
+struct Proto<'a, F: FnMut(&[u8])>(&'a mut F);
+
+impl<F: FnMut(&[u8])> Proto<'_, F> {
+    fn varint(&mut self, mut value: u64) {
+        let mut buf = [0u8; 10];
+        let mut len = 0;
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                buf[len] = byte;
+                len += 1;
+                break;
+            }
+            buf[len] = byte | 0x80;
+            len += 1;
+        }
+        (self.0)(&buf[..len]);
+    }
+
+    fn key(&mut self, field: u64, wire_type: u64) {
+        self.varint(field << 3 | wire_type);
+    }
+
+    /// Writes a nested message made only of varint fields.
+    fn message(&mut self, field: u64, fields: &[(u64, u64)]) {
+        let len: usize = fields
+            .iter()
+            .map(|&(f, v)| varint_len(f << 3) + varint_len(v))
+            .sum();
+        self.key(field, 2);
+        self.varint(len as u64);
+        for &(f, v) in fields {
+            self.key(f, 0);
+            self.varint(v);
+        }
+    }
+}
+
+fn varint_len(value: u64) -> usize {
+    (64 - (value | 1).leading_zeros() as usize).div_ceil(7)
+}
+
+// `core` has no transcendental functions, so the two the sampler needs are
+// approximated here.
+
+const LN_2: f64 = core::f64::consts::LN_2;
+
+/// Natural logarithm of a positive, normal `x`.
+fn ln(x: f64) -> f64 {
+    let bits = x.to_bits();
+    let exponent = ((bits >> 52) & 0x7ff) as i64 - 1023;
+    // Mantissa in [1, 2).
+    let m = f64::from_bits((bits & ((1 << 52) - 1)) | (1023 << 52));
+    // ln(m) = 2 atanh(s), with s <= 1/3 so the series converges quickly.
+    let s = (m - 1.0) / (m + 1.0);
+    let s2 = s * s;
+    let mut term = s;
+    let mut sum = 0.0;
+    for k in 0..12 {
+        sum += term / (2 * k + 1) as f64;
+        term *= s2;
+    }
+    exponent as f64 * LN_2 + 2.0 * sum
+}
+
+/// e^x, for the non-positive `x` the sampler needs.
+fn exp(x: f64) -> f64 {
+    if x < -700.0 {
+        return 0.0;
+    }
+    // e^x = 2^k e^r, with r in [0, ln 2).
+    let k = (x / LN_2) as i64 - if x < 0.0 { 1 } else { 0 };
+    let r = x - k as f64 * LN_2;
+    let mut term = 1.0;
+    let mut sum = 1.0;
+    for n in 1..16 {
+        term *= r / n as f64;
+        sum += term;
+    }
+    sum * f64::from_bits(((k + 1023) as u64) << 52)
+}
+
+/// A `GenericAllocator` that reports a sample of its allocations to a
+/// `SamplingProfiler`. Unsampled allocations only pay for one atomic
+/// subtraction on top of the wrapped allocator.
+pub struct ProfiledAllocator<G: HeapGrower + Default> {
+    alloc: GenericAllocator<G>,
+    profiler: SamplingProfiler,
+}
+
+impl<G: HeapGrower + Default> Default for ProfiledAllocator<G> {
+    fn default() -> Self {
+        Self::new(DEFAULT_PERIOD)
+    }
+}
+
+impl<G: HeapGrower + Default> ProfiledAllocator<G> {
+    pub const fn new(period: usize) -> Self {
+        ProfiledAllocator {
+            alloc: GenericAllocator::new(),
+            profiler: SamplingProfiler::new(period),
+        }
+    }
+
+    #[inline(always)]
+    pub fn allocator(&self) -> &GenericAllocator<G> {
+        &self.alloc
+    }
+
+    #[inline(always)]
+    pub fn profiler(&self) -> &SamplingProfiler {
+        &self.profiler
+    }
+}
+
+unsafe impl<G: HeapGrower + Default> GlobalAlloc for ProfiledAllocator<G> {
+    #[inline(always)]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.alloc.alloc(layout);
+        self.profiler.record_alloc(ptr, layout.size());
+        ptr
+    }
+    #[inline(always)]
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.alloc.alloc_zeroed(layout);
+        self.profiler.record_alloc(ptr, layout.size());
+        ptr
+    }
+    #[inline(always)]
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = self.alloc.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            self.profiler.record_dealloc(ptr);
+            self.profiler.record_alloc(new_ptr, new_size);
+        }
+        new_ptr
+    }
+    #[inline(always)]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.profiler.record_dealloc(ptr);
+        self.alloc.dealloc(ptr, layout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocators::{OomAction, OomEvent, ToyHeap};
+    use test_log::test;
+
+    extern crate alloc;
+    use alloc::string::String;
+    use alloc::vec::Vec;
+
+    fn fake_stack(frames: &mut [usize]) -> usize {
+        frames[0] = 0x1000;
+        frames[1] = 0x2000;
+        2
+    }
+
+    #[test]
+    fn test_math() {
+        let cases = [(0.5, -core::f64::consts::LN_2), (1e-9, -20.723_265_836_946_41), (0.999, -0.001_000_500_333_583_5)];
+        for &(x, expected) in &cases {
+            assert!((ln(x) - expected).abs() < 1e-12, "ln({}) = {}", x, ln(x));
+        }
+        let cases = [(0.0, 1.0), (-1.0, 0.367_879_441_171_442_3), (-20.0, 2.061_153_622_438_558e-9)];
+        for &(x, expected) in &cases {
+            assert!(((exp(x) - expected) / expected).abs() < 1e-12, "exp({}) = {}", x, exp(x));
+        }
+    }
+
+    #[test]
+    fn test_sampling() {
+        let allocator: ProfiledAllocator<ToyHeap> = ProfiledAllocator::new(1);
+        unsafe { allocator.profiler().set_stack_capture(Some(fake_stack)) };
+
+        // With a 1-byte period, a 64-byte allocation goes unsampled with
+        // probability e^-64.
+        let layout = Layout::from_size_align(64, 16).unwrap();
+        let ptrs: Vec<*mut u8> = (0..8).map(|_| unsafe { allocator.alloc(layout) }).collect();
+        assert_eq!(allocator.profiler().live_samples(), 8);
+
+        for &ptr in &ptrs[..4] {
+            unsafe { allocator.dealloc(ptr, layout) };
+        }
+        let remaining = allocator.profiler().live_samples();
+        assert_eq!(remaining, 4);
+
+        let mut text = String::new();
+        allocator.profiler().write_heap_profile(&mut text).unwrap();
+        log::info!("{}", text);
+        let mut lines = text.lines();
+        assert_eq!(
+            lines.next().unwrap(),
+            alloc::format!("heap profile: {}: {} [{}: {}] @ heap_v2/1", remaining, remaining * 64, remaining, remaining * 64)
+        );
+        assert_eq!(lines.next().unwrap(), "1: 64 [1: 64] @ 0x1000 0x2000");
+
+        let mut proto = Vec::new();
+        allocator.profiler().write_pprof(&mut |bytes: &[u8]| proto.extend_from_slice(bytes));
+        // The first field is a length-delimited sample_type.
+        assert_eq!(&proto[..2], &[0x0a, 0x04]);
+        // Each remaining sample has two locations, each a field 4 message
+        // starting with its id.
+        let locations = proto
+            .windows(3)
+            .filter(|w| w[0] == 0x22 && (5..=6).contains(&w[1]) && w[2] == 0x08)
+            .count();
+        assert_eq!(locations, 2 * remaining);
+
+        for &ptr in &ptrs[4..] {
+            unsafe { allocator.dealloc(ptr, layout) };
+        }
+        assert_eq!(allocator.profiler().live_samples(), 0);
+    }
+
+    #[test]
+    fn test_tombstones() {
+        let allocator: ProfiledAllocator<ToyHeap> = ProfiledAllocator::new(1);
+        let profiler = allocator.profiler();
+        let layout = Layout::from_size_align(64, 16).unwrap();
+
+        let mut ptrs = Vec::new();
+        for _ in 0..20 {
+            ptrs.extend((0..100).map(|_| unsafe { allocator.alloc(layout) }));
+            // Free in a different order than allocated, leaving holes in
+            // the runs.
+            let (older, newer) = ptrs.split_at(ptrs.len() / 2);
+            for &ptr in older.iter().step_by(2).chain(newer.iter().rev()) {
+                unsafe { allocator.dealloc(ptr, layout) };
+            }
+            ptrs = older.iter().skip(1).step_by(2).copied().collect();
+            assert_eq!(profiler.live_samples(), ptrs.len());
+        }
+        for ptr in ptrs.drain(..) {
+            unsafe { allocator.dealloc(ptr, layout) };
+        }
+
+        assert_eq!(profiler.live_samples(), 0);
+        // Every tombstone was cleared once its run emptied.
+        assert!(profiler.keys.iter().all(|key| key.load(Ordering::Relaxed) == EMPTY));
+    }
+
+    static OOMS: AtomicUsize = AtomicUsize::new(0);
+    static CROSSINGS: AtomicUsize = AtomicUsize::new(0);
+
+    fn count_oom(_: &OomEvent) -> OomAction {
+        OOMS.fetch_add(1, Ordering::Relaxed);
+        OomAction::Fail
+    }
+
+    fn count_crossing(_: usize, _: usize) {
+        CROSSINGS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn test_budget() {
+        // The budget's handlers run just as they would without the profiler.
+        let allocator: ProfiledAllocator<ToyHeap> = ProfiledAllocator::new(1);
+        allocator.allocator().set_hard_limit(Some(1024));
+        allocator.allocator().set_soft_limit(Some(256), Some(count_crossing));
+        allocator.allocator().set_oom_handler(Some(count_oom));
+        let small = Layout::from_size_align(512, 16).unwrap();
+        let large = Layout::from_size_align(4096, 16).unwrap();
+        unsafe {
+            let ptr = allocator.alloc(small);
+            assert!(!ptr.is_null());
+            assert_eq!(CROSSINGS.load(Ordering::Relaxed), 1);
+            assert!(allocator.alloc(large).is_null());
+            assert!(allocator.alloc_zeroed(large).is_null());
+            assert!(allocator.realloc(ptr, small, large.size()).is_null());
+            assert_eq!(OOMS.load(Ordering::Relaxed), 3);
+            assert_eq!(allocator.profiler().live_samples(), 1);
+            allocator.dealloc(ptr, small);
+        }
+        assert_eq!(allocator.profiler().live_samples(), 0);
+    }
+}
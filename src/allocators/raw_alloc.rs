@@ -1,15 +1,32 @@
 use core::alloc::Layout;
+use core::fmt;
 use core::ptr::{null_mut, NonNull};
 use core::sync::atomic::{AtomicUsize, Ordering};
-use crate::blocklist::{BlockList, Stats, Validity};
+use crate::blocklist::{BlockList, Stats, Validity, BLOCK_ALIGN};
+use crate::allocators::alloc_error::{AllocError, Errno, Operation};
+use crate::allocators::backend;
+use crate::allocators::budget::Budget;
+use crate::allocators::decay::{Decay, DecayStats};
 use crate::allocators::heap_grower::HeapGrower;
+use crate::allocators::heap_map::{self, HeapMapFormat};
+use crate::allocators::regions::Regions;
+use crate::allocators::verify::{self, HeapReport};
+use crate::metrics::{self, AllocatorMetrics};
 
 #[repr(align(64))]
 pub struct RawAlloc<G: HeapGrower> {
     pub grower: G,
     pub blocks: BlockList,
+    pub regions: Regions,
+    /// Limits on live bytes, enforced on every allocation.
+    pub budget: Budget,
+    /// Purging of free pages that have gone unused for a while.
+    pub decay: Decay,
+    // Free memory faulted in by `reserve` and not handed out since.
+    prefaulted: Regions,
     allocation_counter: AtomicUsize,
     deallocation_counter: AtomicUsize,
+    live_bytes: AtomicUsize,
 }
 
 impl<G: HeapGrower> Drop for RawAlloc<G> {
@@ -26,20 +43,53 @@ impl<G: HeapGrower + Default> Default for RawAlloc<G> {
         RawAlloc {
             grower: G::default(),
             blocks: BlockList::default(),
+            regions: Regions::new(),
+            budget: Budget::unlimited(),
+            decay: Decay::disabled(),
+            prefaulted: Regions::new(),
             allocation_counter: AtomicUsize::new(0),
             deallocation_counter: AtomicUsize::new(0),
+            live_bytes: AtomicUsize::new(0),
         }
     }
 }
 
 impl<G: HeapGrower> RawAlloc<G> {
     #[inline(always)]
-    pub fn new(grower: G) -> Self {
+    pub const fn new(grower: G) -> Self {
         RawAlloc {
             grower,
-            blocks: BlockList::default(),
+            blocks: BlockList::new(),
+            regions: Regions::new(),
+            budget: Budget::unlimited(),
+            decay: Decay::disabled(),
+            prefaulted: Regions::new(),
+            allocation_counter: AtomicUsize::new(0),
+            deallocation_counter: AtomicUsize::new(0),
+            live_bytes: AtomicUsize::new(0),
+        }
+    }
+
+    /// An allocator over memory that is already in use, such as a heap
+    /// reloaded from a file: `regions` is everything `grower` has handed out,
+    /// and whatever is not in `blocks` is counted as live.
+    ///
+    /// # Safety
+    ///
+    /// `blocks` must be a valid free list within `regions`.
+    pub unsafe fn adopt(grower: G, blocks: BlockList, regions: Regions) -> Self {
+        let free: usize = blocks.iter().map(|b| b.size()).sum();
+        let live = regions.total_bytes() - free;
+        RawAlloc {
+            grower,
+            blocks,
+            regions,
+            budget: Budget::unlimited(),
+            decay: Decay::disabled(),
+            prefaulted: Regions::new(),
             allocation_counter: AtomicUsize::new(0),
             deallocation_counter: AtomicUsize::new(0),
+            live_bytes: AtomicUsize::new(live),
         }
     }
 
@@ -48,10 +98,45 @@ impl<G: HeapGrower> RawAlloc<G> {
         self.blocks.stats()
     }
 
+    /// Checks the free list against the regions obtained from the grower and
+    /// the bytes currently handed out, returning every inconsistency found.
+    pub fn verify(&self) -> HeapReport {
+        verify::verify(&self.regions, &self.blocks, self.live_bytes())
+    }
+
+    /// Copies the allocator's statistics, including the grower's, into plain
+    /// values.
+    pub fn metrics(&self) -> AllocatorMetrics {
+        let (validity, stats) = self.stats();
+        AllocatorMetrics {
+            live_bytes: self.live_bytes(),
+            regions: self.regions.len(),
+            region_bytes: self.regions.total_bytes(),
+            allocations: self.allocation_count(),
+            deallocations: self.deallocation_count(),
+            prefaulted_bytes: self.prefaulted_bytes(),
+            grower: self.grower.stats(),
+            decay: self.decay.stats(),
+            ..AllocatorMetrics::default()
+        }
+        .with_blocks(&validity, &stats)
+    }
+
+    /// Writes `metrics()` as OpenMetrics text, without allocating.
+    pub fn write_openmetrics<W: fmt::Write>(&self, w: &mut W) -> fmt::Result {
+        metrics::write_openmetrics(w, None, &[self.metrics()])
+    }
+
+    /// Writes every region obtained from the grower, split into used and free
+    /// segments, to `w`.
+    pub fn write_heap_map<W: fmt::Write>(&self, w: &mut W, format: HeapMapFormat) -> fmt::Result {
+        heap_map::write_heap_map(w, &self.regions, &self.blocks, format)
+    }
+
     #[inline(always)]
     pub fn block_size(layout: Layout) -> usize {
         let aligned_layout = layout
-            .align_to(16)
+            .align_to(BLOCK_ALIGN)
             .expect("Alignment failed")
             .pad_to_align();
         aligned_layout.size()
@@ -66,6 +151,30 @@ impl<G: HeapGrower> RawAlloc<G> {
         let count = self.deallocation_counter.load(Ordering::Relaxed);
         count
     }
+    /// Bytes faulted in by `reserve` that are still free and have not been
+    /// handed out since.
+    #[inline]
+    pub fn prefaulted_bytes(&self) -> usize {
+        self.prefaulted.total_bytes()
+    }
+
+    /// Bytes currently handed out, in block sizes.
+    #[inline]
+    pub fn live_bytes(&self) -> usize {
+        self.live_bytes.load(Ordering::Relaxed)
+    }
+
+    #[inline(always)]
+    fn charge(&mut self, bytes: usize) {
+        let live = self.live_bytes.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        self.budget.update(live);
+    }
+
+    #[inline(always)]
+    fn credit(&mut self, bytes: usize) {
+        let live = self.live_bytes.fetch_sub(bytes, Ordering::Relaxed) - bytes;
+        self.budget.update(live);
+    }
 
     #[inline(always)]
     unsafe fn try_expand_allocation(
@@ -74,41 +183,96 @@ impl<G: HeapGrower> RawAlloc<G> {
         old_size: usize,
         new_block_size: usize
     ) -> Option<*mut u8> {
-        if let Some(block) = self.blocks
-            .iter()
-            .find(|block| block.as_range().start as *mut u8 == ptr.add(old_size))
-        {
-            let block_size = block.size();
-            if old_size.wrapping_add(block_size) >= new_block_size {
-                self.blocks.pop_size(block_size);
-                return Some(ptr);
-            }
+        let needed = new_block_size.wrapping_sub(old_size);
+        if !self.budget.admits(self.live_bytes(), needed) {
+            return None;
         }
-        None
+        let claimed = self.blocks.claim(ptr.add(old_size), needed)?;
+        // What is left of the block gets a new header just past the claim.
+        self.reuse(ptr.add(old_size), claimed + BlockList::header_size());
+        self.charge(claimed);
+        Some(ptr)
     }
 
     #[inline(always)]
     pub unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
-        self.allocation_counter.fetch_add(1, Ordering::Relaxed);
+        self.try_alloc(layout).map_or(null_mut(), NonNull::as_ptr)
+    }
+
+    /// Like `alloc`, but says why it failed.
+    ///
+    /// # Safety
+    ///
+    /// As for `GlobalAlloc::alloc`.
+    #[inline(always)]
+    pub unsafe fn try_alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        if layout.align() > BLOCK_ALIGN {
+            return self.alloc_overaligned(layout);
+        }
         let needed_size = Self::block_size(layout);
+        if !self.budget.admits(self.live_bytes(), needed_size) {
+            self.budget.deny();
+            return Err(AllocError::LimitExceeded {
+                requested: needed_size,
+                used: self.live_bytes(),
+                limit: self.budget.hard_limit().unwrap_or(usize::MAX),
+            });
+        }
+        self.allocation_counter.fetch_add(1, Ordering::Relaxed);
+        self.decay_tick();
 
         if let Some(range) = self.blocks.pop_size(needed_size) {
-            return range.start.as_ptr();
+            self.reuse(range.start.as_ptr(), needed_size);
+            self.charge(needed_size);
+            return Ok(range.start);
         }
 
-        match self.grower.grow_heap(needed_size) {
-            Err(_) => {
+        if let Err(error) = self.make_room() {
+            self.allocation_counter.fetch_sub(1, Ordering::Relaxed);
+            return Err(error);
+        }
+        let page_size = self.grower.page_size();
+        match self.grower.grow_heap(needed_size.div_ceil(page_size) * page_size) {
+            Err(error) => {
                 self.allocation_counter.fetch_sub(1, Ordering::Relaxed);
-                null_mut()
+                Err(error.into())
             },
             Ok((ptr, size)) => {
+                self.regions.insert(ptr, size);
                 if size >= needed_size.wrapping_add(BlockList::header_size()) {
                     let free_ptr = NonNull::new_unchecked(ptr.add(needed_size));
                     self.blocks.add_block(free_ptr, size.wrapping_sub(needed_size));
+                    self.charge(needed_size);
+                } else {
+                    // A tail too small to hold a header can never be reused.
+                    self.charge(size);
                 }
-                ptr
+                Ok(NonNull::new_unchecked(ptr))
+            }
+        }
+    }
+
+    /// Blocks are only ever `BLOCK_ALIGN`-aligned, so for larger alignments this
+    /// allocates enough to find an aligned block inside, and frees the rest.
+    #[cold]
+    unsafe fn alloc_overaligned(&mut self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        let needed_size = Self::block_size(layout);
+        let slack = layout.align() - BLOCK_ALIGN;
+        let ptr = self
+            .try_alloc(Layout::from_size_align_unchecked(needed_size + slack, BLOCK_ALIGN))?
+            .as_ptr();
+
+        // Both pieces are multiples of `BLOCK_ALIGN`, so either empty or large enough
+        // for a header.
+        let padding = ptr.align_offset(layout.align());
+        let aligned = ptr.add(padding);
+        for (start, size) in [(ptr, padding), (aligned.add(needed_size), slack - padding)] {
+            if size > 0 {
+                self.blocks.add_block(NonNull::new_unchecked(start), size);
+                self.credit(size);
             }
         }
+        Ok(NonNull::new_unchecked(aligned))
     }
 
     #[inline(always)]
@@ -122,38 +286,59 @@ impl<G: HeapGrower> RawAlloc<G> {
 
     #[inline(always)]
     pub unsafe fn realloc(&mut self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
-        if ptr.is_null() {
-            return self.alloc(Layout::from_size_align_unchecked(new_size, layout.align()));
-        }
+        self.try_realloc(ptr, layout, new_size).map_or(null_mut(), NonNull::as_ptr)
+    }
+
+    /// Like `realloc`, but says why it failed. As with `realloc`, the old
+    /// allocation is untouched on failure.
+    ///
+    /// # Safety
+    ///
+    /// As for `GlobalAlloc::realloc`.
+    #[inline(always)]
+    pub unsafe fn try_realloc(
+        &mut self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<NonNull<u8>, AllocError> {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        let Some(old_ptr) = NonNull::new(ptr) else {
+            return self.try_alloc(new_layout);
+        };
 
         let old_size = Self::block_size(layout);
-        let new_block_size = Self::round_up(new_size, 16);
+        let new_block_size = Self::block_size(new_layout);
 
         if new_block_size <= old_size {
             if new_block_size.wrapping_add(BlockList::header_size()) <= old_size {
                 let free_ptr = NonNull::new_unchecked(ptr.add(new_block_size));
                 self.blocks.add_block(free_ptr, old_size.wrapping_sub(new_block_size));
+                self.credit(old_size.wrapping_sub(new_block_size));
             }
-            return ptr;
+            return Ok(old_ptr);
         }
 
         if let Some(expanded_ptr) = self.try_expand_allocation(ptr, old_size, new_block_size) {
-            return expanded_ptr;
+            return Ok(NonNull::new_unchecked(expanded_ptr));
         }
 
         self.allocation_counter.fetch_add(1, Ordering::Relaxed);
-        let new_ptr = self.alloc(Layout::from_size_align_unchecked(new_size, layout.align()));
-        if !new_ptr.is_null() {
-            core::ptr::copy_nonoverlapping(
-                ptr,
-                new_ptr,
-                core::cmp::min(old_size, new_block_size)
-            );
-            self.dealloc(ptr, layout);
-        } else {
-            self.allocation_counter.fetch_sub(1, Ordering::Relaxed);
+        match self.try_alloc(new_layout) {
+            Ok(new_ptr) => {
+                core::ptr::copy_nonoverlapping(
+                    ptr,
+                    new_ptr.as_ptr(),
+                    core::cmp::min(old_size, new_block_size)
+                );
+                self.dealloc(ptr, layout);
+                Ok(new_ptr)
+            }
+            Err(error) => {
+                self.allocation_counter.fetch_sub(1, Ordering::Relaxed);
+                Err(error)
+            }
         }
-        new_ptr
     }
 
     #[inline(always)]
@@ -187,12 +372,161 @@ impl<G: HeapGrower> RawAlloc<G> {
         core::ptr::write_bytes(ptr, 0, size);
 
         self.blocks.add_block(NonNull::new_unchecked(ptr), size);
+        self.credit(size);
+        self.decay_tick();
+
+        if let Some(threshold) = self.grower.trim_threshold() {
+            // `ptr` may have been merged with its neighbours.
+            let ptr = ptr as *const u8;
+            let merged = self
+                .blocks
+                .iter()
+                .map(|b| b.as_range())
+                .find(|r| r.start <= ptr && ptr < r.end);
+            if let Some(block) = merged {
+                let size = block.end as usize - block.start as usize;
+                let at_top = self.regions.find(block.start, size).is_some_and(|r| r.end == block.end);
+                if size >= threshold && at_top {
+                    self.release(block.start as *mut u8, size);
+                }
+            }
+        }
+    }
+
+    /// Gives the free block at the top of the heap back to the grower, as far
+    /// as the grower can take it. Returns the number of bytes given back.
+    pub fn trim(&mut self) -> usize {
+        let Some(top) = self.blocks.iter().last().map(|b| b.as_range()) else {
+            return 0;
+        };
+        unsafe { self.release(top.start as *mut u8, top.end as usize - top.start as usize) }
+    }
+
+    /// Offers the whole pages in the free block `[ptr, ptr + size)` to
+    /// `shrink_heap`.
+    unsafe fn release(&mut self, ptr: *mut u8, size: usize) -> usize {
+        // Growers hand out whole pages, so the end of a region is on a page.
+        let page_size = self.grower.page_size();
+        let start = ptr.align_offset(page_size);
+        if start >= size || ptr.add(size).align_offset(page_size) != 0 {
+            return 0;
+        }
+        // Detach the block first: its header may be in memory the grower
+        // gives back.
+        if self.blocks.claim(ptr, size) != Some(size) {
+            return 0;
+        }
+        let released = self.grower.shrink_heap(ptr.add(start), size - start);
+        let kept = size - released;
+        self.reuse(ptr.add(kept), released);
+        if kept > 0 {
+            self.blocks.add_block(NonNull::new_unchecked(ptr), kept);
+        }
+        self.regions.remove(ptr.add(kept), released);
+        released
+    }
+
+    /// Makes room in `regions` to record a growth before growing, so that
+    /// every block handed out is in a recorded region, and those who free it
+    /// can tell where it came from.
+    fn make_room(&mut self) -> Result<(), AllocError> {
+        if self.regions.reserve_one() {
+            return Ok(());
+        }
+        Err(AllocError::System {
+            op: Operation::Mmap,
+            errno: Errno::ENOMEM,
+        })
+    }
+
+    /// Makes sure at least `bytes` of free memory are ready, growing the heap
+    /// if there is less, and faults in every free page. Allocations that fit
+    /// in that memory then need neither system calls nor page faults, as
+    /// long as decay is off and the grower does not trim. Returns
+    /// `prefaulted_bytes()`.
+    pub fn reserve(&mut self, bytes: usize) -> Result<usize, AllocError> {
+        let free: usize = self.blocks.iter().map(|b| b.size()).sum();
+        let page_size = self.grower.page_size();
+        if free < bytes {
+            self.make_room()?;
+            let (ptr, size) =
+                unsafe { self.grower.grow_heap((bytes - free).next_multiple_of(page_size)) }.map_err(Into::into)?;
+            self.regions.insert(ptr, size);
+            unsafe { self.blocks.add_block(NonNull::new_unchecked(ptr), size) };
+        }
+
+        // Afterwards, the pre-faulted memory is just the free memory.
+        self.prefaulted = Regions::new();
+        for block in self.blocks.iter() {
+            let range = block.as_range();
+            let size = range.end as usize - range.start as usize;
+            unsafe { backend::touch(range.start as *mut u8, size, page_size) };
+            self.prefaulted.insert(range.start, size);
+        }
+        // Purged pages are back in use; decay picks them up again as dirty.
+        self.decay.forget(0, usize::MAX);
+        Ok(self.prefaulted_bytes())
+    }
+
+    /// Runs a decay step now, releasing every whole free page to the system
+    /// through `HeapGrower::advise`, whether or not decay is enabled.
+    /// Returns the stats after the purge.
+    pub fn purge(&mut self) -> DecayStats {
+        let stats = unsafe { self.decay.step(&self.blocks, &mut self.grower, true) };
+        self.forget_purged();
+        stats
     }
 
     #[inline(always)]
-    const fn round_up(value: usize, increment: usize) -> usize {
-        value.wrapping_add(increment.wrapping_sub(1))
-            .wrapping_div(increment)
-            .wrapping_mul(increment)
+    fn decay_tick(&mut self) {
+        if self.decay.tick() {
+            unsafe { self.decay.step(&self.blocks, &mut self.grower, false) };
+            self.forget_purged();
+        }
+    }
+
+    /// Stops counting pages decay has advised away as pre-faulted.
+    fn forget_purged(&mut self) {
+        if self.prefaulted.is_empty() {
+            return;
+        }
+        for (start, end) in self.decay.purged() {
+            unfault(&mut self.prefaulted, start, end);
+        }
+    }
+
+    /// Tells decay that the free memory `[ptr, ptr + size)` is about to be
+    /// written to or given back, along with the rest of its pages.
+    #[inline(always)]
+    fn reuse(&mut self, ptr: *mut u8, size: usize) {
+        if !self.prefaulted.is_empty() {
+            unfault(&mut self.prefaulted, ptr as usize, ptr as usize + size);
+        }
+        if self.decay.stats() == DecayStats::default() {
+            return;
+        }
+        let page_size = self.grower.page_size();
+        let start = ptr as usize / page_size * page_size;
+        let end = (ptr as usize + size).next_multiple_of(page_size);
+        self.decay.forget(start, end);
+    }
+}
+
+/// Removes `[start, end)` from `prefaulted`, wherever it overlaps.
+fn unfault(prefaulted: &mut Regions, start: usize, end: usize) {
+    loop {
+        let overlap = prefaulted
+            .iter()
+            .map(|r| (r.start as usize, r.end as usize))
+            .find(|&(s, e)| s < end && start < e);
+        let Some((s, e)) = overlap else {
+            return;
+        };
+        let (lo, hi) = (s.max(start), e.min(end));
+        // Splitting a region needs a free entry; without one, the whole
+        // region goes.
+        if !prefaulted.remove(lo as *const u8, hi - lo) {
+            prefaulted.remove(s as *const u8, e - s);
+        }
     }
 }
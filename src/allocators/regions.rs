@@ -0,0 +1,279 @@
+#[cfg(not(target_os = "none"))]
+use core::mem::size_of;
+use core::ops::Range;
+use core::ptr::null_mut;
+use core::slice;
+
+#[cfg(not(target_os = "none"))]
+use crate::allocators::backend::{DefaultBackend, MemoryBackend, Protection};
+
+// Growers are expected to hand out few, large regions, which fit inline.
+const INLINE_REGIONS: usize = 64;
+
+type Entry = (usize, usize);
+
+/// A sorted record of the memory regions handed out by a `HeapGrower`.
+/// Adjacent regions are coalesced, so contiguous growers (such as `ToyHeap`)
+/// only ever use a single entry.
+///
+/// The first 64 regions are kept inline. Past those, the registry moves to
+/// memory mapped for it, doubling as it fills. Only if that mapping fails is
+/// a region left out, and counted in `untracked_bytes` instead. Without an OS
+/// there is nothing to map, and only the inline regions are kept.
+#[derive(Debug)]
+pub struct Regions {
+    inline: [Entry; INLINE_REGIONS],
+    // All the entries, once there are more than fit inline; `capacity` of
+    // them fit.
+    spilled: *mut Entry,
+    capacity: usize,
+    len: usize,
+    untracked: usize,
+}
+
+// The mapped entries belong to the registry alone.
+unsafe impl Send for Regions {}
+unsafe impl Sync for Regions {}
+
+impl Default for Regions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Regions {
+    pub const fn new() -> Self {
+        Regions {
+            inline: [(0, 0); INLINE_REGIONS],
+            spilled: null_mut(),
+            capacity: INLINE_REGIONS,
+            len: 0,
+            untracked: 0,
+        }
+    }
+
+    #[inline]
+    fn ranges(&self) -> &[Entry] {
+        if self.spilled.is_null() {
+            &self.inline[..self.len]
+        } else {
+            unsafe { slice::from_raw_parts(self.spilled, self.len) }
+        }
+    }
+
+    /// Room for `capacity` entries, of which the first `len` are in use.
+    #[inline]
+    fn storage(&mut self) -> &mut [Entry] {
+        if self.spilled.is_null() {
+            &mut self.inline
+        } else {
+            unsafe { slice::from_raw_parts_mut(self.spilled, self.capacity) }
+        }
+    }
+
+    /// Makes room for one more entry, moving the entries to a larger mapping
+    /// if need be. Returns false if there is no memory for one.
+    pub(crate) fn reserve_one(&mut self) -> bool {
+        if self.len < self.capacity {
+            return true;
+        }
+        #[cfg(not(target_os = "none"))]
+        {
+            let mut backend = DefaultBackend::default();
+            let bytes = (2 * self.capacity * size_of::<Entry>()).next_multiple_of(backend.page_size());
+            let Ok(ptr) = (unsafe { backend.map(bytes, Protection::ReadWrite) }) else {
+                return false;
+            };
+            let spilled = ptr as *mut Entry;
+            unsafe {
+                spilled.copy_from_nonoverlapping(self.ranges().as_ptr(), self.len);
+                self.unmap_spilled(&mut backend);
+            }
+            self.spilled = spilled;
+            self.capacity = bytes / size_of::<Entry>();
+            true
+        }
+
+        #[cfg(target_os = "none")]
+        false
+    }
+
+    #[cfg(not(target_os = "none"))]
+    unsafe fn unmap_spilled(&mut self, backend: &mut DefaultBackend) {
+        if !self.spilled.is_null() {
+            let _ = backend.unmap(self.spilled as *mut u8, self.capacity * size_of::<Entry>());
+        }
+    }
+
+    /// Records `[ptr, ptr + size)`. Returns false if the region could not be
+    /// recorded because the registry is full.
+    pub fn insert(&mut self, ptr: *const u8, size: usize) -> bool {
+        if size == 0 {
+            return true;
+        }
+        let start = ptr as usize;
+        let end = start.wrapping_add(size);
+
+        let len = self.len;
+        let ix = self.ranges().partition_point(|&(s, _)| s <= start);
+        let joins_prev = ix > 0 && self.ranges()[ix - 1].1 == start;
+        let joins_next = ix < len && self.ranges()[ix].0 == end;
+
+        if !joins_prev && !joins_next && !self.reserve_one() {
+            self.untracked = self.untracked.wrapping_add(size);
+            return false;
+        }
+        let ranges = self.storage();
+        match (joins_prev, joins_next) {
+            (true, true) => {
+                ranges[ix - 1].1 = ranges[ix].1;
+                ranges.copy_within(ix + 1..len, ix);
+                self.len -= 1;
+            }
+            (true, false) => ranges[ix - 1].1 = end,
+            (false, true) => ranges[ix].0 = start,
+            (false, false) => {
+                ranges.copy_within(ix..len, ix + 1);
+                ranges[ix] = (start, end);
+                self.len += 1;
+            }
+        }
+        true
+    }
+
+    /// Forgets `[ptr, ptr + size)`, which must lie within a single region, e.g.
+    /// after the grower gave it back. Returns false if it does not, or if
+    /// the region would have to be split and there is no memory for another
+    /// entry.
+    pub fn remove(&mut self, ptr: *const u8, size: usize) -> bool {
+        if size == 0 {
+            return true;
+        }
+        let start = ptr as usize;
+        let end = start.wrapping_add(size);
+        let Some(ix) = self.index(start, end) else {
+            return false;
+        };
+
+        let len = self.len;
+        let (s, e) = self.ranges()[ix];
+        if s != start && e != end && !self.reserve_one() {
+            return false;
+        }
+        let ranges = self.storage();
+        match (s == start, e == end) {
+            (true, true) => {
+                ranges.copy_within(ix + 1..len, ix);
+                self.len -= 1;
+            }
+            (true, false) => ranges[ix].0 = end,
+            (false, true) => ranges[ix].1 = start,
+            (false, false) => {
+                ranges.copy_within(ix + 1..len, ix + 2);
+                ranges[ix] = (s, start);
+                ranges[ix + 1] = (end, e);
+                self.len += 1;
+            }
+        }
+        true
+    }
+
+    /// Returns the region containing all of `[ptr, ptr + size)`, if any.
+    pub fn find(&self, ptr: *const u8, size: usize) -> Option<Range<*const u8>> {
+        let start = ptr as usize;
+        let end = start.wrapping_add(size);
+        let (s, e) = self.ranges()[self.index(start, end)?];
+        Some(s as *const u8..e as *const u8)
+    }
+
+    /// The index of the region containing all of `[start, end)`, if any.
+    #[inline]
+    fn index(&self, start: usize, end: usize) -> Option<usize> {
+        let ranges = self.ranges();
+        // Regions are sorted and disjoint, so only the last one starting at
+        // or before `start` can hold it.
+        let ix = ranges.partition_point(|&(s, _)| s <= start).checked_sub(1)?;
+        (end <= ranges[ix].1).then_some(ix)
+    }
+
+    #[inline]
+    pub fn contains(&self, ptr: *const u8, size: usize) -> bool {
+        self.find(ptr, size).is_some()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Range<*const u8>> + '_ {
+        self.ranges()
+            .iter()
+            .map(|&(s, e)| s as *const u8..e as *const u8)
+    }
+
+    /// Total number of bytes in tracked regions.
+    pub fn total_bytes(&self) -> usize {
+        self.ranges()
+            .iter()
+            .fold(0, |acc, &(s, e)| acc.wrapping_add(e.wrapping_sub(s)))
+    }
+
+    /// Bytes handed out by the grower that could not be recorded, as there
+    /// was no memory to grow the registry.
+    #[inline]
+    pub fn untracked_bytes(&self) -> usize {
+        self.untracked
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[cfg(not(target_os = "none"))]
+impl Drop for Regions {
+    fn drop(&mut self) {
+        unsafe { self.unmap_spilled(&mut DefaultBackend::default()) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_log::test;
+
+    fn page(k: usize) -> *const u8 {
+        (0x10000 + k * 0x1000) as *const u8
+    }
+
+    #[test]
+    fn test_grows() {
+        let mut regions = Regions::new();
+        // Every other page, so that none coalesce, inserted from both ends.
+        let count = 8 * INLINE_REGIONS + 1;
+        let evens = (0..count).step_by(2);
+        for k in evens.clone().rev().step_by(2).chain(evens.clone().skip(1).step_by(2)) {
+            assert!(regions.insert(page(k), 0x1000));
+        }
+        let inserted = count.div_ceil(2);
+        assert_eq!(regions.len(), inserted);
+        assert_eq!(regions.total_bytes(), inserted * 0x1000);
+        assert_eq!(regions.untracked_bytes(), 0);
+        assert!(regions.iter().zip(regions.iter().skip(1)).all(|(a, b)| a.end < b.start));
+
+        assert!(regions.contains(page(count - 1), 0x1000));
+        assert!(!regions.contains(page(count - 1), 0x1001));
+        assert!(!regions.contains(page(count - 2), 16));
+
+        // Filling the gaps joins them all.
+        for k in (1..count).step_by(2) {
+            assert!(regions.insert(page(k), 0x1000));
+        }
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions.find(page(7), 16), Some(page(0)..page(count)));
+        assert!(regions.remove(page(1), 0x1000));
+        assert_eq!(regions.len(), 2);
+    }
+}
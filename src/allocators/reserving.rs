@@ -0,0 +1,252 @@
+use core::ptr::null_mut;
+
+use crate::allocators::alloc_error::AllocError;
+use crate::allocators::backend::{DefaultBackend, MemoryBackend, Protection};
+use crate::allocators::decay::Advice;
+use crate::allocators::heap_grower::{GrowerStats, HeapGrower};
+use crate::allocators::regions::Regions;
+
+/// The address space a `ReservingHeapGrower` reserves by default. Only pages
+/// that are committed cost memory.
+pub const DEFAULT_RESERVATION: usize = 1 << 30;
+
+/// A `HeapGrower` that reserves one large range of address space up front,
+/// and commits pages from it as the heap grows.
+///
+/// Every growth lands right after the previous one, so free blocks merge
+/// across growths, and growing costs one `mprotect` rather than a new
+/// mapping. Free memory at the top of the heap is decommitted by
+/// `RawAlloc::trim`, without giving up the address space.
+///
+/// When the reservation is used up, another is made, and growth carries on
+/// from there; the heap is then no longer contiguous across the two.
+pub struct ReservingHeapGrower<B: MemoryBackend = DefaultBackend> {
+    backend: B,
+    reservation_size: usize,
+    // The reservation being committed from.
+    base: *mut u8,
+    reserved: usize,
+    committed: usize,
+    // Earlier reservations, and the bytes committed in them.
+    retired: Regions,
+    retired_committed: usize,
+    reservations: usize,
+    attempts: usize,
+    growths: usize,
+    peak: usize,
+}
+
+/// Why a [`ReservingHeapGrower`] did not grow.
+#[derive(Debug)]
+pub enum ReservingError {
+    /// The backend failed.
+    Backend(AllocError),
+    /// A new reservation was needed, but there was no memory to record the
+    /// old one in, so it could never have been unmapped.
+    Untracked,
+}
+
+impl Default for ReservingHeapGrower {
+    fn default() -> Self {
+        Self::new(DEFAULT_RESERVATION)
+    }
+}
+
+impl ReservingHeapGrower {
+    /// A grower that reserves `reservation_size` bytes of address space at a
+    /// time, rounded up to whole pages. Nothing is reserved until the first
+    /// growth.
+    pub const fn new(reservation_size: usize) -> Self {
+        Self::with_backend(reservation_size, DefaultBackend {})
+    }
+}
+
+impl<B: MemoryBackend> ReservingHeapGrower<B> {
+    /// Like `new`, over `backend`.
+    pub const fn with_backend(reservation_size: usize, backend: B) -> Self {
+        ReservingHeapGrower {
+            backend,
+            reservation_size,
+            base: null_mut(),
+            reserved: 0,
+            committed: 0,
+            retired: Regions::new(),
+            retired_committed: 0,
+            reservations: 0,
+            attempts: 0,
+            growths: 0,
+            peak: 0,
+        }
+    }
+
+    /// Bytes of address space reserved, committed or not.
+    pub fn reserved_bytes(&self) -> usize {
+        self.retired.total_bytes() + self.reserved
+    }
+
+    /// Bytes committed, and so usable.
+    pub fn committed_bytes(&self) -> usize {
+        self.retired_committed + self.committed
+    }
+
+    /// How many reservations have been made.
+    pub fn reservations(&self) -> usize {
+        self.reservations
+    }
+
+    /// Returns the pages to the system and makes them inaccessible again,
+    /// keeping the address space reserved.
+    unsafe fn decommit(&mut self, ptr: *mut u8, len: usize) -> bool {
+        self.backend.advise(ptr, len, Advice::DontNeed).is_ok()
+            && self.backend.protect(ptr, len, Protection::None).is_ok()
+    }
+
+    /// Makes a new reservation of at least `size` bytes to commit from.
+    unsafe fn new_reservation(&mut self, size: usize) -> Result<(), ReservingError> {
+        let len = self.reservation_size.next_multiple_of(self.backend.page_size()).max(size);
+        let base = self.backend.map(len, Protection::None).map_err(ReservingError::Backend)?;
+        if !self.base.is_null() {
+            if !self.retired.insert(self.base, self.reserved) {
+                // Keep committing from the old reservation, for whatever
+                // room it has left.
+                let _ = self.backend.unmap(base, len);
+                return Err(ReservingError::Untracked);
+            }
+            self.retired_committed += self.committed;
+        }
+        self.base = base;
+        self.reserved = len;
+        self.committed = 0;
+        self.reservations += 1;
+        Ok(())
+    }
+}
+
+impl<B: MemoryBackend> HeapGrower for ReservingHeapGrower<B> {
+    type Err = ReservingError;
+
+    unsafe fn grow_heap(&mut self, size: usize) -> Result<(*mut u8, usize), Self::Err> {
+        self.attempts += 1;
+        let size = size.next_multiple_of(self.backend.page_size());
+        if self.reserved - self.committed < size {
+            self.new_reservation(size)?;
+        }
+        let ptr = self.base.add(self.committed);
+        self.backend
+            .protect(ptr, size, Protection::ReadWrite)
+            .map_err(ReservingError::Backend)?;
+        self.committed += size;
+        self.growths += 1;
+        self.peak = self.peak.max(self.committed_bytes());
+        Ok((ptr, size))
+    }
+
+    unsafe fn shrink_heap(&mut self, ptr: *mut u8, size: usize) -> usize {
+        // Only the top of the current reservation can be decommitted, so
+        // that committed memory stays one run from its base.
+        let top = self.base.add(self.committed);
+        if ptr < self.base || ptr.add(size) != top || !self.decommit(ptr, size) {
+            return 0;
+        }
+        self.committed -= size;
+        size
+    }
+
+    fn page_size(&self) -> usize {
+        self.backend.page_size()
+    }
+
+    /// Purged pages stay committed, so they can be reused without another
+    /// `mprotect`.
+    unsafe fn advise(&mut self, ptr: *mut u8, size: usize, advice: Advice) -> bool {
+        self.backend.advise(ptr, size, advice).is_ok()
+    }
+
+    fn stats(&self) -> GrowerStats {
+        GrowerStats {
+            attempts: self.attempts,
+            growths: self.growths,
+            mapped_bytes: self.committed_bytes(),
+            peak_bytes: self.peak,
+            ..GrowerStats::default()
+        }
+    }
+}
+
+impl<B: MemoryBackend> Drop for ReservingHeapGrower<B> {
+    fn drop(&mut self) {
+        unsafe {
+            for range in self.retired.iter() {
+                let _ = self.backend.unmap(range.start as *mut u8, range.end as usize - range.start as usize);
+            }
+            if !self.base.is_null() {
+                let _ = self.backend.unmap(self.base, self.reserved);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocators::RawAlloc;
+    use core::alloc::Layout;
+    use crate::blocklist::BLOCK_ALIGN;
+    use test_log::test;
+
+    #[test]
+    fn test_contiguous() {
+        let mut allocator = RawAlloc::new(ReservingHeapGrower::default());
+        let layout = Layout::from_size_align(10000, BLOCK_ALIGN).unwrap();
+        let mut pointers = [null_mut(); 8];
+        unsafe {
+            for ptr in &mut pointers {
+                *ptr = allocator.alloc(layout);
+                assert!(!ptr.is_null());
+                ptr.write_bytes(0x5A, layout.size());
+            }
+            assert_eq!(allocator.regions.len(), 1, "growths should be contiguous");
+            assert_eq!(allocator.grower.reservations(), 1);
+            for ptr in pointers {
+                allocator.dealloc(ptr, layout);
+            }
+            assert_eq!(allocator.blocks.len(), 1, "blocks should merge across growths");
+
+            let committed = allocator.grower.committed_bytes();
+            assert_eq!(allocator.trim(), committed);
+            assert_eq!(allocator.grower.committed_bytes(), 0);
+            assert_eq!(allocator.grower.reserved_bytes(), DEFAULT_RESERVATION);
+            assert!(allocator.verify().is_valid(), "{}", allocator.verify());
+
+            // Decommitted pages can be committed again.
+            let ptr = allocator.alloc(layout);
+            ptr.write_bytes(0x5A, layout.size());
+            assert_eq!(ptr, pointers[0]);
+        }
+    }
+
+    #[test]
+    fn test_new_reservation() {
+        let page = DefaultBackend {}.page_size();
+        let mut allocator = RawAlloc::new(ReservingHeapGrower::new(4 * page));
+        let small = Layout::from_size_align(3 * page, BLOCK_ALIGN).unwrap();
+        let large = Layout::from_size_align(6 * page, BLOCK_ALIGN).unwrap();
+        unsafe {
+            let a = allocator.alloc(small);
+            let b = allocator.alloc(small);
+            let c = allocator.alloc(large);
+            for (ptr, layout) in [(a, small), (b, small), (c, large)] {
+                assert!(!ptr.is_null());
+                ptr.write_bytes(0x5A, layout.size());
+            }
+            assert_eq!(allocator.grower.reservations(), 3);
+            assert_eq!(allocator.grower.reserved_bytes(), 4 * page * 2 + 6 * page);
+            assert_eq!(allocator.grower.stats().mapped_bytes, 12 * page);
+
+            allocator.dealloc(a, small);
+            allocator.dealloc(b, small);
+            allocator.dealloc(c, large);
+            assert!(allocator.verify().is_valid(), "{}", allocator.verify());
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use core::ptr::null_mut;
+
+use crate::allocators::heap_grower::{GrowerStats, HeapGrower};
+use crate::blocklist::BLOCK_ALIGN;
+
+/// The most separate buffers a `StaticHeap` can hand out memory from.
+pub const MAX_SPANS: usize = 8;
+
+// Every block handed to `RawAlloc` must be aligned and sized in whole
+// `BLOCK_ALIGN`s.
+const ALIGN: usize = BLOCK_ALIGN;
+
+#[derive(Clone, Copy)]
+struct Span {
+    start: *mut u8,
+    len: usize,
+    used: usize,
+}
+
+const EMPTY_SPAN: Span = Span {
+    start: null_mut(),
+    len: 0,
+    used: 0,
+};
+
+impl Span {
+    /// Takes `size` bytes from the unused end of the span, if they fit.
+    fn take(&mut self, size: usize) -> Option<*mut u8> {
+        let next = self.start.wrapping_add(self.used);
+        let padding = next.align_offset(ALIGN);
+        let available = (self.len - self.used).saturating_sub(padding);
+        if available < size {
+            return None;
+        }
+        self.used += padding + size;
+        Some(next.wrapping_add(padding))
+    }
+}
+
+/// A `HeapGrower` over fixed buffers rather than memory from the OS, for
+/// targets with no OS to ask, or where this crate is the only heap.
+///
+/// It can be built in a const context, so it can back a `static`
+/// `GenericAllocator`:
+///
+/// ```rust
+/// use basic_allocator::allocators::{GenericAllocator, StaticHeap};
+///
+/// static mut MEMORY: [u8; 4096] = [0; 4096];
+/// static ALLOCATOR: GenericAllocator<StaticHeap> = GenericAllocator::with_grower(unsafe {
+///     StaticHeap::from_raw_parts(core::ptr::addr_of_mut!(MEMORY) as *mut u8, 4096)
+/// });
+/// ```
+///
+/// More buffers can be donated later, up to [`MAX_SPANS`] in total. Memory
+/// is never given back.
+pub struct StaticHeap {
+    spans: [Span; MAX_SPANS],
+    len: usize,
+    attempts: usize,
+    growths: usize,
+    taken: usize,
+}
+
+pub struct StaticHeapOverflowError();
+
+impl Default for StaticHeap {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl StaticHeap {
+    /// A heap with no memory, until some is donated.
+    pub const fn empty() -> Self {
+        StaticHeap {
+            spans: [EMPTY_SPAN; MAX_SPANS],
+            len: 0,
+            attempts: 0,
+            growths: 0,
+            taken: 0,
+        }
+    }
+
+    pub const fn new(buffer: &'static mut [u8]) -> Self {
+        unsafe { Self::from_raw_parts(buffer.as_mut_ptr(), buffer.len()) }
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` must be valid for reads and writes of `len` bytes for the rest
+    /// of the program, and not used by anything else.
+    pub const unsafe fn from_raw_parts(ptr: *mut u8, len: usize) -> Self {
+        let mut heap = Self::empty();
+        heap.spans[0] = Span {
+            start: ptr,
+            len,
+            used: 0,
+        };
+        heap.len = 1;
+        heap
+    }
+
+    /// Adds `buffer` to the memory this heap can hand out. If all
+    /// [`MAX_SPANS`] are in use, the buffer is given back.
+    pub fn donate(&mut self, buffer: &'static mut [u8]) -> Result<(), &'static mut [u8]> {
+        if self.len == MAX_SPANS {
+            return Err(buffer);
+        }
+        unsafe { self.donate_raw(buffer.as_mut_ptr(), buffer.len()) };
+        Ok(())
+    }
+
+    /// Like [`donate`](Self::donate), for memory that is not a Rust slice,
+    /// such as a region from a bootloader's memory map. Returns false if all
+    /// [`MAX_SPANS`] are in use.
+    ///
+    /// # Safety
+    ///
+    /// As for [`from_raw_parts`](Self::from_raw_parts).
+    pub unsafe fn donate_raw(&mut self, ptr: *mut u8, len: usize) -> bool {
+        let Some(span) = self.spans.get_mut(self.len) else {
+            return false;
+        };
+        *span = Span {
+            start: ptr,
+            len,
+            used: 0,
+        };
+        self.len += 1;
+        true
+    }
+
+    /// Bytes not yet handed out, ignoring alignment padding.
+    pub fn remaining(&self) -> usize {
+        self.spans[..self.len].iter().map(|s| s.len - s.used).sum()
+    }
+}
+
+impl HeapGrower for StaticHeap {
+    type Err = StaticHeapOverflowError;
+
+    unsafe fn grow_heap(&mut self, size: usize) -> Result<(*mut u8, usize), StaticHeapOverflowError> {
+        self.attempts += 1;
+        let size = size.div_ceil(ALIGN) * ALIGN;
+        // First fit, so the end of a nearly full buffer is not wasted on a
+        // request that a later one can satisfy.
+        for span in &mut self.spans[..self.len] {
+            if let Some(ptr) = span.take(size) {
+                self.growths += 1;
+                self.taken += size;
+                return Ok((ptr, size));
+            }
+        }
+        Err(StaticHeapOverflowError())
+    }
+
+    fn stats(&self) -> GrowerStats {
+        GrowerStats {
+            attempts: self.attempts,
+            growths: self.growths,
+            mapped_bytes: self.taken,
+            peak_bytes: self.taken,
+            ..GrowerStats::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocators::{GenericAllocator, RawAlloc};
+    use core::alloc::Layout;
+    use core::ptr::addr_of_mut;
+    use test_log::test;
+
+    static mut MEMORY: [u8; 1024] = [0; 1024];
+    static ALLOCATOR: GenericAllocator<StaticHeap> = GenericAllocator::with_grower(unsafe {
+        // Off by one, so the heap has to align its blocks itself.
+        StaticHeap::from_raw_parts((addr_of_mut!(MEMORY) as *mut u8).wrapping_add(1), 1023)
+    });
+
+    #[test]
+    fn test_static_allocator() {
+        let layout = Layout::from_size_align(256, BLOCK_ALIGN).unwrap();
+        let mut raw = unsafe { ALLOCATOR.get_raw() };
+        let mut pointers = [null_mut(); 3];
+        for ptr in &mut pointers {
+            *ptr = unsafe { raw.alloc(layout) };
+            assert!(!ptr.is_null());
+            assert_eq!(ptr.align_offset(BLOCK_ALIGN), 0);
+        }
+        // 1023 bytes starting one past an aligned address leave room for 3.
+        assert!(unsafe { raw.alloc(layout) }.is_null());
+
+        static mut MORE: [u8; 512] = [0; 512];
+        let more = unsafe { &mut *addr_of_mut!(MORE) };
+        assert!(raw.grower().donate(more).is_ok());
+        let ptr = unsafe { raw.alloc(layout) };
+        assert!(!ptr.is_null());
+        assert!(raw.verify().is_valid(), "{}", raw.verify());
+
+        for p in pointers.iter().chain([&ptr]) {
+            unsafe { raw.dealloc(*p, layout) };
+        }
+        let stats = raw.metrics().grower;
+        assert_eq!((stats.attempts, stats.growths), (5, 4));
+    }
+
+    #[test]
+    fn test_first_fit() {
+        #[repr(align(16))]
+        struct Buffer([u8; 256]);
+        let mut first = Buffer([0; 256]);
+        let mut second = Buffer([0; 256]);
+
+        let mut heap = StaticHeap::empty();
+        unsafe {
+            assert!(heap.grow_heap(16).is_err());
+            assert!(heap.donate_raw(first.0.as_mut_ptr(), 256));
+            assert!(heap.donate_raw(second.0.as_mut_ptr(), 256));
+        }
+        let mut allocator = RawAlloc::new(heap);
+        let most = Layout::from_size_align(192, BLOCK_ALIGN).unwrap();
+        let half = Layout::from_size_align(128, BLOCK_ALIGN).unwrap();
+        unsafe {
+            let a = allocator.alloc(most);
+            let b = allocator.alloc(half);
+            let c = allocator.alloc(Layout::from_size_align(48, BLOCK_ALIGN).unwrap());
+            assert_eq!(a, first.0.as_mut_ptr());
+            assert_eq!(b, second.0.as_mut_ptr());
+            // Still fits in the rest of the first buffer.
+            assert_eq!(c, first.0.as_mut_ptr().add(192));
+        }
+        assert_eq!(allocator.grower.remaining(), 256 - 192 - 48 + 256 - 128);
+    }
+}
@@ -1,6 +1,6 @@
 use core::sync::atomic::{AtomicUsize, Ordering};
 use crate::allocators::atomic_array::AtomicArray;
-use crate::allocators::heap_grower::HeapGrower;
+use crate::allocators::heap_grower::{GrowerStats, HeapGrower};
 
 pub struct ToyHeap {
     pub page_size: usize,
@@ -39,6 +39,19 @@ impl HeapGrower for ToyHeap {
         let ptr = self.heap.as_ptr().add(current_size) as *mut u8;
         Ok((ptr, allocating))
     }
+
+    fn stats(&self) -> GrowerStats {
+        let size = self.size.load(Ordering::Relaxed);
+        GrowerStats {
+            mapped_bytes: size,
+            peak_bytes: size,
+            ..GrowerStats::default()
+        }
+    }
+
+    fn page_size(&self) -> usize {
+        self.page_size
+    }
 }
 
 fn round_up(value: usize, increment: usize) -> usize {
@@ -51,6 +64,7 @@ mod tests {
     use crate::allocators::raw_alloc::RawAlloc;
     use core::alloc::Layout;
     use core::ptr::null_mut;
+    use crate::blocklist::BLOCK_ALIGN;
     use test_log::test;
 
     #[test]
@@ -60,9 +74,9 @@ mod tests {
         
         const BLOCKS: usize = 3;
         let layouts: [Layout; BLOCKS] = [
-            Layout::from_size_align(64, 16).unwrap(),
-            Layout::from_size_align(64, 16).unwrap(),
-            Layout::from_size_align(224, 16).unwrap(),
+            Layout::from_size_align(64, BLOCK_ALIGN).unwrap(),
+            Layout::from_size_align(64, BLOCK_ALIGN).unwrap(),
+            Layout::from_size_align(224, BLOCK_ALIGN).unwrap(),
         ];
 
         let pointers: [*mut u8; BLOCKS] = unsafe {
@@ -1,40 +1,489 @@
 use core::alloc::{GlobalAlloc, Layout};
+use core::fmt;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use crate::allocators::alloc_error::{AllocError, Errno, Operation};
+use crate::allocators::arena::{
+    cpu_count, current_cpu, thread_id, thread_slot, Arena, Arenas, Slot, Threads, ARENAS_PER_CPU,
+    MAX_ARENAS,
+};
+use crate::allocators::budget::{OomHandler, SoftLimitHandler};
+use crate::allocators::decay::{DecayDelays, DecayStats};
 use crate::allocators::generic_allocator::GenericAllocator;
+use crate::allocators::heap_grower::EnhancedHeapGrower;
+use crate::allocators::raw_alloc::RawAlloc;
+use crate::allocators::HeapReport;
 use crate::blocklist::{Stats, Validity};
+use crate::metrics::{self, AllocatorMetrics};
 
+/// A heap over memory mapped from the system, behind a lock.
+///
+/// `per_cpu` makes one instead with an arena per CPU, up to `MAX_ARENAS`:
+/// heaps of their own, each behind its own lock. Threads allocate from the
+/// arena of the CPU they are running on, so the locks are rarely contended.
+/// Memory may be freed on any CPU; it goes back to the arena whose regions
+/// it is in. Limits, handlers and decay apply to each arena on its own.
+///
+/// `per_thread` and `with_arenas` instead spread threads over a number of
+/// arenas, round-robin as each first allocates, as jemalloc does. A thread
+/// keeps its arena wherever it runs, and may be pinned to another, or given
+/// one of its own.
 #[derive(Default)]
 pub struct UnixAllocator {
-    alloc: GenericAllocator<crate::allocators::heap_grower::EnhancedHeapGrower>,
+    // Arena 0, the only one if `Single`.
+    alloc: GenericAllocator<EnhancedHeapGrower>,
+    arenas: Arenas,
+    mode: Mode,
+    // For `PerThread`: the arenas threads are spread over, 0 until known,
+    // the next to give out, and private arenas given out so far.
+    threads: Threads,
+    count: AtomicUsize,
+    next: AtomicUsize,
+    privates: AtomicUsize,
+}
+
+/// How threads are given arenas.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum Mode {
+    #[default]
+    Single,
+    PerCpu,
+    PerThread,
 }
 
 impl UnixAllocator {
     #[inline(always)]
     pub const fn new() -> Self {
+        Self::with_mode(Mode::Single, 1)
+    }
+
+    /// An allocator with an arena per CPU, chosen with `getcpu`. Arenas are
+    /// made as CPUs first allocate.
+    pub const fn per_cpu() -> Self {
+        Self::with_mode(Mode::PerCpu, MAX_ARENAS)
+    }
+
+    /// An allocator spreading threads over `ARENAS_PER_CPU` arenas per CPU,
+    /// up to `MAX_ARENAS`.
+    pub const fn per_thread() -> Self {
+        Self::with_mode(Mode::PerThread, 0)
+    }
+
+    /// An allocator spreading threads over `count` arenas, from 1 to
+    /// `MAX_ARENAS`. Any left over may be given to threads of their own.
+    pub const fn with_arenas(count: usize) -> Self {
+        let count = if count == 0 {
+            1
+        } else if count > MAX_ARENAS {
+            MAX_ARENAS
+        } else {
+            count
+        };
+        Self::with_mode(Mode::PerThread, count)
+    }
+
+    const fn with_mode(mode: Mode, count: usize) -> Self {
         UnixAllocator {
             alloc: GenericAllocator::new(),
+            arenas: Arenas::new(),
+            mode,
+            threads: Threads::new(),
+            count: AtomicUsize::new(count),
+            next: AtomicUsize::new(0),
+            privates: AtomicUsize::new(0),
+        }
+    }
+
+    /// The arenas threads are spread over. Private arenas come after them.
+    pub fn arena_count(&self) -> usize {
+        match self.count.load(Ordering::Relaxed) {
+            0 => {
+                let count = (ARENAS_PER_CPU * cpu_count()).min(MAX_ARENAS);
+                self.count.store(count, Ordering::Relaxed);
+                count
+            }
+            count => count,
+        }
+    }
+
+    /// Pins the calling thread to arena `index`, one threads are spread over
+    /// or a private one already made. Returns false if the allocator does
+    /// not spread threads, there is no such arena, or no room to remember
+    /// the thread; it then keeps the arena it had.
+    pub fn pin_thread(&self, index: usize) -> bool {
+        if self.mode != Mode::PerThread {
+            return false;
+        }
+        let exists = index < self.arena_count() || (index < MAX_ARENAS && self.arenas.get(index).is_some());
+        match thread_slot(true) {
+            Some(slot) if exists => {
+                self.threads.set(slot, index);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Makes an arena for the calling thread alone, set up like arena 0, and
+    /// pins the thread to it. Returns its index, or `None` if there are
+    /// `MAX_ARENAS` already or no memory for another.
+    ///
+    /// The arena outlives the thread, along with anything it still holds.
+    pub fn private_arena(&self) -> Option<usize> {
+        if self.mode != Mode::PerThread {
+            return None;
+        }
+        let slot = thread_slot(true)?;
+        let index = self.arena_count() + self.privates.fetch_add(1, Ordering::Relaxed);
+        if index >= MAX_ARENAS {
+            return None;
         }
+        let arena = self.make_arena(index);
+        if core::ptr::eq(arena, &self.alloc) {
+            return None;
+        }
+        self.threads.set(slot, index);
+        Some(index)
+    }
+
+    /// The arenas made so far, with their indices; there is always arena 0.
+    /// Each has its own statistics.
+    pub fn arenas(&self) -> impl Iterator<Item = (usize, &GenericAllocator<EnhancedHeapGrower>)> {
+        core::iter::once((0, &self.alloc)).chain(self.arenas.iter())
     }
+
+    /// The arena for the calling thread.
     #[inline(always)]
+    fn arena(&self) -> &Arena {
+        let index = match self.mode {
+            Mode::Single => return &self.alloc,
+            Mode::PerCpu => current_cpu().map_or(0, |cpu| cpu % MAX_ARENAS),
+            Mode::PerThread => match thread_slot(true) {
+                Some(slot) => self.threads.get(slot).unwrap_or_else(|| self.assign(slot)),
+                // No room to remember the thread's arena, so its id picks one.
+                None => thread_id().map_or(0, |tid| tid as usize % self.arena_count()),
+            },
+        };
+        if index == 0 {
+            return &self.alloc;
+        }
+        match self.arenas.get(index) {
+            Some(arena) => arena,
+            None => self.make_arena(index),
+        }
+    }
+
+    /// The calling thread's arena, if it has been made, without giving the
+    /// thread one: memory may be freed by a thread that never allocated.
+    #[inline(always)]
+    fn home(&self) -> Option<&Arena> {
+        let index = match self.mode {
+            Mode::Single => 0,
+            Mode::PerCpu => current_cpu().map_or(0, |cpu| cpu % MAX_ARENAS),
+            Mode::PerThread => self.threads.get(thread_slot(false)?)?,
+        };
+        if index == 0 {
+            return Some(&self.alloc);
+        }
+        self.arenas.get(index)
+    }
+
+    /// Gives the thread in `slot` the next arena, round-robin.
+    #[cold]
+    fn assign(&self, slot: Slot) -> usize {
+        let index = self.next.fetch_add(1, Ordering::Relaxed) % self.arena_count();
+        self.threads.set(slot, index);
+        index
+    }
+
+    /// Makes arena `index`, set up like arena 0, or falls back on arena 0 if
+    /// there is no memory for it.
+    #[cold]
+    fn make_arena(&self, index: usize) -> &Arena {
+        // Arena 0 stays locked until the new one is in place, so settings
+        // changed meanwhile reach both.
+        let mut first = unsafe { self.alloc.get_raw() };
+        let budget = first.budget();
+        let hard_limit = budget.hard_limit();
+        let soft_limit = (budget.soft_limit(), budget.soft_limit_handler());
+        let oom_handler = budget.oom_handler();
+        let delays = first.decay().delays();
+        let arena = self.arenas.get_or_make(index, |arena| {
+            let raw = arena.get_mut();
+            raw.budget.set_hard_limit(hard_limit);
+            raw.budget.set_soft_limit(soft_limit.0);
+            raw.budget.set_soft_limit_handler(soft_limit.1);
+            raw.budget.set_oom_handler(oom_handler);
+            raw.decay.set_delays(delays);
+        });
+        drop(first);
+        arena.unwrap_or(&self.alloc)
+    }
+
+    /// The arena `ptr` was allocated from, by the regions it is in: most
+    /// often the calling thread's, or else any other. Arenas record every
+    /// region before handing out memory from it, so this is `None` only for
+    /// memory from elsewhere.
+    fn owner(&self, ptr: *const u8, layout: Layout) -> Option<&Arena> {
+        if self.mode == Mode::Single {
+            return Some(&self.alloc);
+        }
+        let size = RawAlloc::<EnhancedHeapGrower>::block_size(layout);
+        let home = self.home();
+        if let Some(home) = home.filter(|home| home.owns(ptr, size)) {
+            return Some(home);
+        }
+        self.arenas()
+            .map(|(_, arena)| arena)
+            .find(|&arena| !home.is_some_and(|home| core::ptr::eq(arena, home)) && arena.owns(ptr, size))
+    }
+
+    /// Free-list statistics, added up over the arenas.
     pub fn stats(&self) -> (Validity, Stats) {
-        self.alloc.stats()
+        let (validity, stats) = (Validity::default(), Stats::default());
+        for (_, arena) in self.arenas() {
+            let (v, s) = arena.stats();
+            for (total, part) in [
+                (&validity.overlaps, &v.overlaps),
+                (&validity.adjacents, &v.adjacents),
+                (&validity.out_of_orders, &v.out_of_orders),
+                (&stats.length, &s.length),
+                (&stats.size, &s.size),
+            ] {
+                total.fetch_add(part.load(Ordering::Relaxed), Ordering::Relaxed);
+            }
+        }
+        (validity, stats)
+    }
+    /// Every arena's report, together.
+    pub fn verify(&self) -> HeapReport {
+        let mut report = HeapReport::default();
+        for (_, arena) in self.arenas() {
+            report.merge(&arena.verify());
+        }
+        report
+    }
+    /// Metrics added up over the arenas; `arenas` has each one's.
+    pub fn metrics(&self) -> AllocatorMetrics {
+        let mut metrics = AllocatorMetrics::default();
+        for (_, arena) in self.arenas() {
+            metrics.merge(&arena.metrics());
+        }
+        metrics
+    }
+    /// See [`GenericAllocator::try_alloc`].
+    ///
+    /// # Safety
+    ///
+    /// As for `GlobalAlloc::alloc`.
+    #[inline(always)]
+    pub unsafe fn try_alloc(&self, layout: Layout) -> Result<NonNull<u8>, AllocError> {
+        self.arena().try_alloc(layout)
+    }
+    /// See [`GenericAllocator::try_realloc`].
+    ///
+    /// # Safety
+    ///
+    /// As for `GlobalAlloc::realloc`.
+    #[inline(always)]
+    pub unsafe fn try_realloc(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> Result<NonNull<u8>, AllocError> {
+        match self.owner(ptr, layout) {
+            Some(arena) => arena.try_realloc(ptr, layout, new_size),
+            None => Err(AllocError::System {
+                op: Operation::Unknown,
+                errno: Errno::EINVAL,
+            }),
+        }
+    }
+    /// See [`GenericAllocator::set_hard_limit`].
+    pub fn set_hard_limit(&self, limit: Option<usize>) {
+        self.arenas().for_each(|(_, arena)| arena.set_hard_limit(limit))
+    }
+    /// See [`GenericAllocator::set_soft_limit`].
+    pub fn set_soft_limit(&self, limit: Option<usize>, handler: Option<SoftLimitHandler>) {
+        self.arenas().for_each(|(_, arena)| arena.set_soft_limit(limit, handler))
+    }
+    /// See [`GenericAllocator::set_oom_handler`].
+    pub fn set_oom_handler(&self, handler: Option<OomHandler>) {
+        self.arenas().for_each(|(_, arena)| arena.set_oom_handler(handler))
+    }
+    /// See [`GenericAllocator::set_decay`].
+    pub fn set_decay(&self, delays: Option<DecayDelays>) {
+        self.arenas().for_each(|(_, arena)| arena.set_decay(delays))
+    }
+    /// See [`GenericAllocator::purge`]; the stats are added up over the
+    /// arenas.
+    pub fn purge(&self) -> DecayStats {
+        let mut total = DecayStats::default();
+        for (_, arena) in self.arenas() {
+            let stats = arena.purge();
+            total.dirty_bytes += stats.dirty_bytes;
+            total.muzzy_bytes += stats.muzzy_bytes;
+            total.released_bytes += stats.released_bytes;
+        }
+        total
+    }
+    /// See [`GenericAllocator::reserve`]. With more than one arena, it warms
+    /// up the calling thread's.
+    pub fn reserve(&self, bytes: usize) -> Result<usize, AllocError> {
+        self.arena().reserve(bytes)
+    }
+    /// See [`GenericAllocator::prepare_fork`].
+    pub fn prepare_fork(&self) {
+        // With `std`, the handlers' gate holds off every arena.
+        #[cfg(not(feature = "std"))]
+        self.arenas().for_each(|(_, arena)| arena.prepare_fork());
+    }
+    /// See [`GenericAllocator::after_fork_parent`].
+    ///
+    /// # Safety
+    ///
+    /// As for [`GenericAllocator::after_fork_parent`].
+    pub unsafe fn after_fork_parent(&self) {
+        #[cfg(not(feature = "std"))]
+        self.arenas().for_each(|(_, arena)| arena.after_fork_parent());
+    }
+    /// See [`GenericAllocator::after_fork_child`].
+    ///
+    /// # Safety
+    ///
+    /// As for [`GenericAllocator::after_fork_child`].
+    pub unsafe fn after_fork_child(&self) {
+        #[cfg(not(feature = "std"))]
+        self.arenas().for_each(|(_, arena)| arena.after_fork_child());
+    }
+    /// Writes the allocator's statistics as OpenMetrics text. The statistics
+    /// are copied out first, so `w` may itself allocate. With more than one
+    /// arena, each arena's samples carry an `arena` label.
+    pub fn write_openmetrics<W: fmt::Write>(&self, w: &mut W) -> fmt::Result {
+        let mut heaps = [AllocatorMetrics::default(); MAX_ARENAS];
+        let mut count = 0;
+        for (index, arena) in self.arenas() {
+            heaps[index] = arena.metrics();
+            count = index + 1;
+        }
+        if count == 1 {
+            return metrics::write_openmetrics(w, None, &heaps[..1]);
+        }
+        metrics::write_openmetrics(w, Some("arena"), &heaps[..count])
     }
 }
 
 unsafe impl GlobalAlloc for UnixAllocator {
     #[inline(always)]
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        self.alloc.get_raw().alloc(layout)
+        self.arena().alloc(layout)
     }
     #[inline(always)]
     unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
-        self.alloc.get_raw().calloc(layout)
+        self.arena().alloc_zeroed(layout)
     }
     #[inline(always)]
     unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
-        self.alloc.get_raw().realloc(ptr, layout, new_size)
+        self.try_realloc(ptr, layout, new_size).map_or(core::ptr::null_mut(), NonNull::as_ptr)
     }
     #[inline(always)]
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        self.alloc.get_raw().dealloc(ptr, layout)
+        if self.mode == Mode::Single {
+            return self.alloc.dealloc(ptr, layout);
+        }
+        let home = self.home();
+        if home.is_some_and(|home| home.try_dealloc(ptr, layout)) {
+            return;
+        }
+        // Freed by another thread, or on another CPU, than allocated it.
+        for (_, arena) in self.arenas() {
+            if !home.is_some_and(|home| core::ptr::eq(arena, home)) && arena.try_dealloc(ptr, layout) {
+                return;
+            }
+        }
+        // Not from any arena. Freeing it into one that did not hand it out
+        // would corrupt that one's heap, so it is left alone.
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+    use super::*;
+    use alloc::string::String;
+    use test_log::test;
+
+    #[test]
+    fn test_cross_arena_free() {
+        let allocator = UnixAllocator::per_cpu();
+        // Arena 3, whichever CPU this runs on.
+        let arena = allocator.arenas.get_or_make(3, |_| {}).unwrap();
+        let layout = Layout::from_size_align(200, 16).unwrap();
+        let ptr = unsafe { arena.alloc(layout) };
+        assert!(!ptr.is_null());
+        assert!(allocator.owner(ptr, layout).is_some_and(|owner| core::ptr::eq(owner, arena)));
+        assert!(arena.metrics().live_bytes > 0);
+
+        // Freed through the allocator, from whichever arena this thread has.
+        unsafe { allocator.dealloc(ptr, layout) };
+        assert_eq!(arena.metrics().live_bytes, 0);
+        let report = allocator.verify();
+        assert!(report.is_valid(), "{}", report);
+
+        assert!(allocator.arenas().any(|(index, _)| index == 3));
+        let mut text = String::new();
+        allocator.write_openmetrics(&mut text).unwrap();
+        assert!(text.contains("arena=\"3\""), "{}", text);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_pin_thread() {
+        let allocator = UnixAllocator::with_arenas(4);
+        assert_eq!(allocator.arena_count(), 4);
+        let layout = Layout::from_size_align(64, 16).unwrap();
+
+        // The first thread to allocate gets arena 0.
+        let first = unsafe { allocator.alloc(layout) };
+        assert!(allocator.alloc.owns(first, 64));
+        assert!(!allocator.pin_thread(4));
+
+        assert!(allocator.pin_thread(2));
+        let pinned = unsafe { allocator.alloc(layout) };
+        assert!(allocator.arenas.get(2).unwrap().owns(pinned, 64));
+
+        let index = allocator.private_arena().unwrap();
+        assert_eq!(index, 4);
+        let private = unsafe { allocator.alloc(layout) };
+        let arena = allocator.arenas.get(index).unwrap();
+        assert!(arena.owns(private, 64));
+        assert!(allocator.pin_thread(index));
+
+        // Each goes back to the arena it came from.
+        for ptr in [first, pinned, private] {
+            unsafe { allocator.dealloc(ptr, layout) };
+        }
+        for (index, arena) in allocator.arenas() {
+            assert_eq!(arena.metrics().live_bytes, 0, "arena {}", index);
+        }
+        assert!(!UnixAllocator::per_cpu().pin_thread(0));
+        assert_eq!(UnixAllocator::new().private_arena(), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_free_without_arena() {
+        let allocator = UnixAllocator::with_arenas(4);
+        let arena = allocator.arenas.get_or_make(2, |_| {}).unwrap();
+        let layout = Layout::from_size_align(64, 16).unwrap();
+        let ptr = unsafe { arena.alloc(layout) };
+        let slot = thread_slot(true).unwrap();
+
+        unsafe { allocator.dealloc(ptr, layout) };
+        assert_eq!(arena.metrics().live_bytes, 0);
+        // Freeing neither gave this thread an arena nor made one.
+        assert_eq!(allocator.threads.get(slot), None);
+        assert_eq!(allocator.arenas().count(), 2);
     }
 }
@@ -0,0 +1,260 @@
+use core::fmt::{self, Display};
+
+use super::regions::Regions;
+use crate::blocklist::{header_size, BlockList, FreeBlock, BLOCK_ALIGN};
+use crate::relation::Relation;
+
+// Enough to diagnose a broken heap without needing an allocator to report it.
+const MAX_REPORTED: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeapError {
+    /// A free block that is not inside any region obtained from the grower.
+    OutsideRegion { block: *const u8, size: usize },
+    /// A free block whose size is not a multiple of `BLOCK_ALIGN` or is
+    /// smaller than a header.
+    BadSize { block: *const u8, size: usize },
+    /// A free block that does not come after its predecessor.
+    OutOfOrder { block: *const u8, next: *const u8 },
+    /// Two free blocks that share bytes.
+    Overlap { block: *const u8, next: *const u8 },
+    /// Two free blocks that touch but were not merged.
+    Unmerged { block: *const u8, next: *const u8 },
+    /// The list loops back on itself; `block` is on the cycle.
+    Cycle { block: *const u8 },
+    /// The list's `length` counter disagrees with the number of blocks.
+    LengthMismatch { recorded: usize, counted: usize },
+    /// Free and live bytes do not add up to the bytes obtained from the grower.
+    ByteMismatch { free: usize, live: usize, mapped: usize },
+    /// Memory from the grower that the region registry could not record, as
+    /// there was no memory to grow it. Blocks there cannot be checked, nor
+    /// their owner found.
+    Untracked { bytes: usize },
+}
+
+impl Display for HeapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            HeapError::OutsideRegion { block, size } => {
+                write!(f, "free block {:p} ({} bytes) is outside every region", block, size)
+            }
+            HeapError::BadSize { block, size } => {
+                write!(f, "free block {:p} has invalid size {}", block, size)
+            }
+            HeapError::OutOfOrder { block, next } => {
+                write!(f, "free block {:p} is followed by earlier block {:p}", block, next)
+            }
+            HeapError::Overlap { block, next } => {
+                write!(f, "free blocks {:p} and {:p} overlap", block, next)
+            }
+            HeapError::Unmerged { block, next } => {
+                write!(f, "free blocks {:p} and {:p} are adjacent but not merged", block, next)
+            }
+            HeapError::Cycle { block } => write!(f, "free list has a cycle through {:p}", block),
+            HeapError::LengthMismatch { recorded, counted } => write!(
+                f,
+                "free list records {} blocks but contains {}",
+                recorded, counted
+            ),
+            HeapError::ByteMismatch { free, live, mapped } => write!(
+                f,
+                "{} free + {} live bytes != {} mapped bytes",
+                free, live, mapped
+            ),
+            HeapError::Untracked { bytes } => {
+                write!(f, "{} bytes from the grower are in no recorded region", bytes)
+            }
+        }
+    }
+}
+
+/// The result of `RawAlloc::verify`. Only the first few errors are kept, but
+/// all of them are counted.
+#[derive(Debug, Clone)]
+pub struct HeapReport {
+    errors: [Option<HeapError>; MAX_REPORTED],
+    total: usize,
+}
+
+impl Default for HeapReport {
+    fn default() -> Self {
+        HeapReport {
+            errors: [None; MAX_REPORTED],
+            total: 0,
+        }
+    }
+}
+
+impl HeapReport {
+    fn push(&mut self, error: HeapError) {
+        if let Some(slot) = self.errors.get_mut(self.total) {
+            *slot = Some(error);
+        }
+        self.total += 1;
+    }
+
+    /// Adds `other`'s errors to these, e.g. to report on several heaps.
+    pub fn merge(&mut self, other: &HeapReport) {
+        for &error in other.errors() {
+            self.push(error);
+        }
+        // Those `other` counted but did not keep.
+        self.total += other.total - other.errors().count();
+    }
+
+    #[inline]
+    pub fn is_valid(&self) -> bool {
+        self.total == 0
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &HeapError> {
+        self.errors.iter().flatten()
+    }
+
+    /// The number of errors found, including any that were not kept.
+    #[inline]
+    pub fn error_count(&self) -> usize {
+        self.total
+    }
+
+    #[inline]
+    pub fn truncated(&self) -> bool {
+        self.total > MAX_REPORTED
+    }
+}
+
+impl Display for HeapReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} heap errors", self.total)?;
+        for error in self.errors() {
+            write!(f, "\n  {}", error)?;
+        }
+        if self.truncated() {
+            write!(f, "\n  ... and {} more", self.total - MAX_REPORTED)?;
+        }
+        Ok(())
+    }
+}
+
+fn start(block: &FreeBlock) -> *const u8 {
+    block.header.as_ptr() as *const u8
+}
+
+/// Finds a block on a cycle in `blocks`, if there is one.
+fn find_cycle(blocks: &BlockList) -> Option<*const u8> {
+    let mut slow = blocks.iter();
+    let mut fast = blocks.iter();
+    loop {
+        fast.next()?;
+        let hare = fast.next()?;
+        let tortoise = slow.next()?;
+        if start(hare) == start(tortoise) {
+            return Some(start(hare));
+        }
+    }
+}
+
+pub fn verify(regions: &Regions, blocks: &BlockList, live: usize) -> HeapReport {
+    let mut report = HeapReport::default();
+
+    if let Some(block) = find_cycle(blocks) {
+        report.push(HeapError::Cycle { block });
+        return report;
+    }
+
+    let mut counted = 0usize;
+    let mut free = 0usize;
+    let mut previous: Option<&FreeBlock> = None;
+    for block in blocks {
+        let size = block.size();
+        counted += 1;
+        free = free.wrapping_add(size);
+
+        if size % BLOCK_ALIGN != 0 || size < header_size() {
+            report.push(HeapError::BadSize { block: start(block), size });
+        }
+        if !regions.contains(start(block), size) {
+            report.push(HeapError::OutsideRegion { block: start(block), size });
+        }
+        if let Some(prev) = previous {
+            let (first, next) = (start(prev), start(block));
+            match prev.relation(block) {
+                Relation::Before => {}
+                Relation::AdjacentBefore => report.push(HeapError::Unmerged { block: first, next }),
+                Relation::Overlapping => report.push(HeapError::Overlap { block: first, next }),
+                Relation::AdjacentAfter | Relation::After => {
+                    report.push(HeapError::OutOfOrder { block: first, next })
+                }
+            }
+        }
+        previous = Some(block);
+    }
+
+    if counted != blocks.len() {
+        report.push(HeapError::LengthMismatch {
+            recorded: blocks.len(),
+            counted,
+        });
+    }
+
+    // Bytes the registry could not record were still handed out.
+    if regions.untracked_bytes() > 0 {
+        report.push(HeapError::Untracked {
+            bytes: regions.untracked_bytes(),
+        });
+    }
+    let mapped = regions.total_bytes().wrapping_add(regions.untracked_bytes());
+    if free.wrapping_add(live) != mapped {
+        report.push(HeapError::ByteMismatch { free, live, mapped });
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocators::{RawAlloc, ToyHeap};
+    use core::alloc::Layout;
+    use core::ptr::NonNull;
+    use test_log::test;
+
+    #[repr(align(16))]
+    struct Buffer([u8; 64]);
+
+    #[test]
+    fn test_foreign_block() {
+        let mut allocator = RawAlloc::new(ToyHeap::default());
+        let layout = Layout::from_size_align(32, 16).unwrap();
+        let ptr = unsafe { allocator.alloc(layout) };
+        assert!(allocator.verify().is_valid());
+
+        // Hand the allocator memory it never got from its grower.
+        let mut foreign = Buffer([0; 64]);
+        let foreign_ptr = NonNull::new(foreign.0.as_mut_ptr()).unwrap();
+        unsafe { allocator.blocks.add_block(foreign_ptr, 64) };
+
+        let report = allocator.verify();
+        log::info!("{}", report);
+        let errors: [HeapError; 2] = [
+            HeapError::OutsideRegion {
+                block: foreign_ptr.as_ptr(),
+                size: 64,
+            },
+            HeapError::ByteMismatch {
+                free: 96,
+                live: 32,
+                mapped: 64,
+            },
+        ];
+        assert_eq!(report.error_count(), 2);
+        for error in &errors {
+            assert!(report.errors().any(|e| e == error), "missing {}", error);
+        }
+
+        let _ = unsafe { allocator.blocks.claim(foreign_ptr.as_ptr(), 64) };
+        unsafe { allocator.dealloc(ptr, layout) };
+        let report = allocator.verify();
+        assert!(report.is_valid(), "{}", report);
+    }
+}
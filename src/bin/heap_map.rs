@@ -0,0 +1,258 @@
+//! Renders a heap map, as written by `RawAlloc::write_heap_map` in the `Text`
+//! format, as an ASCII map or an SVG strip chart.
+
+use std::io::Read;
+
+use basic_allocator::allocators::{HeapMapFormat, RawAlloc, ToyHeap};
+use core::alloc::Layout;
+
+const USAGE: &str = "[--ascii | --svg] [--width N] [FILE | --demo]
+
+Reads a text heap map from FILE (or stdin) and renders it. With --demo, a map is
+generated from a small random workload on a ToyHeap instead.
+
+ASCII legend: '#' used, '.' free, '+' mixed, '!' contains free blocks that touch
+but were not merged.";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Used,
+    Free,
+}
+
+struct Segment {
+    kind: Kind,
+    start: usize,
+    end: usize,
+}
+
+struct Region {
+    start: usize,
+    end: usize,
+    segments: Vec<Segment>,
+}
+
+fn parse_hex(s: &str) -> Result<usize, String> {
+    let digits = s.trim_start_matches("0x");
+    usize::from_str_radix(digits, 16).map_err(|e| format!("bad address {:?}: {}", s, e))
+}
+
+fn parse(input: &str) -> Result<Vec<Region>, String> {
+    let mut regions: Vec<Region> = Vec::new();
+    for line in input.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            ["heap_map", ..] | [] => {}
+            ["region", start, end, _] => regions.push(Region {
+                start: parse_hex(start)?,
+                end: parse_hex(end)?,
+                segments: Vec::new(),
+            }),
+            [kind @ ("used" | "free"), start, end, _] => {
+                let region = regions
+                    .last_mut()
+                    .ok_or_else(|| format!("segment before any region: {:?}", line))?;
+                region.segments.push(Segment {
+                    kind: if *kind == "used" { Kind::Used } else { Kind::Free },
+                    start: parse_hex(start)?,
+                    end: parse_hex(end)?,
+                });
+            }
+            _ => return Err(format!("unrecognised line: {:?}", line)),
+        }
+    }
+    Ok(regions)
+}
+
+fn unmerged_pairs(region: &Region) -> usize {
+    region
+        .segments
+        .windows(2)
+        .filter(|w| w[0].kind == Kind::Free && w[1].kind == Kind::Free && w[0].end == w[1].start)
+        .count()
+}
+
+fn render_ascii(regions: &[Region], width: usize) {
+    for region in regions {
+        let size = region.end - region.start;
+        let free: usize = region
+            .segments
+            .iter()
+            .filter(|s| s.kind == Kind::Free)
+            .map(|s| s.end - s.start)
+            .sum();
+        let largest_free = region
+            .segments
+            .iter()
+            .filter(|s| s.kind == Kind::Free)
+            .map(|s| s.end - s.start)
+            .max()
+            .unwrap_or(0);
+
+        println!(
+            "{:#x}-{:#x}: {} bytes, {} free, {} unmerged free pairs, fragmentation {:.2}",
+            region.start,
+            region.end,
+            size,
+            free,
+            unmerged_pairs(region),
+            if free == 0 { 0.0 } else { 1.0 - largest_free as f64 / free as f64 },
+        );
+
+        let cells = width.min(size).max(1);
+        let mut line = String::with_capacity(cells);
+        for cell in 0..cells {
+            let lo = region.start + size * cell / cells;
+            let hi = region.start + size * (cell + 1) / cells;
+            let overlapping: Vec<&Segment> = region
+                .segments
+                .iter()
+                .filter(|s| s.start < hi && s.end > lo)
+                .collect();
+            let touching_frees = overlapping
+                .windows(2)
+                .any(|w| w[0].kind == Kind::Free && w[1].kind == Kind::Free);
+            let c = if touching_frees {
+                '!'
+            } else if overlapping.iter().all(|s| s.kind == Kind::Used) {
+                '#'
+            } else if overlapping.iter().all(|s| s.kind == Kind::Free) {
+                '.'
+            } else {
+                '+'
+            };
+            line.push(c);
+        }
+        println!("[{}]", line);
+    }
+}
+
+fn render_svg(regions: &[Region], width: usize) {
+    const STRIP: usize = 24;
+    const GAP: usize = 20;
+    let height = regions.len() * (STRIP + GAP) + GAP;
+    println!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" font-family="monospace" font-size="12">"#,
+        width, height
+    );
+    for (i, region) in regions.iter().enumerate() {
+        let y = GAP + i * (STRIP + GAP);
+        let size = (region.end - region.start) as f64;
+        println!(
+            r#"  <text x="0" y="{}">{:#x}-{:#x} ({} bytes, {} unmerged free pairs)</text>"#,
+            y - 4,
+            region.start,
+            region.end,
+            region.end - region.start,
+            unmerged_pairs(region),
+        );
+        for seg in &region.segments {
+            let x = (seg.start - region.start) as f64 / size * width as f64;
+            let w = (seg.end - seg.start) as f64 / size * width as f64;
+            let fill = match seg.kind {
+                Kind::Used => "#c0392b",
+                Kind::Free => "#27ae60",
+            };
+            println!(
+                r#"  <rect x="{:.2}" y="{}" width="{:.2}" height="{}" fill="{}" stroke="white" stroke-width="0.5"><title>{} {:#x}-{:#x} ({} bytes)</title></rect>"#,
+                x,
+                y,
+                w,
+                STRIP,
+                fill,
+                if seg.kind == Kind::Used { "used" } else { "free" },
+                seg.start,
+                seg.end,
+                seg.end - seg.start,
+            );
+        }
+    }
+    println!("</svg>");
+}
+
+fn demo_map() -> String {
+    let mut allocator = RawAlloc::new(ToyHeap::default());
+    let mut live: Vec<(*mut u8, Layout)> = Vec::new();
+    // A simple deterministic xorshift, so the demo is reproducible.
+    let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+
+    for _ in 0..512 {
+        if live.is_empty() || next() % 3 != 0 {
+            let layout = Layout::from_size_align(16 * (1 + next() as usize % 32), 16).unwrap();
+            let ptr = unsafe { allocator.alloc(layout) };
+            if !ptr.is_null() {
+                live.push((ptr, layout));
+            }
+        } else {
+            let (ptr, layout) = live.swap_remove(next() as usize % live.len());
+            unsafe { allocator.dealloc(ptr, layout) };
+        }
+    }
+
+    let mut out = String::new();
+    allocator
+        .write_heap_map(&mut out, HeapMapFormat::Text)
+        .expect("writing to a String cannot fail");
+    for (ptr, layout) in live {
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+    out
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let mut svg = false;
+    let mut width = None;
+    let mut input = None;
+    let mut demo = false;
+
+    let mut rest = args.iter().skip(1);
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--help" | "-h" => {
+                println!("USAGE: {} {}", args[0], USAGE);
+                return;
+            }
+            "--ascii" => svg = false,
+            "--svg" => svg = true,
+            "--demo" => demo = true,
+            "--width" => width = rest.next().and_then(|w| w.parse().ok()),
+            path => input = Some(path.to_owned()),
+        }
+    }
+
+    let text = if demo {
+        demo_map()
+    } else {
+        let mut text = String::new();
+        let result = match &input {
+            Some(path) => std::fs::File::open(path).and_then(|mut f| f.read_to_string(&mut text)),
+            None => std::io::stdin().read_to_string(&mut text),
+        };
+        if let Err(e) = result {
+            eprintln!("Failed to read heap map: {}", e);
+            std::process::exit(1);
+        }
+        text
+    };
+
+    let regions = match parse(&text) {
+        Ok(regions) => regions,
+        Err(e) => {
+            eprintln!("Failed to parse heap map: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if svg {
+        render_svg(&regions, width.unwrap_or(1000));
+    } else {
+        render_ascii(&regions, width.unwrap_or(80));
+    }
+}
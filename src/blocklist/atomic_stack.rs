@@ -0,0 +1,190 @@
+//! A lock-free stack of free blocks, all of one size.
+//!
+//! Unlike `BlockList`, blocks are neither sorted nor merged, so pushing and
+//! popping are each a single compare-and-swap on the head. The head packs the
+//! address of the top block with a tag, bumped by every change, so a pop that
+//! read a head another thread has since popped and pushed back (the "ABA"
+//! problem) fails and tries again, rather than installing a stale `next`.
+
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use super::free_header::BLOCK_ALIGN;
+
+// Blocks are `BLOCK_ALIGN`-aligned, so the low bits of their addresses are
+// always zero, and are not stored.
+const SHIFT: u32 = BLOCK_ALIGN.trailing_zeros();
+
+// User space addresses fit in 48 bits on every 64-bit target supported,
+// unless 57-bit addresses have been asked for.
+#[cfg(target_pointer_width = "64")]
+const ADDRESS_BITS: u32 = 48;
+
+#[cfg(target_pointer_width = "32")]
+const ADDRESS_BITS: u32 = 32;
+
+// The rest of the head is the tag: 20 bits on 64-bit targets, 35 on 32-bit.
+const INDEX_BITS: u32 = ADDRESS_BITS - SHIFT;
+const INDEX_MASK: u64 = (1 << INDEX_BITS) - 1;
+
+#[inline(always)]
+fn pack(address: usize, tag: u64) -> u64 {
+    (tag << INDEX_BITS) | (address >> SHIFT) as u64
+}
+
+#[inline(always)]
+fn unpack(head: u64) -> (usize, u64) {
+    (((head & INDEX_MASK) as usize) << SHIFT, head >> INDEX_BITS)
+}
+
+/// A Treiber stack of free blocks. Each block holds the address of the next
+/// in its first word.
+#[derive(Debug, Default)]
+pub struct AtomicStack {
+    head: AtomicU64,
+    len: AtomicUsize,
+}
+
+impl AtomicStack {
+    pub const fn new() -> Self {
+        AtomicStack {
+            head: AtomicU64::new(0),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    /// Whether a block at `ptr` can be pushed: its address must fit in the
+    /// head.
+    #[inline(always)]
+    pub fn fits(ptr: NonNull<u8>) -> bool {
+        let address = ptr.as_ptr() as usize;
+        address.is_multiple_of(BLOCK_ALIGN) && (address >> SHIFT) as u64 <= INDEX_MASK
+    }
+
+    /// Pushes the block at `ptr`. Returns false, leaving the block alone, if
+    /// it does not `fit`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a block of at least a word that nothing else uses, and
+    /// it must stay mapped as long as the stack does, even once popped:
+    /// another thread's pop may still read it.
+    #[inline(always)]
+    pub unsafe fn push(&self, ptr: NonNull<u8>) -> bool {
+        if !Self::fits(ptr) {
+            return false;
+        }
+        let next = &*(ptr.as_ptr() as *const AtomicUsize);
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            let (top, tag) = unpack(head);
+            next.store(top, Ordering::Relaxed);
+            let new = pack(ptr.as_ptr() as usize, tag.wrapping_add(1));
+            match self.head.compare_exchange_weak(head, new, Ordering::Release, Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(current) => head = current,
+            }
+        }
+        self.len.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    /// Pops the most recently pushed block, if any.
+    #[inline(always)]
+    pub fn pop(&self) -> Option<NonNull<u8>> {
+        let mut head = self.head.load(Ordering::Acquire);
+        loop {
+            let (top, tag) = unpack(head);
+            let ptr = NonNull::new(top as *mut u8)?;
+            // Another thread may pop `ptr` and hand it out meanwhile, so this
+            // may read whatever was written there since; the tag will have
+            // changed, and the exchange fails. `push` made sure the memory is
+            // still mapped.
+            let next = unsafe { (*(ptr.as_ptr() as *const AtomicUsize)).load(Ordering::Relaxed) };
+            let new = pack(next, tag.wrapping_add(1));
+            match self.head.compare_exchange_weak(head, new, Ordering::Acquire, Ordering::Acquire) {
+                Ok(_) => {
+                    self.len.fetch_sub(1, Ordering::Relaxed);
+                    return Some(ptr);
+                }
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    /// Blocks on the stack. Only a snapshot while other threads push or pop.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Relaxed) & INDEX_MASK == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_log::test;
+
+    #[repr(C, align(16))]
+    struct Blocks([[usize; 2]; 4]);
+
+    #[test]
+    fn test_push_pop() {
+        let mut blocks = Blocks([[0; 2]; 4]);
+        let stack = AtomicStack::new();
+        assert_eq!(stack.pop(), None);
+
+        let pointers = blocks.0.each_mut().map(|b| NonNull::from(b).cast::<u8>());
+        for &ptr in &pointers {
+            assert!(unsafe { stack.push(ptr) });
+        }
+        assert_eq!(stack.len(), 4);
+        for &ptr in pointers.iter().rev() {
+            assert_eq!(stack.pop(), Some(ptr));
+        }
+        assert!(stack.is_empty());
+        assert_eq!(stack.pop(), None);
+
+        // Misaligned, or beyond the addresses the head can hold.
+        let misaligned = unsafe { NonNull::new_unchecked(pointers[0].as_ptr().add(1)) };
+        assert!(!unsafe { stack.push(misaligned) });
+        #[cfg(target_pointer_width = "64")]
+        assert!(!AtomicStack::fits(NonNull::new((1usize << 56) as *mut u8).unwrap()));
+        assert!(stack.is_empty());
+    }
+
+    #[test]
+    fn test_aba() {
+        let mut blocks = Blocks([[0; 2]; 4]);
+        let [a, b, ..] = blocks.0.each_mut().map(|b| NonNull::from(b).cast::<u8>());
+        let stack = AtomicStack::new();
+        unsafe {
+            stack.push(a);
+            stack.push(b);
+        }
+
+        // A pop stalls after reading the head, about to install `a`...
+        let stale = stack.head.load(Ordering::Acquire);
+        let (top, tag) = unpack(stale);
+        assert_eq!(top, b.as_ptr() as usize);
+        let install = pack(a.as_ptr() as usize, tag.wrapping_add(1));
+
+        // ...while others pop both, and push `b` back, alone.
+        assert_eq!(stack.pop(), Some(b));
+        assert_eq!(stack.pop(), Some(a));
+        unsafe { stack.push(b) };
+
+        // `b` is on top again, but the tag tells the stalled pop so.
+        assert_eq!(unpack(stack.head.load(Ordering::Relaxed)).0, b.as_ptr() as usize);
+        assert!(stack
+            .head
+            .compare_exchange(stale, install, Ordering::Acquire, Ordering::Relaxed)
+            .is_err());
+        assert_eq!(stack.pop(), Some(b));
+        assert_eq!(stack.pop(), None);
+    }
+}
@@ -19,11 +19,7 @@ pub struct BlockList {
 impl Default for BlockList {
     #[inline(always)]
     fn default() -> Self {
-        BlockList {
-            first: None,
-            merged: AtomicBool::new(true),
-            length: AtomicUsize::new(0),
-        }
+        Self::new()
     }
 }
 
@@ -88,6 +84,49 @@ impl<C, R> ApplyState<C, R> {
 }
 
 impl BlockList {
+    #[inline(always)]
+    pub const fn new() -> Self {
+        BlockList {
+            first: None,
+            merged: AtomicBool::new(true),
+            length: AtomicUsize::new(0),
+        }
+    }
+
+    /// Takes over a list of free blocks already in memory, starting at
+    /// `first`, such as one saved in a file. If the memory has moved `delta`
+    /// bytes since the list was built, every `next` pointer is moved with it.
+    ///
+    /// # Safety
+    ///
+    /// `first` must start a valid free list, as built at its old address,
+    /// that nothing else owns.
+    pub unsafe fn adopt(first: Option<NonNull<u8>>, delta: isize) -> Self {
+        let mut list = BlockList::new();
+        let Some(first) = first else {
+            return list;
+        };
+        let mut block = FreeBlock { header: first.cast() };
+        let mut length = 1;
+        let mut current = &mut block;
+        while let Some(next) = current.next_mut() {
+            let moved = (next.header.as_ptr() as *mut u8).wrapping_offset(delta);
+            next.header = NonNull::new_unchecked(moved).cast();
+            length += 1;
+            current = current.next_mut().expect("just moved");
+        }
+        list.first = Some(block);
+        list.length = AtomicUsize::new(length);
+        list
+    }
+
+    /// The start of the first free block, e.g. to save the list along with
+    /// the memory it is in.
+    #[inline]
+    pub fn first_ptr(&self) -> Option<NonNull<u8>> {
+        self.first.as_ref().map(|block| block.header.cast())
+    }
+
     // Cache line size optimization
     const BATCH_SIZE: usize = 8;
     const CACHE_LINE_SIZE: usize = 64;
@@ -130,29 +169,95 @@ impl BlockList {
     #[inline(always)]
     pub unsafe fn add_block(&mut self, ptr: NonNull<u8>, size: usize) {
         let new_block = FreeBlock::from_raw(ptr, None, size);
+        self.length.fetch_add(1, Ordering::Relaxed);
         match self.first.take() {
             None => {
                 self.first = Some(new_block);
-                self.length.fetch_add(1, Ordering::Relaxed);
             }
             Some(first) => {
                 match new_block.relation(&first) {
                     Relation::Before | Relation::AdjacentBefore => {
                         let mut block = new_block;
-                        block.replace_next(first);
-                        block.try_merge_next();
+                        let _ = block.replace_next(first);
+                        if block.try_merge_next() {
+                            self.length.fetch_sub(1, Ordering::Relaxed);
+                        }
                         self.first = Some(block);
                         self.try_merge_all();
                     }
                     _ => {
                         self.first = Some(first);
-                        self.merge_block(new_block);
+                        let merges = self.merge_block(new_block);
+                        self.length.fetch_sub(merges, Ordering::Relaxed);
                         self.try_merge_all();
                     }
                 }
             }
         }
     }
+
+    /// Removes `size` bytes from the front of the free block starting at
+    /// `ptr`, leaving any remainder in the list. Returns the number of bytes
+    /// claimed (the whole block if the remainder would be too small to hold a
+    /// header), or `None` if no block starts at `ptr` or it is too small.
+    ///
+    /// # Safety
+    ///
+    /// The claimed bytes are handed to the caller and must be returned with
+    /// `add_block` once they are no longer used.
+    pub unsafe fn claim(&mut self, ptr: *const u8, size: usize) -> Option<usize> {
+        // Detaches `block`, returning what should take its place in the list,
+        // how many bytes were claimed, and whether the list got shorter.
+        unsafe fn carve(mut block: FreeBlock, ptr: *const u8, size: usize) -> (Option<FreeBlock>, usize, bool) {
+            let block_size = block.size();
+            let after = block.take_next();
+            core::mem::forget(block);
+            let rest = block_size.wrapping_sub(size);
+            if rest >= BlockList::header_size() {
+                let tail = NonNull::new_unchecked(ptr.add(size) as *mut u8);
+                (Some(FreeBlock::from_raw(tail, after, rest)), size, false)
+            } else {
+                (after, block_size, true)
+            }
+        }
+
+        let first = self.first.as_ref()?;
+        let (claimed, removed) = if first.header.as_ptr() as *const u8 == ptr {
+            if first.size() < size {
+                return None;
+            }
+            let (replacement, claimed, removed) = carve(self.first.take()?, ptr, size);
+            self.first = replacement;
+            (claimed, removed)
+        } else {
+            self.apply((), |prev, ()| {
+                let Some(next) = prev.next() else {
+                    return ApplyState::Fail(());
+                };
+                let start = next.header.as_ptr() as *const u8;
+                if start > ptr {
+                    return ApplyState::Fail(());
+                }
+                if start != ptr {
+                    return ApplyState::Continue(());
+                }
+                if next.size() < size {
+                    return ApplyState::Fail(());
+                }
+                let block = prev.take_next().expect("next was just checked");
+                let (replacement, claimed, removed) = carve(block, ptr, size);
+                if let Some(replacement) = replacement {
+                    let _ = prev.replace_next(replacement);
+                }
+                ApplyState::Finished((claimed, removed))
+            })
+            .into_result()?
+        };
+        if removed {
+            self.length.fetch_sub(1, Ordering::Relaxed);
+        }
+        Some(claimed)
+    }
     #[inline(always)]
     unsafe fn try_merge_all(&mut self) {
         while self.merged.swap(false, Ordering::Relaxed) {
@@ -200,25 +305,21 @@ impl BlockList {
 
 
 
+    /// Inserts `block` after the first block, returning how many merges
+    /// happened.
     #[inline(always)]
-    unsafe fn merge_block(&mut self, block: FreeBlock) {
+    unsafe fn merge_block(&mut self, block: FreeBlock) -> usize {
         self.apply(block, |prev, new_block| {
-            if let Some(next) = prev.next() {
-                match new_block.relation(next) {
-                    Relation::Before | Relation::AdjacentBefore => {
-                        prev.insert_merge(new_block);
-                        ApplyState::Finished(())
-                    }
-                    Relation::AdjacentAfter | Relation::After if prev.try_merge_next() => {
-                        ApplyState::Finished(())
-                    }
-                    _ => ApplyState::Continue(new_block),
-                }
-            } else {
-                prev.insert_merge(new_block);
-                ApplyState::Finished(())
+            match prev.next() {
+                Some(next) if matches!(
+                    new_block.relation(next),
+                    Relation::AdjacentAfter | Relation::After
+                ) => ApplyState::Continue(new_block),
+                _ => ApplyState::Finished(prev.insert_merge(new_block)),
             }
-        });
+        })
+        .into_result()
+        .unwrap_or(0)
     }
 
 
@@ -325,6 +426,7 @@ impl BlockList {
                         let range = NonNull::new_unchecked(first.header.as_ptr() as *mut u8)..
                                     NonNull::new_unchecked(first.header.as_ptr().add(size) as *mut u8);
                         self.first = first.take_next();
+                        self.length.fetch_sub(1, Ordering::Relaxed);
                         return Some(range);
                     }
                     return Some(first.split(size));
@@ -332,7 +434,7 @@ impl BlockList {
             }
         }
 
-        self.apply((), |previous, ()| unsafe {
+        let (range, removed) = self.apply((), |previous, ()| unsafe {
             let Some(next) = previous.next_mut() else {
                 return ApplyState::Continue(());
             };
@@ -345,18 +447,22 @@ impl BlockList {
                         // Exact match: direct range creation
                         let ptr = next.header.as_ptr();
                         previous.header_mut().next = next.take_next();
-                        NonNull::new_unchecked(ptr as *mut u8)..
-                        NonNull::new_unchecked(ptr.add(size) as *mut u8)
+                        (NonNull::new_unchecked(ptr as *mut u8)..
+                        NonNull::new_unchecked(ptr.add(size) as *mut u8), true)
                     } else {
                         // Larger block: split
-                        next.split(size)
+                        (next.split(size), false)
                     }
                 )
             } else {
                 ApplyState::Continue(())
             }
         })
-        .into_result()
+        .into_result()?;
+        if removed {
+            self.length.fetch_sub(1, Ordering::Relaxed);
+        }
+        Some(range)
 
 
     }
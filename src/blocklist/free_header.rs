@@ -2,14 +2,25 @@ use core::ptr::NonNull;
 use core::sync::atomic::{AtomicUsize, Ordering};
 use super::free_block::FreeBlock;
 
+/// The alignment of every block, and the unit block sizes come in: two
+/// words, so 16 bytes on 64-bit targets and 8 on 32-bit ones.
+pub const BLOCK_ALIGN: usize = 2 * core::mem::size_of::<usize>();
+
+/// The smallest block that can be freed, as it must hold a header.
+pub const MIN_BLOCK_SIZE: usize = HEADER_SIZE;
+
 #[derive(Debug)]
-#[repr(C, align(16))]
+#[cfg_attr(target_pointer_width = "64", repr(C, align(16)))]
+#[cfg_attr(target_pointer_width = "32", repr(C, align(8)))]
 pub struct FreeHeader {
     pub(crate) next: Option<FreeBlock>,
     pub(crate) size: AtomicUsize,
 }
 
-const HEADER_SIZE: usize = 16;
+const HEADER_SIZE: usize = BLOCK_ALIGN;
+
+static_assertions::const_assert_eq!(core::mem::size_of::<FreeHeader>(), HEADER_SIZE);
+static_assertions::const_assert_eq!(core::mem::align_of::<FreeHeader>(), BLOCK_ALIGN);
 
 impl FreeHeader {
     #[inline(always)]
@@ -1,12 +1,16 @@
+#[cfg(target_has_atomic = "64")]
+mod atomic_stack;
 mod block_list;
 mod free_block;
 mod free_header;
 mod stats;
 mod validity;
 
+#[cfg(target_has_atomic = "64")]
+pub use atomic_stack::AtomicStack;
 pub use block_list::{BlockList, ApplyState};
 pub use free_block::FreeBlock;
-pub use free_header::{FreeHeader, header_size};
+pub use free_header::{FreeHeader, header_size, BLOCK_ALIGN, MIN_BLOCK_SIZE};
 pub use stats::Stats;
 pub use validity::Validity;
 
@@ -19,6 +19,35 @@
 //! See also
 //! [`core::alloc::GlobalAlloc`](https://doc.rust-lang.org/nightly/core/alloc/trait.GlobalAlloc.html).
 //!
+//! Without an OS, a [`GenericAllocator`](allocators/struct.GenericAllocator.html)
+//! over a [`StaticHeap`](allocators/struct.StaticHeap.html) can be the global
+//! allocator instead. Build with `default-features = false` so that nothing
+//! depends on the `alloc` crate. On such targets (`target_os = "none"`),
+//! `UnixAllocator` and the backends that make system calls are left out:
+//!
+//! ```rust
+//! use basic_allocator::allocators::{GenericAllocator, StaticHeap};
+//!
+//! static mut HEAP: [u8; 1 << 20] = [0; 1 << 20];
+//!
+//! #[global_allocator]
+//! static ALLOCATOR: GenericAllocator<StaticHeap> = GenericAllocator::with_grower(unsafe {
+//!     StaticHeap::from_raw_parts(core::ptr::addr_of_mut!(HEAP) as *mut u8, 1 << 20)
+//! });
+//! # fn main() {}
+//! ```
+//!
+//! ## Features
+//!
+//! - `alloc` (default): [`ToyHeap`](allocators/struct.ToyHeap.html),
+//!   `AtomicArray` and the [`testing`](testing/index.html) harness, which
+//!   need a global allocator of their own.
+//! - `use_libc`: get memory from the OS through `libc` rather than raw system
+//!   calls, by making `LibcBackend` the `DefaultBackend`. `RawBackend` is
+//!   still available.
+//! - `std`: keep allocators usable in the child of a `fork`, through handlers
+//!   registered with `pthread_atfork`.
+//!
 //! ## Major Components
 //!
 //! This module has several parts.
@@ -29,8 +58,9 @@
 //! which can be reused by the allocator.
 //!
 //! The free block starts with a header, and then has unused memory after that.
-//! The header is 16 bytes, and consists of a pointer to the next block and the
-//! size of the block as a whole.
+//! The header is two words (16 bytes on 64-bit targets, 8 on 32-bit ones), and
+//! consists of a pointer to the next block and the size of the block as a
+//! whole. Blocks are aligned to, and sized in multiples of, the header size.
 //!
 //! ### [`RawAlloc`](allocators/struct.RawAlloc.html)
 //!
@@ -45,12 +75,25 @@
 //! A `UnixAllocator` wraps `RawAlloc` with a spin lock to make it thread-safe,
 //! allowing it to be used as the global allocator. It also combines `RawAlloc`
 //! with a unix-specific `UnixHeapGrower` to use virtual memory pages as its
-//! underlying basis for making those calls.
+//! underlying basis for making those calls. `UnixAllocator::per_cpu` keeps
+//! an arena, with a lock of its own, for each CPU instead, and frees memory
+//! into whichever arena it came from. `UnixAllocator::per_thread` instead
+//! gives each thread an arena of its own, round-robin, which it can change
+//! with `pin_thread` or `private_arena`.
+//!
+//! ### [`LockFreeAllocator`](allocators/struct.LockFreeAllocator.html)
+//!
+//! A `LockFreeAllocator` puts a lock-free
+//! [`AtomicStack`](blocklist/struct.AtomicStack.html) of free blocks per size
+//! class in front of a locked heap, so that threads allocate and free small
+//! blocks without taking the lock.
 //!
 //! ### [`HeapGrower`](allocators/struct.HeapGrower.html)
 //!
 //! `HeapGrower` is a simple trait interface meant to abstract over the calls to
 //! the OS to expand the heap.
+//! [`StaticHeap`](allocators/struct.StaticHeap.html) instead hands out memory
+//! from fixed buffers, for targets without an OS to ask.
 //!
 //! ## Implementation
 //!
@@ -96,19 +139,29 @@
 //! could be better, in terms of features and performance:
 //!
 //! 1. It could return memory to the OS when it was done with a page
-//! 2. It could not require 16-byte alignment
+//! 2. It could not require two-word alignment
 //! 3. It could have a thread-safe linked-list implementation, removing the need
-//!    for a spin lock
+//!    for a spin lock. `LockFreeAllocator` does so for small blocks, but
+//!    blocks there are never merged
 //! 4. It could implement
 //!    [`realloc`](https://doc.rust-lang.org/core/alloc/trait.GlobalAlloc.html#method.realloc),
 //!    so that containers could be resized in place when possible
 //!
 //! ... and probably more. Beyond those basic features, there are lots of
 //! optimizations in other allocators that make them more performant.
+#[cfg(any(feature = "alloc", test))]
+extern crate alloc;
+
 pub mod allocators;
 pub mod blocklist;
+pub mod metrics;
+#[cfg(not(target_os = "none"))]
 mod mmap;
 pub mod relation;
+#[cfg(feature = "alloc")]
+pub mod testing;
 
-pub use allocators::{RawAlloc, UnixAllocator};
+pub use allocators::RawAlloc;
+#[cfg(not(target_os = "none"))]
+pub use allocators::UnixAllocator;
 pub use blocklist::BlockList;
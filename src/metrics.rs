@@ -0,0 +1,363 @@
+//! Renders allocator statistics in the OpenMetrics text format, as scraped by
+//! Prometheus.
+//!
+//! Rendering never allocates: statistics are first copied into plain
+//! [`AllocatorMetrics`] values, and then written through any
+//! [`core::fmt::Write`] sink, such as a [`FixedBuf`] over a static buffer.
+
+use core::fmt::{self, Write};
+use core::sync::atomic::Ordering;
+
+use crate::allocators::{DecayStats, GrowerStats};
+use crate::blocklist::{Stats, Validity};
+
+/// A snapshot of one heap's statistics.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AllocatorMetrics {
+    pub free_blocks: usize,
+    pub free_bytes: usize,
+    pub live_bytes: usize,
+    pub regions: usize,
+    pub region_bytes: usize,
+    pub allocations: usize,
+    pub deallocations: usize,
+    pub overlaps: usize,
+    pub adjacents: usize,
+    pub out_of_orders: usize,
+    /// Free bytes faulted in ahead of use by `reserve`.
+    pub prefaulted_bytes: usize,
+    pub grower: GrowerStats,
+    pub decay: DecayStats,
+}
+
+impl AllocatorMetrics {
+    /// Copies in the free-list statistics from `BlockList::stats`.
+    pub fn with_blocks(mut self, validity: &Validity, stats: &Stats) -> Self {
+        let (length, size) = stats.get_stats();
+        self.free_blocks = length;
+        self.free_bytes = size;
+        self.overlaps = validity.overlaps.load(Ordering::Relaxed);
+        self.adjacents = validity.adjacents.load(Ordering::Relaxed);
+        self.out_of_orders = validity.out_of_orders.load(Ordering::Relaxed);
+        self
+    }
+
+    /// Adds `other` to these, e.g. for the totals over several heaps. Peaks
+    /// are added too, so the total peak is an upper bound.
+    pub fn merge(&mut self, other: &AllocatorMetrics) {
+        self.free_blocks += other.free_blocks;
+        self.free_bytes += other.free_bytes;
+        self.live_bytes += other.live_bytes;
+        self.regions += other.regions;
+        self.region_bytes += other.region_bytes;
+        self.allocations += other.allocations;
+        self.deallocations += other.deallocations;
+        self.overlaps += other.overlaps;
+        self.adjacents += other.adjacents;
+        self.out_of_orders += other.out_of_orders;
+        self.prefaulted_bytes += other.prefaulted_bytes;
+
+        let (grower, other_grower) = (&mut self.grower, &other.grower);
+        grower.attempts += other_grower.attempts;
+        grower.growths += other_grower.growths;
+        grower.mapped_bytes += other_grower.mapped_bytes;
+        grower.peak_bytes += other_grower.peak_bytes;
+        grower.hugetlb_bytes += other_grower.hugetlb_bytes;
+        grower.transparent_huge_bytes += other_grower.transparent_huge_bytes;
+
+        let (decay, other_decay) = (&mut self.decay, &other.decay);
+        decay.dirty_bytes += other_decay.dirty_bytes;
+        decay.muzzy_bytes += other_decay.muzzy_bytes;
+        decay.released_bytes += other_decay.released_bytes;
+    }
+
+    fn is_valid(&self) -> bool {
+        self.overlaps == 0 && self.adjacents == 0 && self.out_of_orders == 0
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Gauge,
+    Counter,
+}
+
+struct Family {
+    name: &'static str,
+    kind: Kind,
+    unit: Option<&'static str>,
+    help: &'static str,
+    value: fn(&AllocatorMetrics) -> usize,
+}
+
+// The names and order of these families are part of the public interface;
+// dashboards depend on them.
+const FAMILIES: &[Family] = &[
+    Family {
+        name: "basic_allocator_free_blocks",
+        kind: Kind::Gauge,
+        unit: None,
+        help: "Blocks in the free list.",
+        value: |m| m.free_blocks,
+    },
+    Family {
+        name: "basic_allocator_free_bytes",
+        kind: Kind::Gauge,
+        unit: Some("bytes"),
+        help: "Bytes in the free list.",
+        value: |m| m.free_bytes,
+    },
+    Family {
+        name: "basic_allocator_live_bytes",
+        kind: Kind::Gauge,
+        unit: Some("bytes"),
+        help: "Bytes handed out and not yet freed.",
+        value: |m| m.live_bytes,
+    },
+    Family {
+        name: "basic_allocator_regions",
+        kind: Kind::Gauge,
+        unit: None,
+        help: "Distinct memory regions obtained from the grower.",
+        value: |m| m.regions,
+    },
+    Family {
+        name: "basic_allocator_region_bytes",
+        kind: Kind::Gauge,
+        unit: Some("bytes"),
+        help: "Bytes in memory regions obtained from the grower.",
+        value: |m| m.region_bytes,
+    },
+    Family {
+        name: "basic_allocator_allocations",
+        kind: Kind::Counter,
+        unit: None,
+        help: "Allocations made.",
+        value: |m| m.allocations,
+    },
+    Family {
+        name: "basic_allocator_deallocations",
+        kind: Kind::Counter,
+        unit: None,
+        help: "Deallocations made.",
+        value: |m| m.deallocations,
+    },
+    Family {
+        name: "basic_allocator_free_list_valid",
+        kind: Kind::Gauge,
+        unit: None,
+        help: "1 if the free list is ordered, non-overlapping and fully merged.",
+        value: |m| m.is_valid() as usize,
+    },
+    Family {
+        name: "basic_allocator_free_list_overlaps",
+        kind: Kind::Gauge,
+        unit: None,
+        help: "Neighbouring free blocks that overlap.",
+        value: |m| m.overlaps,
+    },
+    Family {
+        name: "basic_allocator_free_list_adjacent",
+        kind: Kind::Gauge,
+        unit: None,
+        help: "Neighbouring free blocks that touch but were not merged.",
+        value: |m| m.adjacents,
+    },
+    Family {
+        name: "basic_allocator_free_list_out_of_order",
+        kind: Kind::Gauge,
+        unit: None,
+        help: "Neighbouring free blocks in the wrong order.",
+        value: |m| m.out_of_orders,
+    },
+    Family {
+        name: "basic_allocator_grower_attempts",
+        kind: Kind::Counter,
+        unit: None,
+        help: "Requests to the grower for more memory.",
+        value: |m| m.grower.attempts,
+    },
+    Family {
+        name: "basic_allocator_grower_growths",
+        kind: Kind::Counter,
+        unit: None,
+        help: "Requests to the grower that returned memory.",
+        value: |m| m.grower.growths,
+    },
+    Family {
+        name: "basic_allocator_grower_mapped_bytes",
+        kind: Kind::Gauge,
+        unit: Some("bytes"),
+        help: "Bytes currently obtained by the grower from the system.",
+        value: |m| m.grower.mapped_bytes,
+    },
+    Family {
+        name: "basic_allocator_grower_peak_bytes",
+        kind: Kind::Gauge,
+        unit: Some("bytes"),
+        help: "Most bytes ever obtained by the grower from the system at once.",
+        value: |m| m.grower.peak_bytes,
+    },
+    Family {
+        name: "basic_allocator_grower_hugetlb_bytes",
+        kind: Kind::Gauge,
+        unit: Some("bytes"),
+        help: "Bytes obtained by the grower as explicit huge pages.",
+        value: |m| m.grower.hugetlb_bytes,
+    },
+    Family {
+        name: "basic_allocator_grower_transparent_huge_bytes",
+        kind: Kind::Gauge,
+        unit: Some("bytes"),
+        help: "Bytes obtained by the grower and advised to use transparent huge pages.",
+        value: |m| m.grower.transparent_huge_bytes,
+    },
+    Family {
+        name: "basic_allocator_dirty_bytes",
+        kind: Kind::Gauge,
+        unit: Some("bytes"),
+        help: "Whole free pages still backed by memory.",
+        value: |m| m.decay.dirty_bytes,
+    },
+    Family {
+        name: "basic_allocator_muzzy_bytes",
+        kind: Kind::Gauge,
+        unit: Some("bytes"),
+        help: "Whole free pages the system may reclaim when it needs to.",
+        value: |m| m.decay.muzzy_bytes,
+    },
+    Family {
+        name: "basic_allocator_released_bytes",
+        kind: Kind::Gauge,
+        unit: Some("bytes"),
+        help: "Whole free pages given back to the system.",
+        value: |m| m.decay.released_bytes,
+    },
+    Family {
+        name: "basic_allocator_prefaulted_bytes",
+        kind: Kind::Gauge,
+        unit: Some("bytes"),
+        help: "Free bytes faulted in ahead of use and not handed out since.",
+        value: |m| m.prefaulted_bytes,
+    },
+];
+
+/// Writes `heaps` as OpenMetrics text, ending with `# EOF`.
+///
+/// With a single heap and no `label`, samples are unlabelled. Otherwise each
+/// heap's samples carry `label="<index>"`, e.g. `arena="3"`.
+pub fn write_openmetrics<W: Write>(
+    w: &mut W,
+    label: Option<&str>,
+    heaps: &[AllocatorMetrics],
+) -> fmt::Result {
+    for family in FAMILIES {
+        let kind = match family.kind {
+            Kind::Gauge => "gauge",
+            Kind::Counter => "counter",
+        };
+        writeln!(w, "# TYPE {} {}", family.name, kind)?;
+        if let Some(unit) = family.unit {
+            writeln!(w, "# UNIT {} {}", family.name, unit)?;
+        }
+        writeln!(w, "# HELP {} {}", family.name, family.help)?;
+
+        let suffix = match family.kind {
+            Kind::Gauge => "",
+            Kind::Counter => "_total",
+        };
+        for (i, heap) in heaps.iter().enumerate() {
+            w.write_str(family.name)?;
+            w.write_str(suffix)?;
+            match label {
+                Some(label) => write!(w, "{{{}=\"{}\"}}", label, i)?,
+                None if heaps.len() > 1 => write!(w, "{{index=\"{}\"}}", i)?,
+                None => {}
+            }
+            writeln!(w, " {}", (family.value)(heap))?;
+        }
+    }
+    w.write_str("# EOF\n")
+}
+
+/// A `core::fmt::Write` sink over a caller-provided buffer. Writes that do not
+/// fit fail with `fmt::Error`, leaving what did fit in place.
+pub struct FixedBuf<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> FixedBuf<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        FixedBuf { buf, len: 0 }
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only whole `&str`s are ever copied in.
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or_default()
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Write for FixedBuf<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len.checked_add(s.len()).ok_or(fmt::Error)?;
+        let dest = self.buf.get_mut(self.len..end).ok_or(fmt::Error)?;
+        dest.copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocators::{RawAlloc, ToyHeap};
+    use core::alloc::Layout;
+    use test_log::test;
+
+    #[test]
+    fn test_render() {
+        let mut allocator = RawAlloc::new(ToyHeap::default());
+        let layout = Layout::from_size_align(32, 16).unwrap();
+        unsafe { allocator.alloc(layout) };
+
+        let mut buf = [0u8; 4096];
+        let mut out = FixedBuf::new(&mut buf);
+        allocator.write_openmetrics(&mut out).unwrap();
+        let text = out.as_str();
+        log::info!("{}", text);
+
+        for line in [
+            "# TYPE basic_allocator_free_bytes gauge",
+            "# UNIT basic_allocator_free_bytes bytes",
+            "basic_allocator_free_bytes 32",
+            "basic_allocator_live_bytes 32",
+            "basic_allocator_allocations_total 1",
+            "basic_allocator_free_list_valid 1",
+            "basic_allocator_grower_mapped_bytes 64",
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {:?}", line);
+        }
+        assert!(text.ends_with("\n# EOF\n"));
+
+        let heaps = [allocator.metrics(), AllocatorMetrics::default()];
+        let mut buf = [0u8; 8192];
+        let mut out = FixedBuf::new(&mut buf);
+        write_openmetrics(&mut out, Some("arena"), &heaps).unwrap();
+        let text = out.as_str();
+        assert!(text.contains("\nbasic_allocator_free_bytes{arena=\"0\"} 32\nbasic_allocator_free_bytes{arena=\"1\"} 0\n"));
+
+        let mut small = [0u8; 16];
+        assert!(write_openmetrics(&mut FixedBuf::new(&mut small), None, &heaps[..1]).is_err());
+    }
+}
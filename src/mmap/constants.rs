@@ -1,9 +1,46 @@
 // Protection flags
+pub const PROT_NONE: u64 = 0x00;
 pub const PROT_READ: u64 = 0x01;
 pub const PROT_WRITE: u64 = 0x02;
 
 // Mapping flags
+pub const MAP_SHARED: u64 = 0x01;
 pub const MAP_PRIVATE: u64 = 0x02;
+pub const MAP_FIXED: u64 = 0x10;
+
+#[cfg(target_os = "linux")]
+pub const MAP_NORESERVE: u64 = 0x4000;
+
+// Faults the pages in before mmap returns.
+#[cfg(target_os = "linux")]
+pub const MAP_POPULATE: u64 = 0x8000;
+
+#[cfg(target_os = "linux")]
+pub const MAP_HUGETLB: u64 = 0x40000;
+
+// Selects the huge page size for MAP_HUGETLB, as log2 of the size.
+#[cfg(target_os = "linux")]
+pub const MAP_HUGE_SHIFT: u64 = 26;
+
+#[cfg(target_os = "linux")]
+pub const MAP_HUGE_2MB: u64 = 21 << MAP_HUGE_SHIFT;
+
+// madvise advice
+#[cfg(target_os = "linux")]
+pub const MADV_DONTNEED: u64 = 4;
+
+#[cfg(target_os = "linux")]
+pub const MADV_FREE: u64 = 8;
+
+#[cfg(target_os = "linux")]
+pub const MADV_HUGEPAGE: u64 = 14;
+
+// msync flags
+#[cfg(target_os = "linux")]
+pub const MS_SYNC: u64 = 4;
+
+// lseek whence
+pub const SEEK_END: u64 = 2;
 
 #[cfg(target_os = "macos")]
 pub const MAP_ANON: u64 = 0x1000;
@@ -18,15 +55,261 @@ pub const MAP_ANON: u64 = 0x20;
 #[cfg(target_os = "macos")]
 pub(crate) const SYS_MMAP: i64 = 0x2000000 + 197;
 
-#[cfg(target_os = "linux")]
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
 pub(crate) const SYS_MMAP: i64 = 9;
 
-#[cfg(target_os = "linux")]
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub(crate) const SYS_MPROTECT: i64 = 10;
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
 pub(crate) const SYS_MUNMAP: i64 = 11;
 
-#[cfg(target_os = "linux")]
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
 pub(crate) const SYS_MREMAP: i64 = 25;
 
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub(crate) const SYS_BRK: i64 = 12;
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub(crate) const SYS_LSEEK: i64 = 8;
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub(crate) const SYS_MSYNC: i64 = 26;
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub(crate) const SYS_FTRUNCATE: i64 = 77;
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub(crate) const SYS_MADVISE: i64 = 28;
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub(crate) const SYS_MBIND: i64 = 237;
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub(crate) const SYS_SET_MEMPOLICY: i64 = 238;
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub(crate) const SYS_GET_MEMPOLICY: i64 = 239;
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub(crate) const SYS_GETCPU: i64 = 309;
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub(crate) const SYS_GETTID: i64 = 186;
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub(crate) const SYS_SCHED_GETAFFINITY: i64 = 204;
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub(crate) const SYS_OPENAT: i64 = 257;
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub(crate) const SYS_READ: i64 = 0;
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub(crate) const SYS_CLOSE: i64 = 3;
+
+// aarch64 and riscv64 share the kernel's generic system call table.
+#[cfg(all(target_os = "linux", any(target_arch = "aarch64", target_arch = "riscv64")))]
+pub(crate) const SYS_MMAP: i64 = 222;
+
+#[cfg(all(target_os = "linux", any(target_arch = "aarch64", target_arch = "riscv64")))]
+pub(crate) const SYS_MPROTECT: i64 = 226;
+
+#[cfg(all(target_os = "linux", any(target_arch = "aarch64", target_arch = "riscv64")))]
+pub(crate) const SYS_MUNMAP: i64 = 215;
+
+#[cfg(all(target_os = "linux", any(target_arch = "aarch64", target_arch = "riscv64")))]
+pub(crate) const SYS_MREMAP: i64 = 216;
+
+#[cfg(all(target_os = "linux", any(target_arch = "aarch64", target_arch = "riscv64")))]
+pub(crate) const SYS_BRK: i64 = 214;
+
+#[cfg(all(target_os = "linux", any(target_arch = "aarch64", target_arch = "riscv64")))]
+pub(crate) const SYS_LSEEK: i64 = 62;
+
+#[cfg(all(target_os = "linux", any(target_arch = "aarch64", target_arch = "riscv64")))]
+pub(crate) const SYS_MSYNC: i64 = 227;
+
+#[cfg(all(target_os = "linux", any(target_arch = "aarch64", target_arch = "riscv64")))]
+pub(crate) const SYS_FTRUNCATE: i64 = 46;
+
+#[cfg(all(target_os = "linux", any(target_arch = "aarch64", target_arch = "riscv64")))]
+pub(crate) const SYS_MADVISE: i64 = 233;
+
+#[cfg(all(target_os = "linux", any(target_arch = "aarch64", target_arch = "riscv64")))]
+pub(crate) const SYS_MBIND: i64 = 235;
+
+#[cfg(all(target_os = "linux", any(target_arch = "aarch64", target_arch = "riscv64")))]
+pub(crate) const SYS_SET_MEMPOLICY: i64 = 237;
+
+#[cfg(all(target_os = "linux", any(target_arch = "aarch64", target_arch = "riscv64")))]
+pub(crate) const SYS_GET_MEMPOLICY: i64 = 236;
+
+#[cfg(all(target_os = "linux", any(target_arch = "aarch64", target_arch = "riscv64")))]
+pub(crate) const SYS_GETCPU: i64 = 168;
+
+#[cfg(all(target_os = "linux", any(target_arch = "aarch64", target_arch = "riscv64")))]
+pub(crate) const SYS_GETTID: i64 = 178;
+
+#[cfg(all(target_os = "linux", any(target_arch = "aarch64", target_arch = "riscv64")))]
+pub(crate) const SYS_SCHED_GETAFFINITY: i64 = 123;
+
+#[cfg(all(target_os = "linux", any(target_arch = "aarch64", target_arch = "riscv64")))]
+pub(crate) const SYS_OPENAT: i64 = 56;
+
+#[cfg(all(target_os = "linux", any(target_arch = "aarch64", target_arch = "riscv64")))]
+pub(crate) const SYS_READ: i64 = 63;
+
+#[cfg(all(target_os = "linux", any(target_arch = "aarch64", target_arch = "riscv64")))]
+pub(crate) const SYS_CLOSE: i64 = 57;
+
+// i686; SYS_MMAP is mmap2.
+#[cfg(all(target_os = "linux", target_arch = "x86"))]
+pub(crate) const SYS_MMAP: i64 = 192;
+
+#[cfg(all(target_os = "linux", target_arch = "x86"))]
+pub(crate) const SYS_MPROTECT: i64 = 125;
+
+#[cfg(all(target_os = "linux", target_arch = "x86"))]
+pub(crate) const SYS_MUNMAP: i64 = 91;
+
+#[cfg(all(target_os = "linux", target_arch = "x86"))]
+pub(crate) const SYS_MREMAP: i64 = 163;
+
+#[cfg(all(target_os = "linux", target_arch = "x86"))]
+pub(crate) const SYS_BRK: i64 = 45;
+
+#[cfg(all(target_os = "linux", target_arch = "x86"))]
+pub(crate) const SYS_LSEEK: i64 = 19;
+
+#[cfg(all(target_os = "linux", target_arch = "x86"))]
+pub(crate) const SYS_MSYNC: i64 = 144;
+
+#[cfg(all(target_os = "linux", target_arch = "x86"))]
+pub(crate) const SYS_FTRUNCATE: i64 = 93;
+
+#[cfg(all(target_os = "linux", target_arch = "x86"))]
+pub(crate) const SYS_MADVISE: i64 = 219;
+
+#[cfg(all(target_os = "linux", target_arch = "x86"))]
+pub(crate) const SYS_MBIND: i64 = 274;
+
+#[cfg(all(target_os = "linux", target_arch = "x86"))]
+pub(crate) const SYS_SET_MEMPOLICY: i64 = 276;
+
+#[cfg(all(target_os = "linux", target_arch = "x86"))]
+pub(crate) const SYS_GET_MEMPOLICY: i64 = 275;
+
+#[cfg(all(target_os = "linux", target_arch = "x86"))]
+pub(crate) const SYS_GETCPU: i64 = 318;
+
+#[cfg(all(target_os = "linux", target_arch = "x86"))]
+pub(crate) const SYS_GETTID: i64 = 224;
+
+#[cfg(all(target_os = "linux", target_arch = "x86"))]
+pub(crate) const SYS_SCHED_GETAFFINITY: i64 = 242;
+
+#[cfg(all(target_os = "linux", target_arch = "x86"))]
+pub(crate) const SYS_OPENAT: i64 = 295;
+
+#[cfg(all(target_os = "linux", target_arch = "x86"))]
+pub(crate) const SYS_READ: i64 = 3;
+
+#[cfg(all(target_os = "linux", target_arch = "x86"))]
+pub(crate) const SYS_CLOSE: i64 = 6;
+
+// armv7; SYS_MMAP is mmap2.
+#[cfg(all(target_os = "linux", target_arch = "arm"))]
+pub(crate) const SYS_MMAP: i64 = 192;
+
+#[cfg(all(target_os = "linux", target_arch = "arm"))]
+pub(crate) const SYS_MPROTECT: i64 = 125;
+
+#[cfg(all(target_os = "linux", target_arch = "arm"))]
+pub(crate) const SYS_MUNMAP: i64 = 91;
+
+#[cfg(all(target_os = "linux", target_arch = "arm"))]
+pub(crate) const SYS_MREMAP: i64 = 163;
+
+#[cfg(all(target_os = "linux", target_arch = "arm"))]
+pub(crate) const SYS_BRK: i64 = 45;
+
+#[cfg(all(target_os = "linux", target_arch = "arm"))]
+pub(crate) const SYS_LSEEK: i64 = 19;
+
+#[cfg(all(target_os = "linux", target_arch = "arm"))]
+pub(crate) const SYS_MSYNC: i64 = 144;
+
+#[cfg(all(target_os = "linux", target_arch = "arm"))]
+pub(crate) const SYS_FTRUNCATE: i64 = 93;
+
+#[cfg(all(target_os = "linux", target_arch = "arm"))]
+pub(crate) const SYS_MADVISE: i64 = 220;
+
+#[cfg(all(target_os = "linux", target_arch = "arm"))]
+pub(crate) const SYS_MBIND: i64 = 319;
+
+#[cfg(all(target_os = "linux", target_arch = "arm"))]
+pub(crate) const SYS_SET_MEMPOLICY: i64 = 321;
+
+#[cfg(all(target_os = "linux", target_arch = "arm"))]
+pub(crate) const SYS_GET_MEMPOLICY: i64 = 320;
+
+#[cfg(all(target_os = "linux", target_arch = "arm"))]
+pub(crate) const SYS_GETCPU: i64 = 345;
+
+#[cfg(all(target_os = "linux", target_arch = "arm"))]
+pub(crate) const SYS_GETTID: i64 = 224;
+
+#[cfg(all(target_os = "linux", target_arch = "arm"))]
+pub(crate) const SYS_SCHED_GETAFFINITY: i64 = 242;
+
+#[cfg(all(target_os = "linux", target_arch = "arm"))]
+pub(crate) const SYS_OPENAT: i64 = 322;
+
+#[cfg(all(target_os = "linux", target_arch = "arm"))]
+pub(crate) const SYS_READ: i64 = 3;
+
+#[cfg(all(target_os = "linux", target_arch = "arm"))]
+pub(crate) const SYS_CLOSE: i64 = 6;
+
+// NUMA memory policies
+#[cfg(target_os = "linux")]
+pub const MPOL_DEFAULT: u64 = 0;
+
+#[cfg(target_os = "linux")]
+pub const MPOL_PREFERRED: u64 = 1;
+
+#[cfg(target_os = "linux")]
+pub const MPOL_BIND: u64 = 2;
+
+#[cfg(target_os = "linux")]
+pub const MPOL_INTERLEAVE: u64 = 3;
+
+// get_mempolicy flags
+#[cfg(target_os = "linux")]
+pub const MPOL_F_NODE: u64 = 1;
+
+#[cfg(target_os = "linux")]
+pub const MPOL_F_ADDR: u64 = 2;
+
+// Reading the auxiliary vector, for the page size
+#[cfg(target_os = "linux")]
+pub(crate) const AT_FDCWD: i64 = -100;
+
+#[cfg(target_os = "linux")]
+pub(crate) const O_RDONLY: u64 = 0;
+
+#[cfg(target_os = "linux")]
+pub(crate) const O_CLOEXEC: u64 = 0x80000;
+
+#[cfg(target_os = "linux")]
+pub(crate) const AT_NULL: usize = 0;
+
+#[cfg(target_os = "linux")]
+pub(crate) const AT_PAGESZ: usize = 6;
+
 // mremap flags
 #[cfg(target_os = "linux")]
 pub const MREMAP_MAYMOVE: u64 = 1;
@@ -1,5 +1,4 @@
 #[derive(Debug)]
 pub struct MmapError {
     pub code: i64,
-    pub message: &'static str,
 }
\ No newline at end of file
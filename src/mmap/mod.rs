@@ -1,4 +1,6 @@
-#![cfg(not(feature = "use_libc"))]
+// `RawBackend` is built with or without libc; with it, only the calls the
+// backend makes are used.
+#![cfg_attr(feature = "use_libc", allow(dead_code, unused_imports))]
 
 mod constants;
 mod error;
@@ -8,4 +10,9 @@ mod tests;
 
 pub use constants::*;
 pub use error::MmapError;
-pub use platform::{mmap, munmap,mremap};
\ No newline at end of file
+pub use platform::{mmap, munmap,mremap};
+#[cfg(target_os = "linux")]
+pub use platform::{
+    brk, ftruncate, get_mempolicy, getcpu, gettid, lseek, madvise, mbind, mprotect, msync,
+    page_size, sched_getaffinity, set_mempolicy,
+};
\ No newline at end of file
@@ -1,5 +1,6 @@
 mod unix;
 mod windows;
+#[cfg(target_arch = "wasm32")]
 mod wasm;
 
 use crate::mmap::error::MmapError;
@@ -42,3 +43,88 @@ pub unsafe fn mremap(
     #[cfg(target_arch = "wasm32")]
     return wasm::mremap(old_addr, old_size, new_size, flags);
 }
+
+/// Moves the program break to `addr`, returning the new break; a null `addr`
+/// just queries it.
+#[cfg(target_os = "linux")]
+pub unsafe fn brk(addr: *mut u8) -> *mut u8 {
+    unix::brk(addr)
+}
+
+#[cfg(target_os = "linux")]
+pub unsafe fn lseek(fd: u64, offset: i64, whence: u64) -> Result<u64, MmapError> {
+    unix::lseek(fd, offset, whence)
+}
+
+#[cfg(target_os = "linux")]
+pub unsafe fn ftruncate(fd: u64, len: u64) -> Result<(), MmapError> {
+    unix::ftruncate(fd, len)
+}
+
+#[cfg(target_os = "linux")]
+pub unsafe fn msync(addr: *mut u8, len: usize, flags: u64) -> Result<(), MmapError> {
+    unix::msync(addr, len, flags)
+}
+
+#[cfg(target_os = "linux")]
+pub unsafe fn madvise(addr: *mut u8, len: usize, advice: u64) -> Result<(), MmapError> {
+    unix::madvise(addr, len, advice)
+}
+
+#[cfg(target_os = "linux")]
+pub unsafe fn mprotect(addr: *mut u8, len: usize, prot: u64) -> Result<(), MmapError> {
+    unix::mprotect(addr, len, prot)
+}
+
+#[cfg(target_os = "linux")]
+pub unsafe fn mbind(
+    addr: *mut u8,
+    len: usize,
+    mode: u64,
+    nodemask: *const u64,
+    maxnode: u64,
+    flags: u64,
+) -> Result<(), MmapError> {
+    unix::mbind(addr, len, mode, nodemask, maxnode, flags)
+}
+
+#[cfg(target_os = "linux")]
+pub unsafe fn set_mempolicy(mode: u64, nodemask: *const u64, maxnode: u64) -> Result<(), MmapError> {
+    unix::set_mempolicy(mode, nodemask, maxnode)
+}
+
+#[cfg(target_os = "linux")]
+pub unsafe fn get_mempolicy(
+    mode: *mut i32,
+    nodemask: *mut u64,
+    maxnode: u64,
+    addr: *mut u8,
+    flags: u64,
+) -> Result<(), MmapError> {
+    unix::get_mempolicy(mode, nodemask, maxnode, addr, flags)
+}
+
+/// Stores the CPU the calling thread is running on in `cpu`.
+#[cfg(target_os = "linux")]
+pub unsafe fn getcpu(cpu: *mut u32) -> Result<(), MmapError> {
+    unix::getcpu(cpu)
+}
+
+/// The calling thread's id.
+#[cfg(target_os = "linux")]
+pub unsafe fn gettid() -> u32 {
+    unix::gettid()
+}
+
+/// Stores the calling thread's CPU affinity mask, `len` bytes at most, in
+/// `mask`, returning how many bytes were stored.
+#[cfg(target_os = "linux")]
+pub unsafe fn sched_getaffinity(mask: *mut u64, len: usize) -> Result<usize, MmapError> {
+    unix::sched_getaffinity(mask, len)
+}
+
+/// The system's page size, or `None` if it cannot be found out.
+#[cfg(target_os = "linux")]
+pub fn page_size() -> Option<usize> {
+    unsafe { unix::page_size() }
+}
@@ -1,4 +1,4 @@
-use super::syscall::{syscall_mmap, syscall_munmap, syscall_mremap};
+use super::syscall::{error_code, syscall3, syscall6, syscall_brk, syscall_mmap, syscall_munmap, syscall_mremap};
 use crate::mmap::constants::*;
 use crate::mmap::error::MmapError;
 
@@ -11,6 +11,11 @@ pub(crate) unsafe fn unix_mmap(
     fd: u64,
     offset: i64,
 ) -> Result<*mut u8, MmapError> {
+    // 32-bit targets map with mmap2, which takes the offset in 4096-byte
+    // units, so that files past 4 GiB can be mapped.
+    #[cfg(target_pointer_width = "32")]
+    let offset = offset / 4096;
+
     syscall_mmap(SYS_MMAP, addr, len, prot, flags, fd, offset)
 }
 #[inline(always)]
@@ -27,3 +32,112 @@ pub(crate) unsafe fn mremap(
 ) -> Result<*mut u8, MmapError> {
     syscall_mremap(SYS_MREMAP, old_addr, old_size, new_size, flags)
 }
+
+#[inline(always)]
+pub(crate) unsafe fn brk(addr: *mut u8) -> *mut u8 {
+    syscall_brk(SYS_BRK, addr)
+}
+
+#[inline(always)]
+fn check(result: i64) -> Result<u64, MmapError> {
+    if let Some(code) = error_code(result) {
+        return Err(MmapError { code });
+    }
+    Ok(result as u64)
+}
+
+#[inline(always)]
+pub(crate) unsafe fn lseek(fd: u64, offset: i64, whence: u64) -> Result<u64, MmapError> {
+    check(syscall3(SYS_LSEEK, fd, offset as u64, whence))
+}
+
+#[inline(always)]
+pub(crate) unsafe fn ftruncate(fd: u64, len: u64) -> Result<(), MmapError> {
+    check(syscall3(SYS_FTRUNCATE, fd, len, 0)).map(|_| ())
+}
+
+#[inline(always)]
+pub(crate) unsafe fn msync(addr: *mut u8, len: usize, flags: u64) -> Result<(), MmapError> {
+    check(syscall3(SYS_MSYNC, addr as u64, len as u64, flags)).map(|_| ())
+}
+
+#[inline(always)]
+pub(crate) unsafe fn madvise(addr: *mut u8, len: usize, advice: u64) -> Result<(), MmapError> {
+    check(syscall3(SYS_MADVISE, addr as u64, len as u64, advice)).map(|_| ())
+}
+
+#[inline(always)]
+pub(crate) unsafe fn mprotect(addr: *mut u8, len: usize, prot: u64) -> Result<(), MmapError> {
+    check(syscall3(SYS_MPROTECT, addr as u64, len as u64, prot)).map(|_| ())
+}
+
+#[inline(always)]
+pub(crate) unsafe fn mbind(
+    addr: *mut u8,
+    len: usize,
+    mode: u64,
+    nodemask: *const u64,
+    maxnode: u64,
+    flags: u64,
+) -> Result<(), MmapError> {
+    let result = syscall6(SYS_MBIND, addr as u64, len as u64, mode, nodemask as u64, maxnode, flags);
+    check(result).map(|_| ())
+}
+
+#[inline(always)]
+pub(crate) unsafe fn set_mempolicy(mode: u64, nodemask: *const u64, maxnode: u64) -> Result<(), MmapError> {
+    check(syscall3(SYS_SET_MEMPOLICY, mode, nodemask as u64, maxnode)).map(|_| ())
+}
+
+#[inline(always)]
+pub(crate) unsafe fn get_mempolicy(
+    mode: *mut i32,
+    nodemask: *mut u64,
+    maxnode: u64,
+    addr: *mut u8,
+    flags: u64,
+) -> Result<(), MmapError> {
+    let result = syscall6(SYS_GET_MEMPOLICY, mode as u64, nodemask as u64, maxnode, addr as u64, flags, 0);
+    check(result).map(|_| ())
+}
+
+#[inline(always)]
+pub(crate) unsafe fn getcpu(cpu: *mut u32) -> Result<(), MmapError> {
+    // The node, and the long-unused cache, are not asked for.
+    check(syscall3(SYS_GETCPU, cpu as u64, 0, 0)).map(|_| ())
+}
+
+#[inline(always)]
+pub(crate) unsafe fn gettid() -> u32 {
+    // Cannot fail.
+    syscall3(SYS_GETTID, 0, 0, 0) as u32
+}
+
+#[inline(always)]
+pub(crate) unsafe fn sched_getaffinity(mask: *mut u64, len: usize) -> Result<usize, MmapError> {
+    // The kernel's mask is written, and its size returned.
+    check(syscall3(SYS_SCHED_GETAFFINITY, 0, len as u64, mask as u64))
+        .map(|size| size as usize)
+}
+
+/// The page size, from the `AT_PAGESZ` entry of the auxiliary vector the
+/// kernel passed the process, or `None` if `/proc` cannot be read.
+pub(crate) unsafe fn page_size() -> Option<usize> {
+    let path = b"/proc/self/auxv\0";
+    let fd = syscall3(SYS_OPENAT, AT_FDCWD as u64, path.as_ptr() as u64, O_RDONLY | O_CLOEXEC);
+    if error_code(fd).is_some() {
+        return None;
+    }
+    // Entries are pairs of words, a type and a value, ending with AT_NULL.
+    let mut entry = [0usize; 2];
+    let len = core::mem::size_of_val(&entry) as i64;
+    let mut page_size = None;
+    while syscall3(SYS_READ, fd as u64, entry.as_mut_ptr() as u64, len as u64) == len && entry[0] != AT_NULL {
+        if entry[0] == AT_PAGESZ {
+            page_size = Some(entry[1]);
+            break;
+        }
+    }
+    syscall3(SYS_CLOSE, fd as u64, 0, 0);
+    page_size
+}
@@ -16,7 +16,6 @@ pub(crate) unsafe fn mmap(
     if result.is_err() || (result.as_ref().unwrap() as usize) & (1 << 63) != 0 {
         Err(MmapError {
             code: -(result.unwrap_or(0 as *mut u8) as i64),
-            message: "mmap syscall failed on macOS",
         })
     } else {
         result
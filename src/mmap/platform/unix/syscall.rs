@@ -1,6 +1,17 @@
 use core::arch::asm;
 use crate::mmap::error::MmapError;
 
+/// The error code in a raw syscall result. The kernel returns errors as
+/// -4095..=-1; anything else is a success, including an address in the top
+/// half of a 32-bit address space, which is negative once sign-extended.
+#[inline(always)]
+pub(crate) fn error_code(result: i64) -> Option<i64> {
+    if (-4095..0).contains(&result) {
+        return Some(-result);
+    }
+    None
+}
+
 #[inline(always)]
 pub(crate) unsafe fn syscall_mmap(
     syscall_num: i64,
@@ -11,25 +22,11 @@ pub(crate) unsafe fn syscall_mmap(
     fd: u64,
     offset: i64,
 ) -> Result<*mut u8, MmapError> {
-    let mut out_addr: i64;
-    
-    asm!(
-        "syscall",
-        in("rax") syscall_num,
-        in("rdi") addr as i64,
-        in("rsi") len,
-        in("rdx") prot,
-        in("r10") flags,
-        in("r8") fd,
-        in("r9") offset,
-        lateout("rax") out_addr,
-        options(nostack),
-    );
+    let out_addr = syscall6(syscall_num, addr as u64, len as u64, prot, flags, fd, offset as u64);
 
-    if out_addr < 0 {
+    if let Some(code) = error_code(out_addr) {
         return Err(MmapError {
-            code: -out_addr,
-            message: "mmap syscall failed",
+            code,
         });
     }
 
@@ -42,20 +39,11 @@ pub(crate) unsafe fn syscall_munmap(
     addr: *mut u8,
     len: usize,
 ) -> Result<(), MmapError> {
-    let result: i64;
-    
-    asm!(
-        "syscall",
-        inout("rax") syscall_num => result,
-        in("rdi") addr as i64,
-        in("rsi") len as i64,
-        options(nostack),
-    );
+    let result = syscall3(syscall_num, addr as u64, len as u64, 0);
 
-    if result != 0 {
+    if let Some(code) = error_code(result) {
         return Err(MmapError {
-            code: result,
-            message: "munmap syscall failed",
+            code,
         });
     }
 
@@ -70,25 +58,269 @@ pub(crate) unsafe fn syscall_mremap(
     new_size: usize,
     flags: u64,
 ) -> Result<*mut u8, MmapError> {
-    let mut out_addr: i64;
-    
-    asm!(
-        "syscall",
-        in("rax") syscall_num,
-        in("rdi") old_addr as i64,
-        in("rsi") old_size,
-        in("rdx") new_size,
-        in("r10") flags,
-        lateout("rax") out_addr,
-        options(nostack),
-    );
+    let out_addr = syscall6(syscall_num, old_addr as u64, old_size as u64, new_size as u64, flags, 0, 0);
 
-    if out_addr < 0 {
+    if let Some(code) = error_code(out_addr) {
         return Err(MmapError {
-            code: -out_addr,
-            message: "mremap syscall failed",
+            code,
         });
     }
 
     Ok(out_addr as *mut u8)
 }
+
+/// `brk` returns the new program break, or the unchanged old one if it could
+/// not be moved; it never returns an error code.
+#[inline(always)]
+pub(crate) unsafe fn syscall_brk(syscall_num: i64, addr: *mut u8) -> *mut u8 {
+    syscall3(syscall_num, addr as u64, 0, 0) as *mut u8
+}
+
+/// A system call taking up to three arguments. Returns the raw result, which
+/// is a negated error code on failure.
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+pub(crate) unsafe fn syscall3(syscall_num: i64, arg1: u64, arg2: u64, arg3: u64) -> i64 {
+    let result: i64;
+
+    asm!(
+        "syscall",
+        inout("rax") syscall_num => result,
+        in("rdi") arg1,
+        in("rsi") arg2,
+        in("rdx") arg3,
+        lateout("rcx") _,
+        lateout("r11") _,
+        options(nostack),
+    );
+
+    result
+}
+
+/// A system call taking up to six arguments. Returns the raw result, which
+/// is a negated error code on failure.
+#[cfg(target_arch = "x86_64")]
+#[inline(always)]
+pub(crate) unsafe fn syscall6(
+    syscall_num: i64,
+    arg1: u64,
+    arg2: u64,
+    arg3: u64,
+    arg4: u64,
+    arg5: u64,
+    arg6: u64,
+) -> i64 {
+    let result: i64;
+
+    asm!(
+        "syscall",
+        inout("rax") syscall_num => result,
+        in("rdi") arg1,
+        in("rsi") arg2,
+        in("rdx") arg3,
+        in("r10") arg4,
+        in("r8") arg5,
+        in("r9") arg6,
+        lateout("rcx") _,
+        lateout("r11") _,
+        options(nostack),
+    );
+
+    result
+}
+
+// On aarch64 the number goes in x8 and the result comes back in x0, over the
+// first argument.
+#[cfg(target_arch = "aarch64")]
+#[inline(always)]
+pub(crate) unsafe fn syscall3(syscall_num: i64, arg1: u64, arg2: u64, arg3: u64) -> i64 {
+    let result: i64;
+
+    asm!(
+        "svc #0",
+        in("x8") syscall_num,
+        inlateout("x0") arg1 as i64 => result,
+        in("x1") arg2,
+        in("x2") arg3,
+        options(nostack),
+    );
+
+    result
+}
+
+#[cfg(target_arch = "aarch64")]
+#[inline(always)]
+pub(crate) unsafe fn syscall6(
+    syscall_num: i64,
+    arg1: u64,
+    arg2: u64,
+    arg3: u64,
+    arg4: u64,
+    arg5: u64,
+    arg6: u64,
+) -> i64 {
+    let result: i64;
+
+    asm!(
+        "svc #0",
+        in("x8") syscall_num,
+        inlateout("x0") arg1 as i64 => result,
+        in("x1") arg2,
+        in("x2") arg3,
+        in("x3") arg4,
+        in("x4") arg5,
+        in("x5") arg6,
+        options(nostack),
+    );
+
+    result
+}
+
+// On riscv64 the number goes in a7 and the result comes back in a0, over the
+// first argument.
+#[cfg(target_arch = "riscv64")]
+#[inline(always)]
+pub(crate) unsafe fn syscall3(syscall_num: i64, arg1: u64, arg2: u64, arg3: u64) -> i64 {
+    let result: i64;
+
+    asm!(
+        "ecall",
+        in("a7") syscall_num,
+        inlateout("a0") arg1 as i64 => result,
+        in("a1") arg2,
+        in("a2") arg3,
+        options(nostack),
+    );
+
+    result
+}
+
+#[cfg(target_arch = "riscv64")]
+#[inline(always)]
+pub(crate) unsafe fn syscall6(
+    syscall_num: i64,
+    arg1: u64,
+    arg2: u64,
+    arg3: u64,
+    arg4: u64,
+    arg5: u64,
+    arg6: u64,
+) -> i64 {
+    let result: i64;
+
+    asm!(
+        "ecall",
+        in("a7") syscall_num,
+        inlateout("a0") arg1 as i64 => result,
+        in("a1") arg2,
+        in("a2") arg3,
+        in("a3") arg4,
+        in("a4") arg5,
+        in("a5") arg6,
+        options(nostack),
+    );
+
+    result
+}
+
+// On i686 the number goes in eax and the arguments in ebx, ecx, edx, esi,
+// edi and ebp, with the result back in eax. Results are sign-extended, so
+// error codes come out negative as on 64-bit targets.
+#[cfg(target_arch = "x86")]
+#[inline(always)]
+pub(crate) unsafe fn syscall3(syscall_num: i64, arg1: u64, arg2: u64, arg3: u64) -> i64 {
+    let result: i32;
+
+    asm!(
+        "int 0x80",
+        inlateout("eax") syscall_num as i32 => result,
+        in("ebx") arg1 as u32,
+        in("ecx") arg2 as u32,
+        in("edx") arg3 as u32,
+        options(nostack),
+    );
+
+    result as i64
+}
+
+#[cfg(target_arch = "x86")]
+#[inline(always)]
+pub(crate) unsafe fn syscall6(
+    syscall_num: i64,
+    arg1: u64,
+    arg2: u64,
+    arg3: u64,
+    arg4: u64,
+    arg5: u64,
+    arg6: u64,
+) -> i64 {
+    let result: i32;
+    // LLVM reserves esi and ebp, so they are loaded by hand, along with the
+    // syscall number, from memory that eax points to.
+    let rest = [arg4 as u32, arg6 as u32, syscall_num as u32];
+
+    asm!(
+        "push ebp",
+        "push esi",
+        "mov esi, [eax]",
+        "mov ebp, [eax + 4]",
+        "mov eax, [eax + 8]",
+        "int 0x80",
+        "pop esi",
+        "pop ebp",
+        inlateout("eax") rest.as_ptr() => result,
+        in("ebx") arg1 as u32,
+        in("ecx") arg2 as u32,
+        in("edx") arg3 as u32,
+        in("edi") arg5 as u32,
+    );
+
+    result as i64
+}
+
+// On armv7 (EABI) the number goes in r7 and the result comes back in r0,
+// over the first argument.
+#[cfg(target_arch = "arm")]
+#[inline(always)]
+pub(crate) unsafe fn syscall3(syscall_num: i64, arg1: u64, arg2: u64, arg3: u64) -> i64 {
+    let result: i32;
+
+    asm!(
+        "svc #0",
+        in("r7") syscall_num as u32,
+        inlateout("r0") arg1 as i32 => result,
+        in("r1") arg2 as u32,
+        in("r2") arg3 as u32,
+        options(nostack),
+    );
+
+    result as i64
+}
+
+#[cfg(target_arch = "arm")]
+#[inline(always)]
+pub(crate) unsafe fn syscall6(
+    syscall_num: i64,
+    arg1: u64,
+    arg2: u64,
+    arg3: u64,
+    arg4: u64,
+    arg5: u64,
+    arg6: u64,
+) -> i64 {
+    let result: i32;
+
+    asm!(
+        "svc #0",
+        in("r7") syscall_num as u32,
+        inlateout("r0") arg1 as i32 => result,
+        in("r1") arg2 as u32,
+        in("r2") arg3 as u32,
+        in("r3") arg4 as u32,
+        in("r4") arg5 as u32,
+        in("r5") arg6 as u32,
+        options(nostack),
+    );
+
+    result as i64
+}
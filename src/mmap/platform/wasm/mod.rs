@@ -1,57 +1,31 @@
-use wasm_bindgen::prelude::*;
-use js_sys::{Object, WebAssembly, Uint8Array};
+use core::arch::wasm32;
 use crate::mmap::error::MmapError;
-extern crate alloc;
-use alloc::vec;
 
+const WASM_PAGE_SIZE: usize = 65536;
+
+// Linear memory only grows, and always at its end; `addr`, protection and
+// file mappings have no meaning here.
 pub(crate) unsafe fn wasm_mmap(
-    addr: *mut u8,
+    _addr: *mut u8,
     len: usize,
     _prot: u64,
     _flags: u64,
     _fd: u64,
     _offset: i64,
 ) -> Result<*mut u8, MmapError> {
-    let pages = (len + 65535) / 65536;
-    let descriptor = Object::new();
-    
-    js_sys::Reflect::set(
-        &descriptor,
-        &JsValue::from_str("initial"),
-        &JsValue::from_f64(pages as f64),
-    ).map_err(|_| MmapError {
-        code: -1,
-        message: "Failed to set memory descriptor".into(),
-    })?;
-
-    let memory = WebAssembly::Memory::new(&descriptor)
-        .map_err(|_| MmapError {
-            code: -1,
-            message: "Failed to allocate WebAssembly memory".into(),
-        })?;
-
-    let array = Uint8Array::new(&memory.buffer());
-    let ptr = if addr.is_null() {
-        let buffer = vec![0u8; len].as_mut_ptr();
-        array.raw_copy_to_ptr(buffer);
-        buffer
-    } else {
-        array.raw_copy_to_ptr(addr);
-        addr
-    };
-
-    if ptr.is_null() {
+    let pages = len.div_ceil(WASM_PAGE_SIZE);
+    let previous = wasm32::memory_grow::<0>(pages);
+    if previous == usize::MAX {
         return Err(MmapError {
             code: -1,
-            message: "Failed to get WebAssembly memory pointer".into(),
         });
     }
 
-    Ok(ptr)
+    Ok((previous * WASM_PAGE_SIZE) as *mut u8)
 }
 
-pub(crate) unsafe fn wasm_munmap(addr: *mut u8, _len: usize) -> Result<(), MmapError> {
-    // WebAssembly memory is managed by the runtime
+pub(crate) unsafe fn wasm_munmap(_addr: *mut u8, _len: usize) -> Result<(), MmapError> {
+    // WebAssembly memory cannot be shrunk
     Ok(())
 }
 
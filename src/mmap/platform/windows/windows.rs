@@ -41,7 +41,6 @@ pub(crate) unsafe fn windows_mmap(
     if ptr.is_null() {
         return Err(MmapError {
             code: -1,
-            message: "VirtualAlloc failed",
         });
     }
     
@@ -54,7 +53,6 @@ pub(crate) unsafe fn windows_munmap(addr: *mut u8) -> Result<(), MmapError> {
     if success == 0 {
         return Err(MmapError {
             code: -1,
-            message: "VirtualFree failed",
         });
     }
     
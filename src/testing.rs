@@ -0,0 +1,434 @@
+//! Model-based randomized testing for `RawAlloc` over any `HeapGrower`.
+//!
+//! A random sequence of [`Op`]s is run against a fresh allocator while a
+//! reference model tracks what every live allocation should contain. After
+//! each step the harness checks that:
+//!
+//! - live allocations never overlap,
+//! - every pointer honours its layout's alignment,
+//! - contents survive other operations and `realloc`,
+//! - `calloc` memory is zeroed,
+//! - `RawAlloc::verify` finds no inconsistencies.
+//!
+//! On failure the sequence is shrunk to a minimal one that still fails.
+
+use alloc::vec::Vec;
+use core::alloc::Layout;
+use core::fmt;
+
+use crate::allocators::{HeapError, HeapGrower, RawAlloc};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Alloc {
+        slot: usize,
+        size: usize,
+        align: usize,
+    },
+    Calloc {
+        slot: usize,
+        size: usize,
+        align: usize,
+    },
+    Realloc {
+        slot: usize,
+        size: usize,
+    },
+    Dealloc {
+        slot: usize,
+    },
+}
+
+impl Op {
+    fn size(&self) -> Option<usize> {
+        match *self {
+            Op::Alloc { size, .. } | Op::Calloc { size, .. } | Op::Realloc { size, .. } => {
+                Some(size)
+            }
+            Op::Dealloc { .. } => None,
+        }
+    }
+
+    fn with_size(mut self, new_size: usize) -> Op {
+        match &mut self {
+            Op::Alloc { size, .. } | Op::Calloc { size, .. } | Op::Realloc { size, .. } => {
+                *size = new_size
+            }
+            Op::Dealloc { .. } => {}
+        }
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ModelConfig {
+    /// Number of allocations that can be live at once.
+    pub slots: usize,
+    /// Length of the generated sequence.
+    pub ops: usize,
+    /// Largest size requested.
+    pub max_size: usize,
+    /// Largest alignment requested; a power of two.
+    pub max_align: usize,
+    pub seed: u64,
+}
+
+impl Default for ModelConfig {
+    fn default() -> Self {
+        ModelConfig {
+            slots: 64,
+            ops: 2048,
+            max_size: 1024,
+            max_align: 128,
+            seed: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    /// The allocation in `slot` overlaps the one in `other`.
+    Overlap { slot: usize, other: usize },
+    /// The allocation in `slot` does not honour its alignment.
+    Misaligned {
+        slot: usize,
+        ptr: usize,
+        align: usize,
+    },
+    /// The allocation in `slot` no longer holds what was written to it.
+    Corrupted { slot: usize, offset: usize },
+    /// The `calloc`ed allocation in `slot` was not zeroed.
+    NotZeroed { slot: usize, offset: usize },
+    /// `RawAlloc::verify` failed; this is its first error.
+    Heap(HeapError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Failure {
+    /// Index of the operation after which the failure was found.
+    pub step: usize,
+    pub kind: FailureKind,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "after step {}: ", self.step)?;
+        match self.kind {
+            FailureKind::Overlap { slot, other } => {
+                write!(f, "slot {} overlaps slot {}", slot, other)
+            }
+            FailureKind::Misaligned { slot, ptr, align } => {
+                write!(
+                    f,
+                    "slot {} at {:#x} is not {}-byte aligned",
+                    slot, ptr, align
+                )
+            }
+            FailureKind::Corrupted { slot, offset } => {
+                write!(f, "slot {} corrupted at offset {}", slot, offset)
+            }
+            FailureKind::NotZeroed { slot, offset } => {
+                write!(f, "slot {} not zeroed at offset {}", slot, offset)
+            }
+            FailureKind::Heap(error) => write!(f, "heap error: {}", error),
+        }
+    }
+}
+
+/// A failing sequence, shrunk as far as possible.
+#[derive(Debug, Clone)]
+pub struct Counterexample {
+    pub seed: u64,
+    pub ops: Vec<Op>,
+    pub failure: Failure,
+}
+
+impl fmt::Display for Counterexample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "seed {} failed {}", self.seed, self.failure)?;
+        for (i, op) in self.ops.iter().enumerate() {
+            writeln!(f, "  {}: {:?}", i, op)?;
+        }
+        Ok(())
+    }
+}
+
+/// SplitMix64: small, fast, and good enough to drive the generator.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    /// Sizes spread roughly logarithmically over `1..=max`.
+    fn size(&mut self, max: usize) -> usize {
+        let bits = usize::BITS - max.leading_zeros();
+        let limit = (1usize << self.below(bits as usize + 1)).min(max);
+        1 + self.below(limit)
+    }
+}
+
+pub fn generate(config: &ModelConfig) -> Vec<Op> {
+    let mut rng = Rng(config.seed);
+    (0..config.ops)
+        .map(|_| {
+            let slot = rng.below(config.slots);
+            let align = 1 << rng.below(config.max_align.trailing_zeros() as usize + 1);
+            match rng.below(10) {
+                0..=3 => Op::Alloc {
+                    slot,
+                    size: rng.size(config.max_size),
+                    align,
+                },
+                4 => Op::Calloc {
+                    slot,
+                    size: rng.size(config.max_size),
+                    align,
+                },
+                5 | 6 => Op::Realloc {
+                    slot,
+                    size: rng.size(config.max_size),
+                },
+                _ => Op::Dealloc { slot },
+            }
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy)]
+struct Live {
+    ptr: *mut u8,
+    layout: Layout,
+    // Distinguishes what different allocations write.
+    tag: usize,
+}
+
+fn pattern(tag: usize, offset: usize) -> u8 {
+    (tag.wrapping_mul(131) ^ offset.wrapping_mul(7)) as u8
+}
+
+unsafe fn fill(live: &Live, from: usize) {
+    for offset in from..live.layout.size() {
+        *live.ptr.add(offset) = pattern(live.tag, offset);
+    }
+}
+
+unsafe fn check_contents(slot: usize, live: &Live, upto: usize) -> Result<(), FailureKind> {
+    for offset in 0..upto {
+        if *live.ptr.add(offset) != pattern(live.tag, offset) {
+            return Err(FailureKind::Corrupted { slot, offset });
+        }
+    }
+    Ok(())
+}
+
+fn check_placement(slots: &[Option<Live>], slot: usize) -> Result<(), FailureKind> {
+    let new = slots[slot].expect("only called on live slots");
+    let (start, align) = (new.ptr as usize, new.layout.align());
+    if start % align != 0 {
+        return Err(FailureKind::Misaligned {
+            slot,
+            ptr: start,
+            align,
+        });
+    }
+    let end = start + new.layout.size();
+    for (other, live) in slots.iter().enumerate() {
+        let Some(live) = live else { continue };
+        if other == slot {
+            continue;
+        }
+        let other_start = live.ptr as usize;
+        let other_end = other_start + live.layout.size();
+        if start < other_end && other_start < end {
+            return Err(FailureKind::Overlap { slot, other });
+        }
+    }
+    Ok(())
+}
+
+/// Checks the heap before the harness writes into anything it handed out,
+/// so a corrupted free list is reported rather than followed.
+fn check_heap<G: HeapGrower>(allocator: &RawAlloc<G>) -> Result<(), FailureKind> {
+    match allocator.verify().errors().next() {
+        Some(&error) => Err(FailureKind::Heap(error)),
+        None => Ok(()),
+    }
+}
+
+/// Runs `ops` against `allocator`, freeing everything still live at the end.
+/// Allocations that return null are treated as out-of-memory, not failures.
+///
+/// After a failure the heap cannot be trusted, so live allocations are leaked
+/// rather than freed into it.
+pub fn run<G: HeapGrower>(allocator: &mut RawAlloc<G>, ops: &[Op]) -> Result<(), Failure> {
+    let slot_count = ops
+        .iter()
+        .map(|op| match *op {
+            Op::Alloc { slot, .. }
+            | Op::Calloc { slot, .. }
+            | Op::Realloc { slot, .. }
+            | Op::Dealloc { slot } => slot + 1,
+        })
+        .max()
+        .unwrap_or(0);
+    let mut slots: Vec<Option<Live>> = alloc::vec![None; slot_count];
+
+    let result = (|| {
+        for (step, &op) in ops.iter().enumerate() {
+            let fail = |kind| Failure { step, kind };
+            unsafe {
+                match op {
+                    Op::Alloc { slot, size, align } | Op::Calloc { slot, size, align } => {
+                        if slots[slot].is_some() {
+                            continue;
+                        }
+                        let layout = Layout::from_size_align(size, align).unwrap();
+                        let zeroed = matches!(op, Op::Calloc { .. });
+                        let ptr = if zeroed {
+                            allocator.calloc(layout)
+                        } else {
+                            allocator.alloc(layout)
+                        };
+                        if ptr.is_null() {
+                            continue;
+                        }
+                        let live = Live {
+                            ptr,
+                            layout,
+                            tag: step,
+                        };
+                        slots[slot] = Some(live);
+                        check_placement(&slots, slot).map_err(fail)?;
+                        check_heap(allocator).map_err(fail)?;
+                        if zeroed {
+                            if let Some(offset) = (0..size).find(|&o| *ptr.add(o) != 0) {
+                                return Err(fail(FailureKind::NotZeroed { slot, offset }));
+                            }
+                        }
+                        fill(&live, 0);
+                    }
+                    Op::Realloc { slot, size } => {
+                        let Some(old) = slots[slot] else { continue };
+                        check_contents(slot, &old, old.layout.size()).map_err(fail)?;
+                        let ptr = allocator.realloc(old.ptr, old.layout, size);
+                        if ptr.is_null() {
+                            continue;
+                        }
+                        let layout = Layout::from_size_align(size, old.layout.align()).unwrap();
+                        let live = Live {
+                            ptr,
+                            layout,
+                            tag: old.tag,
+                        };
+                        slots[slot] = Some(live);
+                        check_placement(&slots, slot).map_err(fail)?;
+                        check_heap(allocator).map_err(fail)?;
+                        let kept = old.layout.size().min(size);
+                        check_contents(slot, &live, kept).map_err(fail)?;
+                        fill(&live, kept);
+                    }
+                    Op::Dealloc { slot } => {
+                        let Some(live) = slots[slot].take() else {
+                            continue;
+                        };
+                        check_contents(slot, &live, live.layout.size()).map_err(fail)?;
+                        allocator.dealloc(live.ptr, live.layout);
+                        check_heap(allocator).map_err(fail)?;
+                    }
+                }
+            }
+        }
+
+        let end = ops.len();
+        for (slot, live) in slots.iter().enumerate() {
+            if let Some(live) = live {
+                unsafe { check_contents(slot, live, live.layout.size()) }
+                    .map_err(|kind| Failure { step: end, kind })?;
+            }
+        }
+        Ok(())
+    })();
+
+    if result.is_ok() {
+        for live in slots.iter().flatten() {
+            unsafe { allocator.dealloc(live.ptr, live.layout) };
+        }
+    }
+    result
+}
+
+/// Shrinks a failing `ops` to a shorter sequence with smaller sizes that
+/// still fails, by repeatedly dropping chunks of operations and halving sizes.
+pub fn shrink<G: HeapGrower, F: FnMut() -> RawAlloc<G>>(
+    mut make: F,
+    mut ops: Vec<Op>,
+    mut failure: Failure,
+) -> (Vec<Op>, Failure) {
+    let mut fails = |candidate: &[Op]| run(&mut make(), candidate).err();
+
+    let mut chunk = ops.len().div_ceil(2);
+    while chunk > 0 {
+        let mut removed = false;
+        let mut start = 0;
+        while start < ops.len() {
+            let end = (start + chunk).min(ops.len());
+            let candidate: Vec<Op> = ops[..start].iter().chain(&ops[end..]).copied().collect();
+            match fails(&candidate) {
+                Some(f) => {
+                    ops = candidate;
+                    failure = f;
+                    removed = true;
+                }
+                None => start += chunk,
+            }
+        }
+        if !removed {
+            chunk /= 2;
+        }
+    }
+
+    for i in 0..ops.len() {
+        while let Some(size) = ops[i].size().filter(|&size| size > 1) {
+            let mut candidate = ops.clone();
+            candidate[i] = candidate[i].with_size(size / 2);
+            match fails(&candidate) {
+                Some(f) => {
+                    ops = candidate;
+                    failure = f;
+                }
+                None => break,
+            }
+        }
+    }
+
+    (ops, failure)
+}
+
+/// Generates a sequence from `config` and runs it on an allocator from
+/// `make`, returning a shrunk counterexample if it fails.
+pub fn check<G: HeapGrower, F: FnMut() -> RawAlloc<G>>(
+    mut make: F,
+    config: &ModelConfig,
+) -> Result<(), Counterexample> {
+    let ops = generate(config);
+    let failure = match run(&mut make(), &ops) {
+        Ok(()) => return Ok(()),
+        Err(failure) => failure,
+    };
+    let (ops, failure) = shrink(make, ops, failure);
+    Err(Counterexample {
+        seed: config.seed,
+        ops,
+        failure,
+    })
+}
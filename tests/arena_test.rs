@@ -0,0 +1,138 @@
+//! Threads on a `UnixAllocator` with many arenas, each freeing what the one
+//! before it allocated, wherever that was.
+#![cfg(target_os = "linux")]
+
+use basic_allocator::allocators::UnixAllocator;
+use core::alloc::{GlobalAlloc, Layout};
+use std::ops::Range;
+use std::sync::mpsc;
+use std::thread;
+
+use rand::{Rng, RngCore, SeedableRng};
+use test_log::test;
+
+const THREADS: usize = 8;
+
+// Raw pointers, handed from one thread to the next.
+struct Block(*mut u8, Layout);
+unsafe impl Send for Block {}
+
+/// Each thread makes `count` allocations with sizes drawn from `sizes`.
+fn cross_free(allocator: &UnixAllocator, sizes: Range<usize>, count: usize) {
+    let seed = rand::thread_rng().next_u64();
+    log::info!("Using seed {}", seed);
+
+    let (senders, receivers): (Vec<_>, Vec<_>) = (0..THREADS).map(|_| mpsc::channel::<Block>()).unzip();
+    thread::scope(|scope| {
+        for (t, receiver) in receivers.into_iter().enumerate() {
+            let next = senders[(t + 1) % THREADS].clone();
+            let sizes = sizes.clone();
+            scope.spawn(move || {
+                let mut rng = rand::rngs::StdRng::seed_from_u64(seed + t as u64);
+                for _ in 0..count {
+                    let size = rng.gen_range(sizes.clone());
+                    let layout = Layout::from_size_align(size, 8).unwrap();
+                    let ptr = unsafe { allocator.alloc(layout) };
+                    assert!(!ptr.is_null());
+                    unsafe { ptr.write_bytes(t as u8, size) };
+                    next.send(Block(ptr, layout)).unwrap();
+                    while let Ok(Block(ptr, layout)) = receiver.try_recv() {
+                        unsafe { allocator.dealloc(ptr, layout) };
+                    }
+                }
+                drop(next);
+                // The rest, once the thread before has finished.
+                for Block(ptr, layout) in receiver {
+                    unsafe { allocator.dealloc(ptr, layout) };
+                }
+            });
+        }
+        drop(senders);
+    });
+
+    let report = allocator.verify();
+    assert!(report.is_valid(), "{}", report);
+    for (index, arena) in allocator.arenas() {
+        assert_eq!(arena.metrics().live_bytes, 0, "arena {} still has live bytes", index);
+    }
+}
+
+#[test]
+fn test_cross_cpu_free() {
+    cross_free(&UnixAllocator::per_cpu(), 1..4096, 1_000);
+}
+
+#[test]
+fn test_cross_cpu_free_growths() {
+    // Large enough that every arena grows many times over.
+    cross_free(&UnixAllocator::per_cpu(), 128 << 10..512 << 10, 100);
+}
+
+#[test]
+fn test_cross_thread_free() {
+    let allocator = UnixAllocator::with_arenas(4);
+    cross_free(&allocator, 1..4096, 1_000);
+    // Threads were spread over all of them.
+    assert_eq!(allocator.arenas().count(), 4);
+
+    cross_free(&UnixAllocator::per_thread(), 1..4096, 1_000);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_exited_threads() {
+    // Threads give up their place as they exit, so each new one is given the
+    // next arena round-robin, not left without a place, nor pinned as an
+    // earlier one was.
+    let allocator = UnixAllocator::with_arenas(4);
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    for t in 0..1_000 {
+        thread::scope(|scope| {
+            scope.spawn(|| unsafe {
+                let ptr = allocator.alloc(layout);
+                let owner = allocator.arenas().find(|(_, arena)| arena.owns(ptr, 64)).map(|(index, _)| index);
+                assert_eq!(owner, Some(t % 4), "thread {}", t);
+                assert!(allocator.pin_thread(3));
+                allocator.dealloc(ptr, layout);
+            });
+        });
+    }
+}
+
+#[test]
+fn test_many_regions() {
+    // Interleaved growths leave each arena with far more regions than fit
+    // in its registry inline.
+    let allocator = UnixAllocator::with_arenas(2);
+    let layout = Layout::from_size_align(300_000, 8).unwrap();
+    let ptrs: Vec<*mut u8> = (0..200)
+        .map(|i| {
+            assert!(allocator.pin_thread(i % 2));
+            let ptr = unsafe { allocator.alloc(layout) };
+            assert!(!ptr.is_null());
+            ptr
+        })
+        .collect();
+
+    // All freed from arena 0, some after being grown by arena 1, which
+    // handed them out.
+    assert!(allocator.pin_thread(0));
+    let larger = Layout::from_size_align(2 * layout.size(), 8).unwrap();
+    for (i, &ptr) in ptrs.iter().enumerate() {
+        unsafe {
+            if i % 4 == 1 {
+                let grown = allocator.realloc(ptr, layout, larger.size());
+                assert!(!grown.is_null());
+                allocator.dealloc(grown, larger);
+            } else {
+                allocator.dealloc(ptr, layout);
+            }
+        }
+    }
+
+    let report = allocator.verify();
+    assert!(report.is_valid(), "{}", report);
+    for (index, arena) in allocator.arenas() {
+        assert_eq!(arena.metrics().live_bytes, 0, "arena {} still has live bytes", index);
+    }
+}
@@ -0,0 +1,96 @@
+//! Runs the same calls through each real `MemoryBackend`; with the `use_libc`
+//! feature, both are run and must agree.
+#![cfg(target_os = "linux")]
+
+#[cfg(feature = "use_libc")]
+use basic_allocator::allocators::LibcBackend;
+use basic_allocator::allocators::{
+    Advice, AllocError, EnhancedHeapGrower, Errno, HeapGrower, MemoryBackend, Operation, Protection,
+    RawAlloc, RawBackend,
+};
+use basic_allocator::blocklist::BLOCK_ALIGN;
+use std::alloc::Layout;
+
+/// Each step, and the error it failed with, if any.
+type Outcomes = Vec<(&'static str, Option<AllocError>)>;
+
+fn errno<T>(result: Result<T, AllocError>) -> Option<AllocError> {
+    result.err()
+}
+
+fn script<B: MemoryBackend>(backend: &mut B) -> Outcomes {
+    let page = backend.page_size();
+    let mut outcomes = Vec::new();
+    unsafe {
+        let ptr = backend.map(4 * page, Protection::ReadWrite).unwrap();
+        ptr.write_bytes(0x5A, 4 * page);
+
+        outcomes.push(("advise free", errno(backend.advise(ptr, page, Advice::Free))));
+        outcomes.push(("advise dontneed", errno(backend.advise(ptr.add(page), page, Advice::DontNeed))));
+        assert_eq!(*ptr.add(page), 0, "released pages should read as zeroes");
+        assert_eq!(*ptr.add(2 * page), 0x5A);
+
+        outcomes.push(("shrink in place", errno(backend.remap(ptr, 4 * page, 2 * page))));
+        outcomes.push(("protect none", errno(backend.protect(ptr, page, Protection::None))));
+        outcomes.push(("protect read-write", errno(backend.protect(ptr, page, Protection::ReadWrite))));
+        ptr.write(1);
+
+        outcomes.push(("misaligned unmap", errno(backend.unmap(ptr.add(1), page))));
+        // Not misaligned protect: musl rounds the address down to a page.
+        outcomes.push(("misaligned advise", errno(backend.advise(ptr.add(1), page, Advice::DontNeed))));
+        outcomes.push(("empty map", errno(backend.map(0, Protection::ReadWrite))));
+        outcomes.push(("unmap", errno(backend.unmap(ptr, 2 * page))));
+
+        let reserved = backend.map(16 * page, Protection::None).unwrap();
+        outcomes.push(("commit", errno(backend.protect(reserved, page, Protection::ReadWrite))));
+        reserved.write(1);
+        outcomes.push(("unmap reservation", errno(backend.unmap(reserved, 16 * page))));
+    }
+    outcomes
+}
+
+/// Live and mapped bytes after the same allocations through `backend`.
+fn heap<B: MemoryBackend>(backend: B) -> (usize, usize) {
+    let mut allocator = RawAlloc::new(EnhancedHeapGrower::with_backend(backend));
+    let mut pointers = Vec::new();
+    unsafe {
+        for i in 1..64 {
+            let layout = Layout::from_size_align(i * 1000, BLOCK_ALIGN).unwrap();
+            let ptr = allocator.alloc(layout);
+            assert!(!ptr.is_null());
+            ptr.write_bytes(0x5A, layout.size());
+            pointers.push((ptr, layout));
+        }
+        for (ptr, layout) in pointers.into_iter().step_by(2) {
+            allocator.dealloc(ptr, layout);
+        }
+    }
+    assert!(allocator.verify().is_valid(), "{}", allocator.verify());
+    (allocator.live_bytes(), allocator.grower.stats().mapped_bytes)
+}
+
+#[test]
+fn test_raw_backend() {
+    for (step, error) in script(&mut RawBackend) {
+        let op = match step {
+            "misaligned unmap" => Operation::Munmap,
+            "misaligned advise" => Operation::Madvise,
+            "empty map" => Operation::Mmap,
+            _ => {
+                assert_eq!(error, None, "{}", step);
+                continue;
+            }
+        };
+        assert_eq!(error, Some(AllocError::System { op, errno: Errno::EINVAL }), "{}", step);
+    }
+    let (live, mapped) = heap(RawBackend);
+    assert!(0 < live && live < mapped);
+}
+
+#[cfg(feature = "use_libc")]
+#[test]
+fn test_backends_agree() {
+    assert_eq!(RawBackend.page_size(), LibcBackend.page_size());
+    assert_eq!(script(&mut RawBackend), script(&mut LibcBackend));
+    assert_eq!(heap(RawBackend), heap(LibcBackend));
+}
@@ -0,0 +1,68 @@
+//! Moves the program break. Nothing else in the process may, so the whole
+//! binary runs on an `mmap`-backed global allocator, and glibc's `malloc`,
+//! which would otherwise use the break too, stays idle.
+#![cfg(target_os = "linux")]
+
+use basic_allocator::allocators::{BrkHeapGrower, HeapGrower, UnixAllocator};
+use basic_allocator::RawAlloc;
+use core::alloc::Layout;
+
+use test_log::test;
+
+#[global_allocator]
+static ALLOCATOR: UnixAllocator = UnixAllocator::new();
+
+// The break is process-wide, so everything that moves it is one test.
+#[test]
+fn test_brk() {
+    let mut allocator = RawAlloc::new(BrkHeapGrower::default());
+    let page_size = allocator.grower.page_size();
+    let small = Layout::from_size_align(4000, 16).unwrap();
+    let large = Layout::from_size_align(8000, 16).unwrap();
+    unsafe {
+        let a = allocator.alloc(small);
+        let b = allocator.alloc(large);
+        assert_eq!(allocator.regions.len(), 1, "growths should be contiguous");
+        allocator.dealloc(a, small);
+        allocator.dealloc(b, large);
+        assert_eq!(allocator.blocks.len(), 1, "blocks should merge across growths");
+        assert!(allocator.verify().is_valid(), "{}", allocator.verify());
+
+        assert_eq!(allocator.trim(), 3 * page_size);
+        assert_eq!(allocator.grower.brk_bytes(), 0);
+        assert!(allocator.regions.is_empty());
+        assert!(allocator.verify().is_valid(), "{}", allocator.verify());
+
+        allocator.grower.set_trim_threshold(2 * page_size);
+        let c = allocator.alloc(large);
+        assert_eq!(allocator.grower.brk_bytes(), 2 * page_size);
+        allocator.dealloc(c, large);
+        assert_eq!(allocator.grower.brk_bytes(), 0, "should trim on dealloc");
+        assert!(allocator.verify().is_valid(), "{}", allocator.verify());
+    }
+
+    // Block the break with a mapping just above it.
+    #[cfg(any(feature = "use_libc", feature = "std"))]
+    unsafe {
+        let top = libc::syscall(libc::SYS_brk, 0) as *mut u8;
+        let blocker = libc::mmap(
+            top.add(top.align_offset(page_size)) as *mut libc::c_void,
+            page_size,
+            libc::PROT_READ,
+            libc::MAP_ANONYMOUS | libc::MAP_PRIVATE | libc::MAP_FIXED_NOREPLACE,
+            -1,
+            0,
+        );
+        assert_ne!(blocker, libc::MAP_FAILED);
+
+        let d = allocator.alloc(large);
+        assert!(!d.is_null());
+        assert!(allocator.grower.brk_failed());
+        assert_eq!(allocator.grower.brk_bytes(), 0);
+        assert_eq!(allocator.grower.stats().mapped_bytes, 2 * page_size);
+        allocator.dealloc(d, large);
+        assert!(allocator.verify().is_valid(), "{}", allocator.verify());
+
+        libc::munmap(blocker, page_size);
+    }
+}
@@ -0,0 +1,128 @@
+//! Forks while other threads allocate. The child must find the allocator
+//! unlocked and whole, through the handlers the `std` feature registers.
+#![cfg(all(target_os = "linux", feature = "std"))]
+
+use basic_allocator::allocators::UnixAllocator;
+use core::alloc::{GlobalAlloc, Layout};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[global_allocator]
+static ALLOCATOR: UnixAllocator = UnixAllocator::new();
+
+// Used directly rather than as the global allocator, one arena per thread.
+static ARENAS: UnixAllocator = UnixAllocator::per_thread();
+
+/// Allocates in the child, through the global allocator and `allocator`, and
+/// exits without running anything of the parent's.
+fn child(allocator: &UnixAllocator) -> ! {
+    let mut kept = Vec::new();
+    let mut blocks = Vec::new();
+    for i in 0..200usize {
+        let s: String = (0..i % 50).map(|c| (b'a' + (c % 26) as u8) as char).collect();
+        kept.push(Box::new(s));
+        let layout = Layout::from_size_align(i % 50 + 1, 8).unwrap();
+        blocks.push((unsafe { allocator.alloc(layout) }, layout));
+    }
+    let valid = ALLOCATOR.verify().is_valid() && allocator.verify().is_valid();
+    drop(kept);
+    for (ptr, layout) in blocks {
+        unsafe { allocator.dealloc(ptr, layout) };
+    }
+    unsafe { libc::_exit(if valid { 0 } else { 2 }) }
+}
+
+/// Waits for `pid` to exit, killing it if it takes too long.
+fn wait(pid: libc::pid_t) -> Result<i32, &'static str> {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let mut status = 0;
+    loop {
+        match unsafe { libc::waitpid(pid, &mut status, libc::WNOHANG) } {
+            0 if Instant::now() < deadline => thread::sleep(Duration::from_millis(1)),
+            0 => {
+                unsafe {
+                    libc::kill(pid, libc::SIGKILL);
+                    libc::waitpid(pid, &mut status, 0);
+                }
+                return Err("child deadlocked");
+            }
+            r if r == pid && libc::WIFEXITED(status) => return Ok(libc::WEXITSTATUS(status)),
+            _ => return Err("child did not exit"),
+        }
+    }
+}
+
+/// Forks repeatedly while four threads run `work` in a loop.
+fn fork_during(allocator: &UnixAllocator, work: fn(usize)) {
+    let stop = AtomicBool::new(false);
+    thread::scope(|scope| {
+        for t in 0..4 {
+            let stop = &stop;
+            scope.spawn(move || {
+                let mut i = t;
+                while !stop.load(Ordering::Relaxed) {
+                    work(i);
+                    i += 1;
+                }
+            });
+        }
+
+        for _ in 0..100 {
+            let pid = unsafe { libc::fork() };
+            assert!(pid >= 0, "fork failed");
+            if pid == 0 {
+                child(allocator);
+            }
+            assert_eq!(wait(pid), Ok(0));
+        }
+        stop.store(true, Ordering::Relaxed);
+    });
+
+    let report = allocator.verify();
+    assert!(report.is_valid(), "{}", report);
+}
+
+#[test]
+fn test_fork_during_allocation() {
+    fork_during(&ALLOCATOR, |i| {
+        let v: Vec<usize> = (0..(i * 7) % 3000).collect();
+        assert_eq!(v.len(), (i * 7) % 3000);
+    });
+}
+
+#[test]
+fn test_fork_with_arenas() {
+    fork_during(&ARENAS, |i| {
+        let layout = Layout::from_size_align((i * 7) % 3000 * 8 + 1, 8).unwrap();
+        unsafe {
+            let ptr = ARENAS.alloc(layout);
+            assert!(!ptr.is_null());
+            ptr.write_bytes(0x5A, layout.size());
+            ARENAS.dealloc(ptr, layout);
+        }
+    });
+    assert!(ARENAS.arenas().count() > 1, "threads should have had arenas of their own");
+}
+
+static BY_HAND: UnixAllocator = UnixAllocator::new();
+
+#[test]
+fn test_hooks_by_hand() {
+    // Registered as they would be without `std`. They run before the gate's
+    // own handlers, and must leave the gate for those to close.
+    extern "C" fn prepare() {
+        BY_HAND.prepare_fork()
+    }
+    extern "C" fn parent() {
+        unsafe { BY_HAND.after_fork_parent() }
+    }
+    extern "C" fn child_hook() {
+        unsafe { BY_HAND.after_fork_child() }
+    }
+    unsafe { libc::pthread_atfork(Some(prepare), Some(parent), Some(child_hook)) };
+    fork_during(&BY_HAND, |i| {
+        let layout = Layout::from_size_align(i % 3000 + 1, 8).unwrap();
+        unsafe { BY_HAND.dealloc(BY_HAND.alloc(layout), layout) };
+    });
+}
@@ -0,0 +1,113 @@
+//! Contention on the lock-free stacks: no block may be lost, or handed to two
+//! threads at once.
+#![cfg(target_has_atomic = "64")]
+
+use basic_allocator::allocators::{LockFreeAllocator, MAX_CLASS_SIZE};
+use basic_allocator::blocklist::{AtomicStack, BLOCK_ALIGN};
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::NonNull;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use rand::{Rng, RngCore, SeedableRng};
+use test_log::test;
+
+const THREADS: usize = 8;
+const WORD: usize = core::mem::size_of::<usize>();
+
+#[repr(C, align(16))]
+struct Block([usize; 2]);
+
+#[test]
+fn test_stack_contention() {
+    const BLOCKS: usize = 64;
+    let mut blocks: Vec<Block> = (0..BLOCKS).map(|_| Block([0; 2])).collect();
+    let base = blocks.as_mut_ptr() as usize;
+    let index = |ptr: NonNull<u8>| (ptr.as_ptr() as usize - base) / core::mem::size_of::<Block>();
+
+    let stack = AtomicStack::new();
+    for block in &mut blocks {
+        assert!(unsafe { stack.push(NonNull::from(block).cast()) });
+    }
+    let held: Vec<AtomicBool> = (0..BLOCKS).map(|_| AtomicBool::new(false)).collect();
+
+    thread::scope(|scope| {
+        for _ in 0..THREADS {
+            scope.spawn(|| {
+                let mut mine = Vec::new();
+                for i in 0..100_000usize {
+                    // Hold up to a few blocks at a time, popping and pushing
+                    // in bursts.
+                    if mine.len() < 4 && i % 3 != 0 {
+                        if let Some(ptr) = stack.pop() {
+                            assert!(!held[index(ptr)].swap(true, Ordering::Relaxed), "block popped twice");
+                            mine.push(ptr);
+                        }
+                    } else if let Some(ptr) = mine.pop() {
+                        held[index(ptr)].store(false, Ordering::Relaxed);
+                        assert!(unsafe { stack.push(ptr) });
+                    }
+                }
+                for ptr in mine {
+                    held[index(ptr)].store(false, Ordering::Relaxed);
+                    assert!(unsafe { stack.push(ptr) });
+                }
+            });
+        }
+    });
+
+    assert_eq!(stack.len(), BLOCKS);
+    let mut seen = vec![false; BLOCKS];
+    while let Some(ptr) = stack.pop() {
+        assert!(!std::mem::replace(&mut seen[index(ptr)], true), "block on the stack twice");
+    }
+    assert!(seen.iter().all(|&s| s), "block lost");
+}
+
+#[test]
+fn test_allocator_contention() {
+    let allocator: LockFreeAllocator = LockFreeAllocator::new();
+    let seed = rand::thread_rng().next_u64();
+    log::info!("Using seed {}", seed);
+
+    thread::scope(|scope| {
+        for t in 0..THREADS {
+            let allocator = &allocator;
+            scope.spawn(move || {
+                let mut rng = rand::rngs::StdRng::seed_from_u64(seed + t as u64);
+                let mut held: Vec<(*mut u8, Layout, usize)> = Vec::new();
+                for i in 0..20_000usize {
+                    if held.len() < 64 && rng.gen_bool(0.55) {
+                        // Mostly size classes, some from the heap.
+                        let size = rng.gen_range(1..=MAX_CLASS_SIZE + 256);
+                        let layout = Layout::from_size_align(size, BLOCK_ALIGN).unwrap();
+                        let ptr = unsafe { allocator.alloc(layout) };
+                        assert!(!ptr.is_null());
+                        // Every word says who holds it, so a block handed
+                        // out twice gets overwritten.
+                        let stamp = (t << 24) | i;
+                        for w in 0..size / WORD {
+                            unsafe { (ptr as *mut usize).add(w).write(stamp) };
+                        }
+                        held.push((ptr, layout, stamp));
+                    } else if !held.is_empty() {
+                        let (ptr, layout, stamp) = held.swap_remove(rng.gen_range(0..held.len()));
+                        for w in 0..layout.size() / WORD {
+                            assert_eq!(unsafe { *(ptr as *const usize).add(w) }, stamp, "block shared");
+                        }
+                        unsafe { allocator.dealloc(ptr, layout) };
+                    }
+                }
+                for (ptr, layout, _) in held {
+                    unsafe { allocator.dealloc(ptr, layout) };
+                }
+            });
+        }
+    });
+
+    let report = allocator.heap().verify();
+    assert!(report.is_valid(), "{}", report);
+    // Everything has been freed, so all the heap still has out is cached.
+    let live = allocator.heap().metrics().live_bytes;
+    assert!(allocator.cached_bytes() <= live && live - allocator.cached_bytes() < MAX_CLASS_SIZE);
+}
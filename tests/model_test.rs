@@ -0,0 +1,97 @@
+use basic_allocator::allocators::{AllocError, EnhancedHeapGrower, HeapGrower, RawAlloc, ToyHeap};
+use basic_allocator::blocklist::BLOCK_ALIGN;
+use basic_allocator::testing::{self, FailureKind, ModelConfig, Op};
+
+use rand::RngCore;
+use test_log::test;
+
+fn config() -> ModelConfig {
+    // Set MODEL_SEED to replay a failure.
+    let seed: u64 = match std::env::var("MODEL_SEED") {
+        Ok(seed) => seed.parse().expect("MODEL_SEED should be a u64"),
+        Err(_) => rand::thread_rng().next_u64(),
+    };
+    log::info!("Using seed {}", seed);
+    ModelConfig {
+        seed,
+        ..ModelConfig::default()
+    }
+}
+
+#[test]
+fn test_model_toy_heap() {
+    if let Err(counterexample) = testing::check(|| RawAlloc::new(ToyHeap::default()), &config()) {
+        panic!("{}", counterexample);
+    }
+}
+
+#[test]
+fn test_model_enhanced_heap_grower() {
+    let config = ModelConfig {
+        max_size: 64 * 1024,
+        ..config()
+    };
+    if let Err(counterexample) =
+        testing::check(|| RawAlloc::new(EnhancedHeapGrower::default()), &config)
+    {
+        panic!("{}", counterexample);
+    }
+}
+
+/// Hands out memory that is only aligned to half of `BLOCK_ALIGN`.
+struct MisaligningGrower {
+    memory: Vec<u128>,
+    used: usize,
+}
+
+impl HeapGrower for MisaligningGrower {
+    type Err = AllocError;
+
+    unsafe fn grow_heap(&mut self, size: usize) -> Result<(*mut u8, usize), AllocError> {
+        let size = size.div_ceil(BLOCK_ALIGN) * BLOCK_ALIGN;
+        let start = BLOCK_ALIGN / 2 + self.used;
+        if start + size > self.memory.len() * 16 {
+            return Err(AllocError::Exhausted);
+        }
+        self.used += size;
+        Ok(((self.memory.as_mut_ptr() as *mut u8).add(start), size))
+    }
+}
+
+#[test]
+fn test_model_shrinks() {
+    let make = || {
+        RawAlloc::new(MisaligningGrower {
+            memory: vec![0; 64 * 1024],
+            used: 0,
+        })
+    };
+    // Over-aligned allocations would free half-block slivers of padding,
+    // which the free list cannot hold.
+    let config = ModelConfig {
+        ops: 256,
+        max_align: BLOCK_ALIGN,
+        ..config()
+    };
+    let counterexample = testing::check(make, &config).expect_err("misalignment should be found");
+    log::info!("{}", counterexample);
+
+    // A single allocation is enough to get a misaligned pointer.
+    assert_eq!(counterexample.ops.len(), 1);
+    assert!(matches!(
+        counterexample.ops[0],
+        Op::Alloc {
+            size: 1,
+            align: BLOCK_ALIGN,
+            ..
+        } | Op::Calloc {
+            size: 1,
+            align: BLOCK_ALIGN,
+            ..
+        }
+    ));
+    assert!(matches!(
+        counterexample.failure.kind,
+        FailureKind::Misaligned { align: BLOCK_ALIGN, .. }
+    ));
+}
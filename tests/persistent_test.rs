@@ -0,0 +1,163 @@
+#![cfg(target_os = "linux")]
+
+use basic_allocator::allocators::{PersistentAlloc, PersistentHeapError};
+use std::alloc::Layout;
+use std::fs::{self, File, OpenOptions};
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+
+const RESERVE: usize = 64 * 1024 * 1024;
+
+/// A list node that links to the next one by offset, so the list survives
+/// being mapped at another address.
+#[repr(C)]
+struct Node {
+    value: u64,
+    next: usize,
+}
+
+fn temp_path(name: &str) -> PathBuf {
+    let mut path = std::env::temp_dir();
+    path.push(format!("basic_allocator_{}_{}", name, std::process::id()));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+fn open_file(path: &PathBuf) -> File {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .unwrap()
+}
+
+/// Builds the list `0..count` (in reverse), with odd values freed again to
+/// leave holes in the heap.
+unsafe fn build_list(heap: &mut PersistentAlloc, count: u64) {
+    let layout = Layout::new::<Node>();
+    let padding = Layout::from_size_align(48, 16).unwrap();
+    let mut next = 0;
+    for value in 0..count {
+        let node = heap.alloc(layout) as *mut Node;
+        assert!(!node.is_null());
+        node.write(Node { value, next });
+        next = heap.offset_of(node as *const u8);
+
+        let spare = heap.alloc(padding);
+        if value % 2 == 1 {
+            heap.dealloc(spare, padding);
+        }
+    }
+    let root = heap.at(next);
+    heap.set_root(root);
+}
+
+unsafe fn read_list(heap: &PersistentAlloc) -> Vec<u64> {
+    let mut values = Vec::new();
+    let mut node = heap.root() as *const Node;
+    while !node.is_null() {
+        values.push((*node).value);
+        node = match (*node).next {
+            0 => std::ptr::null(),
+            offset => heap.at(offset) as *const Node,
+        };
+    }
+    values
+}
+
+#[test]
+fn test_reopen() {
+    let path = temp_path("reopen");
+    let file = open_file(&path);
+    let expected: Vec<u64> = (0..1000).rev().collect();
+
+    let live = unsafe {
+        let mut heap = PersistentAlloc::open(file.as_raw_fd(), RESERVE).unwrap();
+        assert!(heap.root().is_null());
+        build_list(&mut heap, 1000);
+        assert!(heap.verify().is_valid(), "{}", heap.verify());
+        heap.allocator().live_bytes()
+    };
+
+    unsafe {
+        let mut heap = PersistentAlloc::open(file.as_raw_fd(), RESERVE).unwrap();
+        assert_eq!(read_list(&heap), expected);
+        assert!(heap.verify().is_valid(), "{}", heap.verify());
+        assert_eq!(heap.allocator().live_bytes(), live);
+
+        // The holes left behind are reused.
+        let regions = heap.allocator().regions.total_bytes();
+        let ptr = heap.alloc(Layout::from_size_align(48, 16).unwrap());
+        assert!(!ptr.is_null());
+        assert_eq!(heap.allocator().regions.total_bytes(), regions);
+    }
+    drop(file);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_relocate() {
+    let path = temp_path("relocate");
+    let copy = temp_path("relocate_copy");
+    let file = open_file(&path);
+    let expected: Vec<u64> = (0..500).rev().collect();
+
+    unsafe {
+        let mut original = PersistentAlloc::open(file.as_raw_fd(), RESERVE).unwrap();
+        build_list(&mut original, 500);
+        original.sync().unwrap();
+        fs::copy(&path, &copy).unwrap();
+
+        // The original still occupies the address the copy was saved at.
+        let copy_file = open_file(&copy);
+        let mut moved = PersistentAlloc::open(copy_file.as_raw_fd(), RESERVE).unwrap();
+        assert_ne!(moved.at(0), original.at(0));
+        assert_eq!(read_list(&moved), expected);
+        assert!(moved.verify().is_valid(), "{}", moved.verify());
+
+        // The relocated free list is usable.
+        for _ in 0..100 {
+            assert!(!moved.alloc(Layout::from_size_align(48, 16).unwrap()).is_null());
+        }
+        assert!(moved.verify().is_valid(), "{}", moved.verify());
+        assert_eq!(read_list(&original), expected);
+    }
+    drop(file);
+    fs::remove_file(&path).unwrap();
+    fs::remove_file(&copy).unwrap();
+}
+
+#[test]
+fn test_rejects() {
+    let path = temp_path("rejects");
+    let file = open_file(&path);
+    fs::write(&path, vec![7u8; 8192]).unwrap();
+    unsafe {
+        assert!(matches!(
+            PersistentAlloc::open(file.as_raw_fd(), RESERVE),
+            Err(PersistentHeapError::NotAHeap)
+        ));
+    }
+
+    file.set_len(0).unwrap();
+    unsafe {
+        let mut heap = PersistentAlloc::open(file.as_raw_fd(), RESERVE).unwrap();
+        build_list(&mut heap, 10);
+        heap.sync().unwrap();
+        build_list(&mut heap, 10);
+        // Crash without syncing.
+        std::mem::forget(heap);
+        assert!(matches!(
+            PersistentAlloc::open(file.as_raw_fd(), RESERVE),
+            Err(PersistentHeapError::Unclean)
+        ));
+        assert!(matches!(
+            PersistentAlloc::open(file.as_raw_fd(), 4096),
+            Err(PersistentHeapError::TooLarge { .. })
+        ));
+    }
+    drop(file);
+    fs::remove_file(&path).unwrap();
+}
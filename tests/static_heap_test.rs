@@ -0,0 +1,52 @@
+//! Runs the whole test binary on a `GenericAllocator` over a fixed buffer, so
+//! nothing here may depend on another allocator.
+use basic_allocator::allocators::{GenericAllocator, StaticHeap};
+use std::ptr::addr_of_mut;
+use std::thread;
+
+const HEAP_SIZE: usize = 16 * 1024 * 1024;
+
+static mut MEMORY: [u8; HEAP_SIZE] = [0; HEAP_SIZE];
+
+#[global_allocator]
+static ALLOCATOR: GenericAllocator<StaticHeap> = GenericAllocator::with_grower(unsafe {
+    StaticHeap::from_raw_parts(addr_of_mut!(MEMORY) as *mut u8, HEAP_SIZE)
+});
+
+#[repr(align(4096))]
+struct Page([u8; 4096]);
+
+#[test]
+fn test_threads() {
+    let handles: Vec<_> = (0..4)
+        .map(|t| {
+            thread::spawn(move || {
+                let mut kept = Vec::new();
+                for i in 0..2000usize {
+                    let v: Vec<usize> = (0..(i * 7 + t) % 300).collect();
+                    if i % 3 == 0 {
+                        kept.push(v);
+                    }
+                }
+                kept.iter().map(|v| v.len()).sum::<usize>()
+            })
+        })
+        .collect();
+    for handle in handles {
+        assert!(handle.join().unwrap() > 0);
+    }
+
+    let report = ALLOCATOR.verify();
+    assert!(report.is_valid(), "{}", report);
+}
+
+#[test]
+fn test_overaligned() {
+    let pages: Vec<Box<Page>> = (0..3).map(|_| Box::new(Page([7; 4096]))).collect();
+    let memory = addr_of_mut!(MEMORY) as usize;
+    for page in &pages {
+        let ptr = page.0.as_ptr() as usize;
+        assert_eq!(ptr % 4096, 0);
+        assert!(memory <= ptr && ptr < memory + HEAP_SIZE);
+    }
+}
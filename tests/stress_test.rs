@@ -35,6 +35,8 @@ fn test_stress() {
         );
         log::info!("Blocks: {}", allocator.blocks);
         assert!(validity.is_valid());
+        let report = allocator.verify();
+        assert!(report.is_valid(), "{}", report);
 
         let found_heap_size = &allocator.grower.size.load(Ordering::Relaxed);
         let found_freed = stats.size.load(Ordering::Relaxed);